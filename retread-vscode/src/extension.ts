@@ -29,6 +29,17 @@ export function activate(context: vscode.ExtensionContext) {
 		})
 	}));
 
+	// The entries in the history of a variable are named `goto <line>`, which jumps to them when evaluated
+	context.subscriptions.push(vscode.commands.registerCommand('extension.retread.gotoHistoryEntry', args => {
+		const expression: string | undefined = args?.variable?.evaluateName;
+		const session = vscode.debug.activeDebugSession;
+		if (!session || !expression?.startsWith('goto ')) {
+			vscode.window.showInformationMessage('Only the entries in the history of a variable can be jumped to');
+			return;
+		}
+		return session.customRequest('evaluate', { expression, context: 'repl' });
+	}));
+
 }
 
 // This method is called when your extension is deactivated
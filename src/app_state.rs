@@ -1,5 +1,7 @@
-use std::fs;
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use dap::base_message::Sendable;
 use dap::events::*;
@@ -8,31 +10,161 @@ use dap::responses::*;
 use dap::types::*;
 use glob::glob;
 use regex::Regex;
-use serde_json::Value;
+use serde_json::{Map, Value};
 
+use crate::condition::{interpolate, Condition, HitCondition};
+use crate::defmt_decoder::DefmtTable;
+use crate::exception_trace::{parse_trace, Trace};
+use crate::format_args::recover_arguments;
+use crate::log_format::{JsonFields, LogFormat, LogfmtFields};
+use crate::log_inference::infer_log_pattern;
+use crate::log_level::LogLevel;
+use crate::log_reader::LogReader;
+use crate::kernel_symbols::{is_oops_line, parse_frames, KernelFrame, SymbolTable};
+use crate::log_preset::find_preset;
+use crate::log_source::{LogInput, LogSource, ProgramSettings, SocketAddress, SocketSettings};
+use crate::payload::{find_payload, leaves, parse_value, summary};
+use crate::pw_tokenizer::TokenDatabase;
+use crate::span_stack::{entry_spans, parse_marker, Span, SpanMarker};
+use crate::text_encoding::TextEncoding;
+use crate::timestamp::{Timestamp, TimestampFormat};
 use crate::{dap_server, log_search::*};
 
 use anyhow::{anyhow, Context, Result};
 
+#[derive(Clone, Debug)]
+enum BreakpointLocation {
+    Line { path: String, line: i64 },
+    /// The entries logged by a function whose name matches
+    Function(Regex),
+}
+
+impl BreakpointLocation {
+    /*
+    The name of a function breakpoint is a `/regex/`, a wildcard like
+    `handle_*`, or just a name. Wildcards and names also match qualified
+    names, like `Conn::handle` or `com.example.Conn.handle`.
+    */
+    fn function(name: &str) -> Result<Self> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow!("Function breakpoints need the name of a function"));
+        }
+        let pattern = match name.strip_prefix('/').and_then(|n| n.strip_suffix('/')) {
+            Some(pattern) => pattern.to_string(),
+            None => format!(
+                r"^(?:.*(?:::|\.))?{}$",
+                regex::escape(name)
+                    .replace(r"\*", ".*")
+                    .replace(r"\?", ".")
+            ),
+        };
+        Ok(BreakpointLocation::Function(
+            Regex::new(&pattern).context(format!("Invalid function name [{}]", name))?,
+        ))
+    }
+}
+
 #[derive(Clone, Debug)]
 struct RetreadBreakpoint {
-    path: String,
-    line: i64,
+    location: BreakpointLocation,
+    condition: Option<Condition>,
+    hit_condition: Option<HitCondition>,
+    /// How many times the breakpoint was hit since the replay last changed direction
+    hits: u64,
+    /// Logpoints print their message, with `{name}` replaced by the variable, instead of stopping
+    log_message: Option<String>,
 }
 
 impl RetreadBreakpoint {
-    pub fn new(source: &Source, breakpoint: &SourceBreakpoint) -> Option<Self> {
-        Some(RetreadBreakpoint {
-            path: source.path.clone()?,
+    pub fn new(source: &Source, breakpoint: &SourceBreakpoint) -> Result<Self> {
+        let location = BreakpointLocation::Line {
+            path: source
+                .path
+                .clone()
+                .context("Breakpoints need the path of their source")?,
             line: breakpoint.line,
+        };
+        Ok(RetreadBreakpoint {
+            log_message: breakpoint
+                .log_message
+                .clone()
+                .filter(|m| !m.trim().is_empty()),
+            ..RetreadBreakpoint::with_conditions(
+                location,
+                breakpoint.condition.as_deref(),
+                breakpoint.hit_condition.as_deref(),
+            )?
         })
     }
+
+    pub fn function(breakpoint: &FunctionBreakpoint) -> Result<Self> {
+        RetreadBreakpoint::with_conditions(
+            BreakpointLocation::function(&breakpoint.name)?,
+            breakpoint.condition.as_deref(),
+            breakpoint.hit_condition.as_deref(),
+        )
+    }
+
+    fn with_conditions(
+        location: BreakpointLocation,
+        condition: Option<&str>,
+        hit_condition: Option<&str>,
+    ) -> Result<Self> {
+        Ok(RetreadBreakpoint {
+            location,
+            condition: condition
+                .filter(|c| !c.trim().is_empty())
+                .map(Condition::parse)
+                .transpose()?,
+            hit_condition: hit_condition
+                .filter(|c| !c.trim().is_empty())
+                .map(HitCondition::parse)
+                .transpose()?,
+            hits: 0,
+            log_message: None,
+        })
+    }
+}
+
+fn variable(name: &str, value: String) -> Variable {
+    Variable {
+        name: name.to_string(),
+        value,
+        ..Default::default()
+    }
+}
+
+fn find_value(values: &[(String, String)], name: &str) -> Option<String> {
+    values
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.clone())
+}
+
+fn file_source(path: &str) -> Source {
+    Source {
+        name: path.split('/').next_back().map(|n| n.to_string()),
+        path: Some(path.to_string()),
+        ..Default::default()
+    }
+}
+
+fn exception_details(trace: &Trace) -> ExceptionDetails {
+    ExceptionDetails {
+        message: Some(trace.message.clone()),
+        type_name: trace.exception.split('.').next_back().map(|t| t.to_string()),
+        full_type_name: Some(trace.exception.clone()),
+        evaluate_name: None,
+        stack_trace: (!trace.text.is_empty()).then(|| trace.text.clone()),
+        inner_exception: trace.cause.as_ref().map(|c| vec![exception_details(c)]),
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum AppState {
-    Uninitialized(UninitializedState),
-    Running(RunningState),
+    Uninitialized(Box<UninitializedState>),
+    Running(Box<RunningState>),
     Exit,
 }
 
@@ -46,18 +178,196 @@ impl UninitializedState {
         UninitializedState { settings: None }
     }
 
+    fn load_program(data: &Map<String, Value>, program: &Value) -> Result<ProgramSettings> {
+        let args: Result<Vec<_>> = match data.get("args") {
+            Some(args) => args
+                .as_array()
+                .context("args is not a valid array")?
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .context(format!("Program argument [{}] is not a valid string", v))
+                        .map(|v| v.to_string())
+                })
+                .collect(),
+            None => Ok(Vec::new()),
+        };
+        let cwd = match data.get("cwd") {
+            Some(cwd) => Some(cwd.as_str().context("cwd is not a string")?.to_string()),
+            None => None,
+        };
+        let env: Result<HashMap<_, _>> = match data.get("env") {
+            Some(env) => env
+                .as_object()
+                .context("env is not a valid object")?
+                .iter()
+                .map(|(k, v)| {
+                    v.as_str()
+                        .context(format!("Environment variable [{}] is not a valid string", k))
+                        .map(|v| (k.to_string(), v.to_string()))
+                })
+                .collect(),
+            None => Ok(HashMap::new()),
+        };
+        let pause_on_breakpoint = match data.get("pause_on_breakpoint") {
+            Some(v) => v
+                .as_bool()
+                .context("pause_on_breakpoint is not a boolean")?,
+            None => false,
+        };
+
+        Ok(ProgramSettings {
+            program: program
+                .as_str()
+                .context("program is not a string")?
+                .to_string(),
+            args: args?,
+            cwd,
+            env: env?,
+            pause_on_breakpoint,
+        })
+    }
+
     fn load_settings(additional_data: &Option<Value>) -> Result<LogSearchSettings> {
         if let Some(Value::Object(data)) = additional_data {
-            let log_file = data
-                .get("log_file")
-                .context("Missing log file")?
-                .as_str()
-                .context("Log file is not a string")?;
-            let regex = data
-                .get("log_pattern")
-                .context("Missing log_pattern")?
-                .as_str()
-                .context("log_pattern is not a valid string")?;
+            let log_file = match data.get("log_file") {
+                Some(v) => Some(v.as_str().context("Log file is not a string")?),
+                None => None,
+            };
+            let encoding = |key: &str| match data.get(key) {
+                Some(e) => TextEncoding::new(
+                    e.as_str()
+                        .context(format!("{} is not a valid string", key))?,
+                ),
+                None => Ok(TextEncoding::default()),
+            };
+            let log_encoding = encoding("log_encoding")?;
+            let source_encoding = encoding("source_encoding")?;
+            let clean_lines = match data.get("strip_control_characters") {
+                Some(v) => v
+                    .as_bool()
+                    .context("strip_control_characters is not a boolean")?,
+                None => true,
+            };
+            let output = log_file.map(|f| f.to_string());
+            let (log_file_name, input) = match (
+                data.get("program"),
+                data.get("listen"),
+                data.get("connect"),
+            ) {
+                (Some(program), _, _) => {
+                    let program = UninitializedState::load_program(data, program)?;
+                    let name = log_file.unwrap_or(&program.program).to_string();
+                    (name, LogInput::Program(program, output))
+                }
+                (None, Some(address), _) | (None, None, Some(address)) => {
+                    let address = address.as_str().context("Socket address is not a string")?;
+                    let socket = SocketSettings {
+                        address: SocketAddress::parse(address),
+                        listen: data.contains_key("listen"),
+                    };
+                    let name = log_file.unwrap_or(address).to_string();
+                    (name, LogInput::Socket(socket, output))
+                }
+                (None, None, None) => {
+                    let log_file = log_file.context("Missing log file")?;
+                    (log_file.to_string(), LogInput::File(log_file.to_string()))
+                }
+            };
+            let mut preset = match data.get("log_preset") {
+                Some(p) => Some(find_preset(
+                    p.as_str().context("log_preset is not a valid string")?,
+                )?),
+                None => None,
+            };
+            let token_database = || match data.get("token_database") {
+                Some(p) => p.as_str().context("token_database is not a valid string"),
+                None => Err(anyhow!("Missing token_database")),
+            };
+            let log_reader = match data.get("log_format").and_then(|f| f.as_str()) {
+                Some("journal") => LogReader::Journal,
+                Some("logcat-binary") => LogReader::LogcatBinary,
+                Some("defmt") => LogReader::Defmt(Arc::new(DefmtTable::load(token_database()?)?)),
+                Some("pw_tokenizer") => {
+                    LogReader::PwTokenizer(Arc::new(TokenDatabase::load(token_database()?)?))
+                }
+                _ => LogReader::Text,
+            };
+            let log_format = match data.get("log_format").map(|f| f.as_str()) {
+                None | Some(Some("regex")) => {
+                    // A log_pattern overrides the regex of the preset
+                    let regexes = match (data.get("log_pattern"), preset, &input) {
+                        (Some(Value::Array(patterns)), _, _) => patterns
+                            .iter()
+                            .map(|v| {
+                                v.as_str()
+                                    .context(format!("log_pattern [{}] is not a valid string", v))
+                                    .map(|v| v.to_string())
+                            })
+                            .collect::<Result<Vec<_>>>()?,
+                        (Some(r), _, _) => vec![r
+                            .as_str()
+                            .context("log_pattern is not a valid string")?
+                            .to_string()],
+                        (None, Some(p), _) => vec![p.pattern.to_string()],
+                        (None, None, LogInput::File(path)) => {
+                            let inference = infer_log_pattern(path, log_encoding, clean_lines)?;
+                            // An inferred preset is used like a log_preset, timestamps and all
+                            preset = inference.preset;
+                            vec![inference.pattern]
+                        }
+                        (None, None, _) => return Err(anyhow!("Missing log_pattern")),
+                    };
+                    if regexes.is_empty() {
+                        return Err(anyhow!("log_pattern is an empty array"));
+                    }
+                    LogFormat::Regex(
+                        regexes
+                            .iter()
+                            .map(|r| Regex::new(r))
+                            .collect::<Result<Vec<_>, _>>()?,
+                    )
+                }
+                Some(Some(
+                    format @ ("json" | "logfmt" | "journal" | "logcat-binary" | "defmt"
+                    | "pw_tokenizer"),
+                )) => {
+                    let mapping = match data.get("log_fields") {
+                        Some(m) => Some(m.as_object().context("log_fields is not a valid object")?),
+                        None => None,
+                    };
+                    match format {
+                        "logfmt" => LogFormat::Logfmt(LogfmtFields::new(mapping)?),
+                        _ => LogFormat::Json(JsonFields::with_defaults(
+                            log_reader.json_fields(),
+                            mapping,
+                        )?),
+                    }
+                }
+                Some(f) => return Err(anyhow!("Unknown log_format {:?}", f)),
+            };
+            // An explicit timestamp_format overrides the one of the preset
+            let timestamp_format = match data.get("timestamp_format") {
+                Some(f) => TimestampFormat::new(
+                    f.as_str()
+                        .context("timestamp_format is not a valid string")?,
+                ),
+                None => preset
+                    .and_then(|p| p.timestamp_format)
+                    .map_or(TimestampFormat::Auto, TimestampFormat::new),
+            };
+            let symbols = match data.get("symbol_file") {
+                Some(p) => Some(SymbolTable::load(
+                    p.as_str().context("symbol_file is not a valid string")?,
+                )?),
+                None => None,
+            };
+            let min_level = match data.get("min_level") {
+                Some(l) => Some(LogLevel::new(
+                    l.as_str().context("min_level is not a valid string")?,
+                )?),
+                None => None,
+            };
             let include_pattern: Result<Vec<_>> = data
                 .get("include")
                 .context("Missing include")?
@@ -85,9 +395,16 @@ impl UninitializedState {
                 .collect();
 
             return Ok(LogSearchSettings {
-                log_file_name: log_file.to_string(),
-                log_file: fs::read_to_string(log_file)?,
-                log_pattern: Regex::new(regex)?,
+                log_file_name,
+                input,
+                log_format,
+                timestamp_format,
+                min_level,
+                log_encoding,
+                source_encoding,
+                clean_lines,
+                log_reader,
+                symbols,
                 include: include_pattern?,
                 exclude: exclude_pattern?,
             });
@@ -107,31 +424,47 @@ impl UninitializedState {
                 let rsp = request.success(ResponseBody::Initialize(Capabilities {
                     supports_step_back: Some(true),
                     supports_restart_request: Some(false),
+                    supports_exception_info_request: Some(true),
+                    supports_goto_targets_request: Some(true),
+                    supports_conditional_breakpoints: Some(true),
+                    supports_hit_conditional_breakpoints: Some(true),
+                    supports_log_points: Some(true),
+                    supports_function_breakpoints: Some(true),
                     ..Default::default()
                 }));
 
                 dap_server::write(Sendable::Response(rsp));
             }
 
-            Command::Launch(ref arguments) => {
-                let resp = match UninitializedState::load_settings(&arguments.additional_data) {
-                    Ok(s) => {
-                        self.settings = Some(s);
+            Command::Launch(LaunchRequestArguments {
+                ref additional_data,
+                ..
+            })
+            | Command::Attach(AttachRequestArguments {
+                ref additional_data,
+                ..
+            }) => {
+                // The log is read, or the program launched, before we answer, so failing to do so is an error the client sees
+                let running_state = UninitializedState::load_settings(additional_data)
+                    .and_then(|s| {
+                        self.settings = Some(s.clone());
+                        RunningState::new(s)
+                    });
+                match running_state {
+                    Ok(mut running_state) => {
                         dap_server::write(Sendable::Event(Event::Initialized));
-                        request.ack()?
+                        dap_server::write(Sendable::Response(request.ack()?));
+                        running_state.init()?;
+                        return Ok(Some(AppState::Running(Box::new(running_state))));
+                    }
+                    Err(e) => {
+                        self.settings = None;
+                        dap_server::write(Sendable::Response(request.error(&format!("{:#}", e))));
                     }
-                    Err(e) => request.error(&e.to_string()),
-                };
-                dap_server::write(Sendable::Response(resp));
-
-                if let Some(settings) = &self.settings {
-                    let mut running_state = RunningState::new(settings.clone())?;
-                    running_state.init()?;
-                    return Ok(Some(AppState::Running(running_state)));
                 }
             }
 
-            _ => panic!("Invalid request"),
+            _ => error!("Unhandled request: {:?}", request),
         }
 
         Ok(None)
@@ -144,6 +477,37 @@ impl Default for UninitializedState {
     }
 }
 
+/// How far past the current entry we look for the rest of a backtrace
+const MAX_BACKTRACE_ENTRIES: usize = 1024;
+/// How many values the history of a variable shows, around the current entry
+const MAX_HISTORY_ENTRIES: usize = 1000;
+
+/// What the scope of a stack frame shows
+#[derive(Clone, Debug)]
+enum FrameScope {
+    /// The message and fields of the entry at the index
+    Entry(usize),
+    /// The fields a span was entered with
+    Span(Vec<(String, String)>),
+}
+
+/// The spans a thread is in from an entry on, after the entry entered or left one
+#[derive(Clone, Debug)]
+struct SpanChange {
+    index: usize,
+    /// Outermost first
+    spans: Vec<Span>,
+    exit: bool,
+}
+
+/// What a variables reference shows
+#[derive(Clone, Debug)]
+enum VariableScope {
+    Variables(Vec<Variable>),
+    /// The values a variable of the entry at the index was logged with, found when they are asked for
+    History(String, usize),
+}
+
 #[derive(Clone, Debug)]
 pub struct RunningState {
     settings: LogSearchSettings,
@@ -152,6 +516,27 @@ pub struct RunningState {
     running: bool,
     reverse: bool,
     files: Vec<(PathBuf, String)>,
+    log: LogSource,
+    /// The first timestamp in the log, found on first use
+    start_time: Option<Timestamp>,
+    /// Names of the threads seen in the log. Thread `i + 1` is `threads[i]`, and 0 is `main`.
+    threads: Vec<String>,
+    /// How many lines of the log have been searched for threads and span markers
+    threads_scanned: usize,
+    /// Where the `enter foo` and `exit foo` markers of each thread changed its spans, in the order of the log
+    span_changes: HashMap<i64, Vec<SpanChange>>,
+    /// Whether any entry has no thread of its own, and so belongs to `main`
+    has_main_thread: bool,
+    /// Only the entries of this thread are stepped through
+    step_thread: Option<i64>,
+    /// The scopes of the frames sent since we stopped, by frame id
+    frame_scopes: HashMap<i64, FrameScope>,
+    /// The variables of the scopes sent since we stopped. Reference `i + 1` is `variables[i]`.
+    variables: Vec<VariableScope>,
+    /// A live source had no lines yet when we launched, so we stop at the entry once one arrives
+    entry_pending: bool,
+    /// Where each line of the log was found in the sources, as searching for it is slow
+    matches: RefCell<HashMap<usize, Option<LogMatch>>>,
 }
 
 impl RunningState {
@@ -171,17 +556,59 @@ impl RunningState {
             .flatten()
             .collect();
 
-        let file_paths: Vec<_> = include.filter(|p| !exclude.contains(p)).collect();
-        info!("Num paths: {}", file_paths.len());
-        let files: Vec<_> = file_paths
-            .iter()
-            .flat_map(|f| match std::fs::read_to_string(f) {
-                Ok(c) => Ok((f.clone(), c)),
-                Err(e) => Err(anyhow!("{}", e)),
-            })
+        let file_paths: Vec<_> = include
+            .filter(|p| !exclude.contains(p) && p.is_file())
             .collect();
+        info!("Num paths: {}", file_paths.len());
+
+        let encoding = settings.source_encoding;
+        let mut files = Vec::new();
+        let (mut binary, mut lossy, mut unreadable) = (Vec::new(), Vec::new(), Vec::new());
+        for path in file_paths {
+            let bytes = match std::fs::read(&path) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    unreadable.push(format!("{} ({})", path.display(), e));
+                    continue;
+                }
+            };
+            if encoding.is_binary(&bytes) {
+                binary.push(path.display().to_string());
+                continue;
+            }
+            let (contents, had_errors) = encoding.decode(&bytes);
+            if had_errors {
+                lossy.push(path.display().to_string());
+            }
+            files.push((path, contents.into_owned()));
+        }
 
         files.iter().for_each(|(f, _c)| info!("{}", f.display()));
+        info!("Searching {} source files", files.len());
+        if !binary.is_empty() {
+            warn!(
+                "Skipped {} binary files: {}",
+                binary.len(),
+                binary.join(", ")
+            );
+        }
+        if !lossy.is_empty() {
+            warn!(
+                "{} files are not valid {}, invalid characters were replaced: {}",
+                lossy.len(),
+                encoding.name(),
+                lossy.join(", ")
+            );
+        }
+        if !unreadable.is_empty() {
+            warn!(
+                "Unable to read {} files: {}",
+                unreadable.len(),
+                unreadable.join(", ")
+            );
+        }
+
+        let log = LogSource::open(&settings)?;
 
         Ok(RunningState {
             settings,
@@ -190,29 +617,562 @@ impl RunningState {
             running: false,
             reverse: false,
             files,
+            log,
+            start_time: None,
+            threads: Vec::new(),
+            threads_scanned: 0,
+            span_changes: HashMap::new(),
+            has_main_thread: false,
+            step_thread: None,
+            frame_scopes: HashMap::new(),
+            variables: Vec::new(),
+            entry_pending: false,
+            matches: RefCell::new(HashMap::new()),
         })
     }
 
     pub fn init(&mut self) -> Result<()> {
+        if self.log.len() == 0 && self.log.is_live() {
+            self.entry_pending = true;
+            return Ok(());
+        }
         self.stop(StoppedEventReason::Entry)
     }
 
+    fn get_log_line(&self) -> Result<String> {
+        self.log
+            .get(self.log_index)
+            .map(|l| l.to_string())
+            .context(format!(
+                "Unable to get line {} from log file",
+                self.log_index
+            ))
+    }
+
     fn get_log_line_search(&mut self) -> Result<LogLineSearch> {
-        loop {
-            let log_line = self
+        for _ in 0..self.max_steps() {
+            let log_line = self.get_log_line()?;
+            if let Ok(res) = self.settings.log_format.parse(&log_line) {
+                if !self.skipped(&res) {
+                    return Ok(res);
+                }
+            }
+            self.step_log_index()?;
+        }
+        Err(anyhow!("No line of the log matched the log_pattern and min_level"))
+    }
+
+    // Entries below the min_level, or of another thread than the one being stepped, are passed over
+    fn skipped(&self, search: &LogLineSearch) -> bool {
+        let below_min_level = matches!(
+            (self.settings.min_level, search.level()),
+            (Some(min), Some(level)) if level < min
+        );
+        let other_thread = match self.step_thread {
+            Some(thread_id) => !self.is_thread(thread_id, search),
+            None => false,
+        };
+        below_min_level || other_thread
+    }
+
+    fn is_thread(&self, thread_id: i64, search: &LogLineSearch) -> bool {
+        match (thread_id, search.thread()) {
+            (0, thread) => thread.is_none(),
+            (_, Some(thread)) => self.threads.get(thread_id as usize - 1) == Some(&thread),
+            (_, None) => false,
+        }
+    }
+
+    // The DAP thread of an entry, registering the threads as they are first seen
+    fn thread_id(&mut self, search: &LogLineSearch) -> i64 {
+        let Some(thread) = search.thread() else {
+            self.has_main_thread = true;
+            return 0;
+        };
+        match self.threads.iter().position(|t| *t == thread) {
+            Some(i) => i as i64 + 1,
+            None => {
+                self.threads.push(thread);
+                self.threads.len() as i64
+            }
+        }
+    }
+
+    // Searches the lines added since the last scan for new threads, and the spans they enter and leave
+    fn scan_threads(&mut self) {
+        while self.threads_scanned < self.log.len() {
+            let index = self.threads_scanned;
+            let line = self.log.get(index).unwrap_or_default();
+            if let Ok(search) = self.settings.log_format.parse(line) {
+                let thread_id = self.thread_id(&search);
+                if let Some(marker) = parse_marker(&search.message) {
+                    self.change_spans(thread_id, index, marker);
+                }
+            }
+            self.threads_scanned += 1;
+        }
+    }
+
+    // An exit leaves the innermost span of that name, and is ignored if the thread is not in one
+    fn change_spans(&mut self, thread_id: i64, index: usize, marker: SpanMarker) {
+        let changes = self.span_changes.entry(thread_id).or_default();
+        let mut spans = changes.last().map_or(Vec::new(), |c| c.spans.clone());
+        let exit = match marker {
+            SpanMarker::Enter(span) => {
+                spans.push(Span {
+                    entered_at: Some(index),
+                    ..span
+                });
+                false
+            }
+            SpanMarker::Exit(name) => match spans.iter().rposition(|s| s.name == name) {
+                Some(i) => {
+                    spans.remove(i);
+                    true
+                }
+                None => return,
+            },
+        };
+        changes.push(SpanChange { index, spans, exit });
+    }
+
+    // The most recent entry of a thread, up to the current one
+    fn latest_entry_of(&self, thread_id: i64) -> Option<(usize, LogLineSearch)> {
+        (0..=self.log_index).rev().find_map(|index| {
+            let search = self.settings.log_format.parse(self.log.get(index)?).ok()?;
+            self.is_thread(thread_id, &search).then_some((index, search))
+        })
+    }
+
+    // Searching both ways through the whole log, without finding anything, means there is nothing to find
+    fn max_steps(&self) -> usize {
+        2 * self.log.len() + 2
+    }
+
+    fn timestamp_at(&self, index: usize) -> Option<Timestamp> {
+        let search = self.settings.log_format.parse(self.log.get(index)?).ok()?;
+        let (_, timestamp) = search.fields.iter().find(|(f, _)| f == "timestamp")?;
+        self.settings.timestamp_format.parse(timestamp)
+    }
+
+    // The time of the entry at `index`, the entry before it and the start of the log
+    fn timestamps(&mut self, index: usize) -> Option<(Timestamp, Option<Timestamp>, Timestamp)> {
+        let time = self.timestamp_at(index)?;
+        let previous = (0..index).rev().find_map(|i| self.timestamp_at(i));
+        if self.start_time.is_none() {
+            self.start_time = (0..=index).find_map(|i| self.timestamp_at(i));
+        }
+        Some((time, previous, self.start_time?))
+    }
+
+    /*
+    The frames of the entry at `index`: where it was logged in the sources,
+    if we found it, then the kernel backtrace or exception trace starting at
+    the entry and the spans of the thread, if any, on top of where it is in the log file.
+    Frames of the current entry are numbered from 0, others after their index.
+    */
+    fn stack_frames(
+        &mut self,
+        index: usize,
+        thread_id: i64,
+        log_match: Option<&LogMatch>,
+        search_options: LogLineSearch,
+    ) -> Result<Vec<StackFrame>> {
+        let first_id = match index == self.log_index {
+            true => 0,
+            false => (index as i64 + 1) << 16,
+        };
+        let mut frames = Vec::new();
+        if let Some(log_match) = log_match {
+            let name = log_match
+                .file
+                .split('/')
+                .next_back()
+                .context(format!("Unable to parse path {}", log_match.file))?
+                .to_string();
+            let source = Source {
+                path: Some(log_match.file.clone()),
+                name: Some(name.clone()),
+                ..Default::default()
+            };
+            let frame_name = match search_options.func {
+                Some(func) => format!("{}:{}", func, log_match.line),
+                None => format!("{}:{}", name, log_match.line),
+            };
+
+            self.frame_scopes.insert(first_id, FrameScope::Entry(index));
+            frames.push(StackFrame {
+                id: first_id,
+                name: frame_name,
+                source: Some(source),
+                line: log_match.line as i64,
+                ..Default::default()
+            });
+        }
+
+        for frame in self.kernel_backtrace(index) {
+            let location = match (&frame.file, frame.line, &frame.function) {
+                (Some(file), Some(line), _) => source_path(&self.files, file).map(|file| LogMatch {
+                    file,
+                    line: line as usize,
+                    score: 0,
+                }),
+                (_, _, Some(function)) => find_function(&self.files, function),
+                _ => None,
+            };
+            frames.push(StackFrame {
+                id: first_id + frames.len() as i64,
+                name: frame.name,
+                source: location.as_ref().map(|l| file_source(&l.file)),
+                line: location.as_ref().map_or(0, |l| l.line as i64),
+                presentation_hint: frame
+                    .unreliable
+                    .then_some(StackFramePresentationhint::Subtle),
+                instruction_pointer_reference: frame.address.map(|a| format!("0x{:x}", a)),
+                ..Default::default()
+            });
+        }
+
+        let trace = self.exception_trace(index);
+        for frame in trace.iter().flat_map(|t| &t.frames) {
+            // Library frames are not searched for, as they would only match files of the same name
+            let path = match (&frame.file, frame.library) {
+                (Some(file), false) => source_path(&self.files, file),
+                _ => None,
+            };
+            frames.push(StackFrame {
+                id: first_id + frames.len() as i64,
+                name: match (&path, &frame.file, frame.line) {
+                    (None, Some(file), Some(line)) => {
+                        format!("{} ({}:{})", frame.function, file, line)
+                    }
+                    _ => frame.function.clone(),
+                },
+                source: path.as_deref().map(file_source),
+                line: match path {
+                    Some(_) => frame.line.unwrap_or(0) as i64,
+                    None => 0,
+                },
+                column: frame.column.unwrap_or(0) as i64,
+                presentation_hint: frame
+                    .library
+                    .then_some(StackFramePresentationhint::Subtle),
+                ..Default::default()
+            });
+        }
+
+        self.scan_threads();
+        for span in self.span_stack(index, thread_id) {
+            let id = first_id + frames.len() as i64;
+            let location = self.span_location(&span);
+            frames.push(StackFrame {
+                id,
+                name: span.name,
+                source: location.as_ref().map(|l| file_source(&l.file)),
+                line: location.as_ref().map_or(0, |l| l.line as i64),
+                ..Default::default()
+            });
+            self.frame_scopes.insert(id, FrameScope::Span(span.fields));
+        }
+
+        let id = first_id + frames.len() as i64;
+        self.frame_scopes.insert(id, FrameScope::Entry(index));
+        frames.push(StackFrame {
+            id,
+            name: self
                 .settings
-                .log_file
-                .lines()
-                .nth(self.log_index)
+                .log_file_name
+                .split('/')
+                .next_back()
                 .context(format!(
-                    "Unable to get line {} from log file",
-                    self.log_index
-                ))?;
-            if let Ok(res) = LogLineSearch::new(&self.settings.log_pattern, log_line) {
-                return Ok(res);
+                    "Unable to parse path {}",
+                    self.settings.log_file_name
+                ))?
+                .to_string(),
+            source: Some(self.get_log_file_source()),
+            line: (index + 1) as i64,
+            ..Default::default()
+        });
+        Ok(frames)
+    }
+
+    /*
+    The frames of the kernel backtrace starting at the entry at `index`,
+    e.g. when stopped at the `BUG:` line of an oops. The backtrace goes on
+    as long as the entries are frames, or the registers and markers in
+    between them.
+    */
+    fn kernel_backtrace(&self, index: usize) -> Vec<KernelFrame> {
+        let mut frames = Vec::new();
+        for message in self.messages_from(index) {
+            let raw_frames = parse_frames(&message);
+            if raw_frames.is_empty() && !is_oops_line(&message) {
+                break;
+            }
+            for frame in raw_frames {
+                match &self.settings.symbols {
+                    Some(symbols) => frames.extend(symbols.resolve(&frame)),
+                    None => frames.push(frame.unresolved()),
+                }
+            }
+        }
+        frames
+    }
+
+    /*
+    The panic or exception logged by the entry at `index`, either by the
+    entry itself or by the lines right after it, the way `logger.exception()`
+    and `log.error("...", e)` log them.
+    */
+    fn exception_trace(&self, index: usize) -> Option<Trace> {
+        let messages = self.messages_from(index);
+        parse_trace(&messages).or_else(|| parse_trace(messages.get(1..)?))
+    }
+
+    // Hands out a reference to the variables, valid until we stop again
+    fn allocate_variables(&mut self, variables: Vec<Variable>) -> i64 {
+        self.variables.push(VariableScope::Variables(variables));
+        self.variables.len() as i64
+    }
+
+    // A variable of the entry at `index`, which expands to its values over the log
+    fn history_variable(&mut self, name: &str, value: String, index: usize) -> Variable {
+        self.variables
+            .push(VariableScope::History(name.to_string(), index));
+        Variable {
+            variables_reference: self.variables.len() as i64,
+            ..variable(name, value)
+        }
+    }
+
+    /*
+    The values of the variable `name` over the log, by index: at every entry
+    logged by the same call as the entry at `index`, or at every entry with
+    the variable if that entry was not found in the sources. Only the
+    entries closest to `index` are kept.
+    */
+    fn variable_history(&self, name: &str, index: usize) -> Vec<(usize, String)> {
+        let call = |m: Option<&LogMatch>| m.map(|m| (m.file.clone(), m.line));
+        let log_call = call(self.match_at(index).as_ref());
+        let mut history = Vec::new();
+        for i in 0..self.log.len() {
+            let Some(line) = self.log.get(i) else {
+                continue;
+            };
+            let Result::Ok(search) = self.settings.log_format.parse(line) else {
+                continue;
+            };
+            let log_match = self.match_at(i);
+            if log_call.is_some() && call(log_match.as_ref()) != log_call {
+                continue;
+            }
+            let value = self
+                .logged_arguments(&search.message, log_match.as_ref())
+                .into_iter()
+                .chain(search.fields)
+                .find(|(n, _)| n == name);
+            if let Some((_, value)) = value {
+                history.push((i, value));
+            }
+        }
+
+        let start = history
+            .partition_point(|(i, _)| *i < index)
+            .saturating_sub(MAX_HISTORY_ENTRIES / 2)
+            .min(history.len().saturating_sub(MAX_HISTORY_ENTRIES));
+        history
+            .into_iter()
+            .skip(start)
+            .take(MAX_HISTORY_ENTRIES)
+            .collect()
+    }
+
+    fn history_variables(&self, name: &str, index: usize) -> Vec<Variable> {
+        self.variable_history(name, index)
+            .into_iter()
+            .map(|(i, value)| {
+                let name = match self.timestamp_at(i) {
+                    Some(time) => format!("line {} at {}", i + 1, time),
+                    None => format!("line {}", i + 1),
+                };
+                // Evaluating an entry jumps to it, which the editor does when it is selected
+                Variable {
+                    evaluate_name: Some(format!("goto {}", i + 1)),
+                    ..variable(&name, value)
+                }
+            })
+            .collect()
+    }
+
+    // The values of the arguments of the log call that logged the message, if it was found
+    fn logged_arguments(
+        &self,
+        message: &str,
+        log_match: Option<&LogMatch>,
+    ) -> Vec<(String, String)> {
+        let Some(log_match) = log_match else {
+            return Vec::new();
+        };
+        match self
+            .files
+            .iter()
+            .find(|(f, _)| f.to_str() == Some(&log_match.file))
+        {
+            Some((path, contents)) => {
+                // Rust formats with braces only, while `%` means printf in most other languages
+                let printf = path.extension().is_none_or(|e| e != "rs");
+                recover_arguments(contents, log_match.line, message, printf)
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /*
+    A variable showing a JSON value, whose objects and arrays can be
+    expanded. `path` is how the value is named in the entry, like
+    `payload.items[0]`.
+    */
+    fn value_variable(&mut self, name: &str, path: &str, value: &Value) -> Variable {
+        let children: Vec<Variable> = match value {
+            Value::Object(map) => map
+                .iter()
+                .map(|(key, child)| self.value_variable(key, &format!("{}.{}", path, key), child))
+                .collect(),
+            Value::Array(items) => items
+                .iter()
+                .enumerate()
+                .map(|(i, child)| {
+                    self.value_variable(&i.to_string(), &format!("{}[{}]", path, i), child)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let count = Some(children.len() as i64);
+        Variable {
+            name: name.to_string(),
+            value: summary(value),
+            evaluate_name: Some(path.to_string()),
+            named_variables: count.filter(|_| value.is_object()),
+            indexed_variables: count.filter(|_| value.is_array()),
+            variables_reference: match children.is_empty() {
+                true => 0,
+                false => self.allocate_variables(children),
+            },
+            ..Default::default()
+        }
+    }
+
+    /*
+    The locals of the entry at `index`: its message, the arguments of the
+    log call that logged it, and the named captures of the log_pattern or
+    fields of the entry, followed by when it was logged, where it is in the
+    log and how well it matched the sources.
+    */
+    fn entry_variables(&mut self, index: usize) -> Vec<Variable> {
+        let line = self.log.get(index).unwrap_or_default().to_string();
+        let search = self
+            .settings
+            .log_format
+            .parse(&line)
+            .unwrap_or_else(|_| LogLineSearch::plain(&line));
+
+        let log_match = self.match_at(index);
+        let mut variables = vec![variable("message", search.message.clone())];
+        if let Some(payload) = find_payload(&search.message) {
+            variables.push(self.value_variable("payload", "payload", &payload));
+        }
+        for (name, value) in self.logged_arguments(&search.message, log_match.as_ref()) {
+            variables.push(self.history_variable(&name, value, index));
+        }
+        if let Some(func) = &search.func {
+            variables.push(variable("func", func.clone()));
+        }
+        if let Some(file) = &search.file {
+            variables.push(variable("file", file.clone()));
+        }
+        if let Some(line) = search.line {
+            variables.push(variable("line", line.to_string()));
+        }
+        for (name, value) in &search.fields {
+            variables.push(match parse_value(value) {
+                Some(value) => self.value_variable(name, name, &value),
+                None => self.history_variable(name, value.clone(), index),
+            });
+        }
+        if let Some((time, previous, start)) = self.timestamps(index) {
+            variables.push(variable("time", time.to_string()));
+            if let Some(previous) = previous {
+                variables.push(variable("delta", time.since(&previous).to_string()));
             }
-            self.increment_log_index().unwrap();
+            variables.push(variable("elapsed", time.since(&start).to_string()));
+        }
+        variables.push(variable("log_line", (index + 1).to_string()));
+        if let Some(log_match) = log_match {
+            variables.push(variable(
+                "score",
+                match log_match.score {
+                    EXACT_SCORE => "exact".to_string(),
+                    score => score.to_string(),
+                },
+            ));
+        }
+        if let Some(pattern) = search.pattern {
+            variables.push(variable("pattern", pattern.to_string()));
         }
+        if let Some(raw) = self.log.get_raw(index) {
+            variables.push(variable("raw", raw.to_string()));
+        }
+        variables
+    }
+
+    /*
+    The spans the entry at `index` is in, innermost first. Entries logged by
+    tracing name their spans themselves. Otherwise the spans are the
+    `enter foo` markers of the thread up to the entry, that were not left by
+    an `exit foo` before it, as found by `scan_threads`.
+    */
+    fn span_stack(&self, index: usize, thread_id: i64) -> Vec<Span> {
+        let search = self
+            .log
+            .get(index)
+            .and_then(|line| self.settings.log_format.parse(line).ok());
+        if let Some(spans) = search.and_then(|s| entry_spans(&s.message, &s.fields)) {
+            return spans.into_iter().rev().collect();
+        }
+
+        let Some(changes) = self.span_changes.get(&thread_id) else {
+            return Vec::new();
+        };
+        let changes = &changes[..changes.partition_point(|c| c.index <= index)];
+        let change = match changes.last() {
+            // The exit marker is logged from inside the span it leaves
+            Some(change) if change.index == index && change.exit => changes.iter().nth_back(1),
+            change => change,
+        };
+        change.map_or(Vec::new(), |c| c.spans.iter().rev().cloned().collect())
+    }
+
+    // Spans entered by a marker are where the marker was logged, others are where their function is
+    fn span_location(&self, span: &Span) -> Option<LogMatch> {
+        let marker = span.entered_at.and_then(|i| self.match_at(i));
+        marker
+            .or_else(|| find_function(&self.files, &span.name))
+            .or_else(|| find_span(&self.files, &span.name))
+    }
+
+    /*
+    The messages of the entries from `index` on, where a backtrace would be.
+    The lines of a backtrace rarely match the log_pattern, and are used as
+    they are, keeping the indentation that tracebacks depend on.
+    */
+    fn messages_from(&self, index: usize) -> Vec<String> {
+        (index..self.log.len().min(index + MAX_BACKTRACE_ENTRIES))
+            .filter_map(|i| self.log.get(i))
+            .map(|line| match self.settings.log_format.parse(line) {
+                Ok(search) if search.message != line.trim() => search.message,
+                _ => line.to_string(),
+            })
+            .collect()
     }
 
     fn get_log_file_source(&self) -> Source {
@@ -221,32 +1181,31 @@ impl RunningState {
                 self.settings
                     .log_file_name
                     .split('/')
-                    .last()
+                    .next_back()
                     .unwrap()
                     .to_string(),
             ),
-            path: Some(self.settings.log_file_name.to_string()),
+            path: self.log.path().map(|p| p.to_string()),
             ..Default::default()
         }
     }
 
     fn clear_breakpoints_for_source(&mut self, source: &Source) {
-        self.breakpoints.retain(|b| {
-            if let Some(ref p) = source.path {
-                *p != b.path
-            } else {
-                true
-            }
+        self.breakpoints.retain(|b| match (&source.path, &b.location) {
+            (Some(p), BreakpointLocation::Line { path, .. }) => p != path,
+            _ => true,
         });
     }
 
     fn increment_log_index(&mut self) -> Result<()> {
         if self.reverse && self.log_index > 0 {
             self.log_index -= 1;
-        } else if !self.reverse && self.log_index + 1 < self.settings.log_file.lines().count() {
+        } else if !self.reverse && self.log_index + 1 < self.log.len() {
             self.log_index += 1;
+        } else if !self.reverse && self.log.is_live() {
+            // Wait for the source to produce more lines
         } else {
-            self.reverse = !self.reverse;
+            self.set_reverse(!self.reverse);
             if self.running {
                 self.stop(StoppedEventReason::Entry)?;
             }
@@ -255,55 +1214,354 @@ impl RunningState {
         Ok(())
     }
 
+    // Like increment_log_index, but fails instead of spinning while waiting for a live source
+    fn step_log_index(&mut self) -> Result<()> {
+        let previous = self.log_index;
+        self.increment_log_index()?;
+        if self.log_index == previous && self.log.is_live() {
+            return Err(anyhow!("Waiting for more log output"));
+        }
+        Ok(())
+    }
+
+    // The log index is at the end of a live source, with nothing new to step to
+    fn waiting_for_log(&self) -> bool {
+        !self.reverse && self.log.is_live() && self.log_index + 1 >= self.log.len()
+    }
+
     fn get_log_match(&mut self) -> Result<LogMatch> {
-        loop {
-            let log_line = self
-                .settings
-                .log_file
-                .lines()
-                .nth(self.log_index)
-                .context(format!(
-                    "Unable to get line {} from log file",
-                    self.log_index
-                ))?;
-            let res = search_files(&self.files, &self.settings.log_pattern, log_line);
-            if let Some(res) = res {
-                if res.score > 0 {
+        for _ in 0..self.max_steps() {
+            let log_line = self.get_log_line()?;
+            let skipped = match self.settings.log_format.parse(&log_line) {
+                Ok(search) => self.skipped(&search),
+                Err(_) => false,
+            };
+            if !skipped {
+                if let Some(res) = self.match_at(self.log_index) {
                     return Ok(res);
                 }
             }
 
-            self.increment_log_index()?;
+            self.step_log_index()?;
+        }
+        Err(anyhow!("No line of the log matched the source files and min_level"))
+    }
+
+    // Tokenized entries are looked up by where they were logged, anything else by searching for the message
+    fn find_in_sources(&self, log_line: &str) -> Option<LogMatch> {
+        if self.settings.log_reader.is_tokenized() {
+            let search = self.settings.log_format.parse(log_line).ok();
+            if let Some(res) = search.and_then(|s| locate(&self.files, &s)) {
+                return Some(res);
+            }
+        }
+        search_files(&self.files, &self.settings.log_format, log_line).filter(|m| m.score > 0)
+    }
+
+    // Lines of the log never change once added, so where they were found is only searched for once
+    fn match_at(&self, index: usize) -> Option<LogMatch> {
+        if let Some(log_match) = self.matches.borrow().get(&index) {
+            return log_match.clone();
+        }
+        let log_match = self.find_in_sources(self.log.get(index)?);
+        self.matches.borrow_mut().insert(index, log_match.clone());
+        log_match
+    }
+
+    // The next entry after the current one logged at the line of the source, wrapping around
+    fn next_entry_at(&self, path: &str, line: usize) -> Option<usize> {
+        let len = self.log.len();
+        (1..=len).map(|n| (self.log_index + n) % len).find(|i| {
+            self.match_at(*i)
+                .is_some_and(|m| m.file == path && m.line == line)
+        })
+    }
+
+    // Commands typed into the debug console
+    fn evaluate(&mut self, expression: &str) -> Result<String> {
+        let mut words = expression.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("level"), Some("off" | "all")) => {
+                self.settings.min_level = None;
+                Ok("Showing entries of all levels".to_string())
+            }
+            (Some("level"), Some(level)) => {
+                let level = LogLevel::new(level)?;
+                self.settings.min_level = Some(level);
+                Ok(format!("Skipping entries below {}", level))
+            }
+            (Some("level"), None) => Ok(match self.settings.min_level {
+                Some(level) => format!("Skipping entries below {}", level),
+                None => "Showing entries of all levels".to_string(),
+            }),
+            (Some("goto"), Some(line)) => {
+                let line: usize = line
+                    .parse()
+                    .context(format!("Invalid line number {}", line))?;
+                self.goto(line)?;
+                Ok(format!("Moved to line {} of the log", line))
+            }
+            (Some("history"), Some(name)) => {
+                let history = self.variable_history(name, self.log_index);
+                if history.is_empty() {
+                    return Err(anyhow!("No values of {} were logged", name));
+                }
+                Ok(history
+                    .into_iter()
+                    .map(|(i, value)| match self.timestamp_at(i) {
+                        Some(time) => format!("{:>8}  {}  {}", i + 1, time, value),
+                        None => format!("{:>8}  {}", i + 1, value),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+            _ => Err(anyhow!(
+                "Unknown command [{}], expected one of: level <LEVEL|off>, goto <LINE>, history <VARIABLE>",
+                expression
+            )),
         }
     }
 
+    // Moves to the entry at a line of the log, counting from 1
+    fn goto(&mut self, line: usize) -> Result<()> {
+        if line == 0 || line > self.log.len() {
+            return Err(anyhow!(
+                "Line {} is not in the log, which has {} lines",
+                line,
+                self.log.len()
+            ));
+        }
+        self.log_index = line - 1;
+        self.running = false;
+        // Otherwise stopping there passes over entries of other threads, to the next one of the stepped thread
+        self.step_thread = None;
+        Ok(())
+    }
+
     fn stop(&mut self, reason: StoppedEventReason) -> Result<()> {
+        // Finding the description may step to the end of the log, which must not stop us again
+        self.running = false;
+        self.frame_scopes.clear();
+        self.variables.clear();
+        let search = self.get_log_line_search().ok();
+        let thread_id = search.as_ref().map_or(0, |s| self.thread_id(s));
+        // Stopping at a panic or exception shows it like a debugger would, unless a breakpoint is why we stopped
+        let trace = self.exception_trace(self.log_index);
+        let reason = match (&trace, reason) {
+            (Some(_), r) if !matches!(r, StoppedEventReason::Breakpoint) => {
+                StoppedEventReason::Exception
+            }
+            (_, r) => r,
+        };
         let stop_event = Event::Stopped(StoppedEventBody {
             reason,
-            description: Some(self.get_log_line_search()?.message),
-            thread_id: Some(0),
+            description: search.map(|s| s.message),
+            thread_id: Some(thread_id),
             preserve_focus_hint: Some(false),
-            text: None,
+            text: trace.map(|t| t.exception),
             all_threads_stopped: Some(false),
             hit_breakpoint_ids: None,
         });
 
         dap_server::write(Sendable::Event(stop_event));
-        self.running = false;
 
         Ok(())
     }
 
+    /*
+    Whether a breakpoint is at the current entry, in the sources or the log
+    file, and its condition holds. Every breakpoint that is hit counts the
+    hit, and stops only if its hit condition holds too. Logpoints print
+    their message instead of stopping.
+    */
+    fn hit_breakpoint(&mut self, log_match: &LogMatch) -> bool {
+        // The variables and functions are only found if a breakpoint needs them
+        let values = OnceCell::new();
+        let functions = OnceCell::new();
+        let hit: Vec<bool> = self
+            .breakpoints
+            .iter()
+            .map(|b| {
+                let at_entry = match &b.location {
+                    BreakpointLocation::Line { path, line } => {
+                        (*line == log_match.line as i64 && *path == log_match.file)
+                            || (*line as usize == self.log_index + 1
+                                && *path == self.settings.log_file_name)
+                    }
+                    BreakpointLocation::Function(pattern) => functions
+                        .get_or_init(|| self.entry_functions(self.log_index, log_match))
+                        .iter()
+                        .any(|f: &String| pattern.is_match(f)),
+                };
+                at_entry
+                    && b.condition.as_ref().is_none_or(|c| {
+                        let values = values
+                            .get_or_init(|| self.condition_values(self.log_index, log_match));
+                        c.evaluate(&|name| find_value(values, name))
+                    })
+            })
+            .collect();
+
+        let mut stop = false;
+        let mut logpoints = Vec::new();
+        for (breakpoint, hit) in self.breakpoints.iter_mut().zip(hit) {
+            if !hit {
+                continue;
+            }
+            breakpoint.hits += 1;
+            if breakpoint
+                .hit_condition
+                .is_none_or(|c| c.matches(breakpoint.hits))
+            {
+                match (&breakpoint.log_message, &breakpoint.location) {
+                    (Some(message), BreakpointLocation::Line { path, line }) => {
+                        logpoints.push((path.clone(), *line, message.clone()))
+                    }
+                    _ => stop = true,
+                }
+            }
+        }
+        for (path, line, message) in logpoints {
+            let values = values.get_or_init(|| self.condition_values(self.log_index, log_match));
+            let output = interpolate(&message, &|name| find_value(values, name));
+            dap_server::write(Sendable::Event(Event::Output(OutputEventBody {
+                category: Some(OutputEventCategory::Stdout),
+                output: output + "\n",
+                source: Some(file_source(&path)),
+                line: Some(line),
+                ..Default::default()
+            })));
+        }
+        stop
+    }
+
+    // The functions the entry was logged by: its `func`, and the function around where it was found
+    fn entry_functions(&self, index: usize, log_match: &LogMatch) -> Vec<String> {
+        let mut functions = Vec::new();
+        let search = self
+            .log
+            .get(index)
+            .and_then(|l| self.settings.log_format.parse(l).ok());
+        if let Some(func) = search.and_then(|s| s.func) {
+            // Some loggers write the function as it is called, e.g. `main()`
+            functions.push(func.trim_end_matches("()").to_string());
+        }
+        let source = self
+            .files
+            .iter()
+            .find(|(f, _)| f.to_str() == Some(&log_match.file));
+        if let Some(function) = source.and_then(|(_, c)| enclosing_function(c, log_match.line)) {
+            functions.push(function);
+        }
+        functions
+    }
+
+    // Hits are counted in the direction we are going, and start over when it changes
+    fn set_reverse(&mut self, reverse: bool) {
+        if self.reverse != reverse {
+            for breakpoint in &mut self.breakpoints {
+                breakpoint.hits = 0;
+            }
+        }
+        self.reverse = reverse;
+    }
+
+    /*
+    The variables conditions can use: the message, the arguments of the log
+    call, the captures or fields of the entry, the keys of a payload in the
+    message, and when the entry was logged.
+    */
+    fn condition_values(&self, index: usize, log_match: &LogMatch) -> Vec<(String, String)> {
+        let Some(search) = self
+            .log
+            .get(index)
+            .and_then(|l| self.settings.log_format.parse(l).ok())
+        else {
+            return Vec::new();
+        };
+        let mut values = vec![("message".to_string(), search.message.clone())];
+        values.extend(self.logged_arguments(&search.message, Some(log_match)));
+        if let Some(func) = &search.func {
+            values.push(("func".to_string(), func.clone()));
+        }
+        if let Some(file) = &search.file {
+            values.push(("file".to_string(), file.clone()));
+        }
+        if let Some(line) = search.line {
+            values.push(("line".to_string(), line.to_string()));
+        }
+        values.extend(search.fields);
+        if let Some(payload) = find_payload(&search.message) {
+            if let Value::Object(map) = &payload {
+                values.extend(map.iter().map(|(k, v)| (k.clone(), summary(v))));
+            }
+            values.extend(leaves("payload", &payload));
+        }
+        if let Some(time) = self.timestamp_at(index) {
+            values.push(("time".to_string(), time.to_string()));
+        }
+        values.push(("log_line".to_string(), (index + 1).to_string()));
+        if let Some(pattern) = search.pattern {
+            values.push(("pattern".to_string(), pattern.to_string()));
+        }
+        values
+    }
+
+    fn pauses_program(&self) -> bool {
+        matches!(self.settings.input, LogInput::Program(ref p, _) if p.pause_on_breakpoint)
+    }
+
+    fn resume_program(&self) {
+        if self.pauses_program() {
+            self.log.resume();
+        }
+    }
+
+    fn stack_trace(&mut self, thread_id: i64) -> Result<Vec<StackFrame>> {
+        let log_match = self.get_log_match()?;
+        let search_options = self.get_log_line_search()?;
+        // Other threads show their most recent entry, which may not be in the sources
+        if self.thread_id(&search_options) == thread_id {
+            return self.stack_frames(self.log_index, thread_id, Some(&log_match), search_options);
+        }
+        match self.latest_entry_of(thread_id) {
+            Some((index, search)) => {
+                let log_match = self.match_at(index);
+                self.stack_frames(index, thread_id, log_match.as_ref(), search)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
     pub fn run(&mut self) -> Result<Option<AppState>> {
-        if self.running {
+        self.log.poll();
+        if self.entry_pending && (self.log.len() > 0 || !self.log.is_live()) {
+            self.entry_pending = false;
+            // Unless the editor already continued without waiting for it
+            if !self.running {
+                self.stop(StoppedEventReason::Entry)?;
+            }
+        }
+        if self.running && !self.waiting_for_log() {
             self.increment_log_index()?;
-            let m = self.get_log_match()?;
-            if self.breakpoints.iter().any(|b| {
-                (b.line == m.line as i64 && b.path == m.file)
-                    || (b.line as usize == self.log_index + 1
-                        && b.path == self.settings.log_file_name)
-            }) {
-                self.stop(StoppedEventReason::Breakpoint)?;
+            let m = match self.get_log_match() {
+                Ok(m) => Some(m),
+                Err(_) if self.waiting_for_log() => None,
+                Err(e) => {
+                    if self.running {
+                        self.stop(StoppedEventReason::Pause)?;
+                    }
+                    return Err(e);
+                }
+            };
+            // Reaching the end of the log stopped us, without hitting the entry we turned around at
+            if let Some(m) = m.filter(|_| self.running) {
+                if self.hit_breakpoint(&m) {
+                    self.stop(StoppedEventReason::Breakpoint)?;
+                    if self.pauses_program() {
+                        self.log.pause();
+                    }
+                }
             }
         }
         let request = match dap_server::read() {
@@ -313,22 +1571,31 @@ impl RunningState {
         info!("Got: {:?}", request);
 
         match request.command {
-            Command::Next(_) | Command::StepIn(_) | Command::StepOut(_) => {
+            Command::Next(NextArguments { thread_id, .. })
+            | Command::StepIn(StepInArguments { thread_id, .. })
+            | Command::StepOut(StepOutArguments { thread_id, .. }) => {
                 dap_server::write(Sendable::Response(request.ack()?));
-                self.stop(StoppedEventReason::Step)?;
-                self.reverse = false;
+                self.set_reverse(false);
+                // A paused program only runs again to log what is stepped to
+                if self.waiting_for_log() {
+                    self.resume_program();
+                }
+                self.step_thread = Some(thread_id);
                 self.increment_log_index()?;
+                self.stop(StoppedEventReason::Step)?;
             }
             Command::ReverseContinue(_) => {
                 dap_server::write(Sendable::Response(request.ack()?));
-                self.reverse = true;
+                self.step_thread = None;
+                self.set_reverse(true);
                 self.running = true;
             }
-            Command::StepBack(_) => {
+            Command::StepBack(StepBackArguments { thread_id, .. }) => {
                 dap_server::write(Sendable::Response(request.ack()?));
-                self.stop(StoppedEventReason::Step)?;
-                self.reverse = true;
+                self.set_reverse(true);
+                self.step_thread = Some(thread_id);
                 self.increment_log_index()?;
+                self.stop(StoppedEventReason::Step)?;
             }
             Command::Pause(_) => {
                 self.running = false;
@@ -336,7 +1603,9 @@ impl RunningState {
                 self.stop(StoppedEventReason::Pause)?;
             }
             Command::Continue(_) => {
-                self.reverse = false;
+                self.resume_program();
+                self.step_thread = None;
+                self.set_reverse(false);
                 self.running = true;
                 dap_server::write(Sendable::Response(request.success(ResponseBody::Continue(
                     ContinueResponse {
@@ -345,115 +1614,209 @@ impl RunningState {
                 ))));
             }
 
-            Command::StackTrace(_) => {
-                let log_match = self.get_log_match()?;
-                let search_options = self.get_log_line_search()?;
-                let name = log_match
-                    .file
-                    .split('/')
-                    .last()
-                    .context(format!("Unable to parse path {}", log_match.file))?
-                    .to_string();
-                let source = Source {
-                    path: Some(log_match.file.clone()),
-                    name: Some(name.clone()),
-                    ..Default::default()
+            Command::StackTrace(ref args) => {
+                let resp = match self.stack_trace(args.thread_id) {
+                    Ok(stack_frames) => request.success(ResponseBody::StackTrace(
+                        StackTraceResponse {
+                            total_frames: Some(stack_frames.len() as i64),
+                            stack_frames,
+                        },
+                    )),
+                    Err(e) => request.error(&e.to_string()),
                 };
-                let frame_name = match search_options.func {
-                    Some(func) => format!("{}:{}", func, log_match.line),
-                    None => format!("{}:{}", name, log_match.line),
+                dap_server::write(Sendable::Response(resp));
+            }
+            Command::ExceptionInfo(ref args) => {
+                let index = match self.get_log_line_search() {
+                    Ok(search) if self.thread_id(&search) == args.thread_id => Some(self.log_index),
+                    _ => self.latest_entry_of(args.thread_id).map(|(index, _)| index),
                 };
-
-                let frame = StackFrame {
-                    id: 0,
-                    name: frame_name,
-                    source: Some(source),
-                    line: log_match.line as i64,
-                    ..Default::default()
+                let resp = match index.and_then(|i| self.exception_trace(i)) {
+                    Some(trace) => request.success(ResponseBody::ExceptionInfo(ExceptionInfoResponse {
+                        exception_id: trace.exception.clone(),
+                        description: Some(trace.message.clone()),
+                        break_mode: ExceptionBreakMode::Always,
+                        details: Some(exception_details(&trace)),
+                    })),
+                    None => request.error("No panic or exception was logged here"),
                 };
-
-                let parent_frame = StackFrame {
-                    id: 1,
-                    name: self
-                        .settings
-                        .log_file_name
-                        .split('/')
-                        .last()
-                        .context(format!(
-                            "Unable to parse path {}",
-                            self.settings.log_file_name
-                        ))?
-                        .to_string(),
-                    source: Some(self.get_log_file_source()),
-                    line: (self.log_index + 1) as i64,
-                    ..Default::default()
+                dap_server::write(Sendable::Response(resp));
+            }
+            Command::Evaluate(ref args) => {
+                let index = self.log_index;
+                let resp = match self.evaluate(&args.expression) {
+                    Ok(result) => request.success(ResponseBody::Evaluate(EvaluateResponse {
+                        result,
+                        ..Default::default()
+                    })),
+                    Err(e) => request.error(&e.to_string()),
                 };
-
+                dap_server::write(Sendable::Response(resp));
+                // `goto` moved us to another entry, which the editor has to show
+                if self.log_index != index {
+                    self.stop(StoppedEventReason::Goto)?;
+                }
+            }
+            // Lines of the log can be jumped to, as can the entries logged at a line of the sources
+            Command::GotoTargets(ref args) => {
+                let line = args.line as usize;
+                let log_path = self.log.path().unwrap_or(&self.settings.log_file_name);
+                let target = match args.source.path.as_deref() {
+                    Some(path) if path == log_path => Some(line),
+                    Some(path) => self.next_entry_at(path, line).map(|i| i + 1),
+                    None => None,
+                };
+                let targets = target
+                    .filter(|t| *t >= 1 && *t <= self.log.len())
+                    .map(|t| GotoTarget {
+                        id: t as i64,
+                        label: format!("Line {} of the log", t),
+                        line: args.line,
+                        ..Default::default()
+                    })
+                    .into_iter()
+                    .collect();
                 dap_server::write(Sendable::Response(request.success(
-                    ResponseBody::StackTrace(StackTraceResponse {
-                        stack_frames: vec![frame, parent_frame],
-                        total_frames: Some(1),
-                    }),
+                    ResponseBody::GotoTargets(GotoTargetsResponse { targets }),
                 )));
             }
+            Command::Goto(ref args) => {
+                let resp = match self.goto(args.target_id as usize) {
+                    Ok(()) => request.ack()?,
+                    Err(e) => request.error(&e.to_string()),
+                };
+                let moved = resp.success;
+                dap_server::write(Sendable::Response(resp));
+                if moved {
+                    self.stop(StoppedEventReason::Goto)?;
+                }
+            }
             Command::Threads => {
-                dap_server::write(Sendable::Response(request.success(ResponseBody::Threads(
-                    ThreadsResponse {
-                        threads: vec![Thread {
-                            id: 0,
-                            name: "main".to_string(),
-                        }],
-                    },
-                ))));
+                self.scan_threads();
+                let mut threads = Vec::new();
+                if self.has_main_thread || self.threads.is_empty() {
+                    threads.push(Thread {
+                        id: 0,
+                        name: "main".to_string(),
+                    });
+                }
+                threads.extend(self.threads.iter().enumerate().map(|(i, name)| Thread {
+                    id: i as i64 + 1,
+                    name: name.clone(),
+                }));
+                dap_server::write(Sendable::Response(
+                    request.success(ResponseBody::Threads(ThreadsResponse { threads })),
+                ));
             }
             Command::Scopes(ref args) => {
-                if args.frame_id == 0 {
-                    let log_match = self.get_log_match()?;
-
-                    let scope = Scope {
-                        name: "Locals".to_string(),
-                        presentation_hint: Some(ScopePresentationhint::Locals),
-                        variables_reference: 133,
-                        named_variables: None,
-                        indexed_variables: None,
-                        line: Some(log_match.line as i64),
-                        ..Default::default()
-                    };
-                    dap_server::write(Sendable::Response(request.success(ResponseBody::Scopes(
-                        ScopesResponse {
-                            scopes: vec![scope],
-                        },
-                    ))));
-                } else {
-                    dap_server::write(Sendable::Response(
-                        request
-                            .success(ResponseBody::Scopes(ScopesResponse { scopes: Vec::new() })),
-                    ));
-                }
+                let scope = match self.frame_scopes.get(&args.frame_id) {
+                    Some(scope) => Some(scope.clone()),
+                    // The top frame is the current entry, even if its stack trace was not asked for
+                    None if args.frame_id == 0 => Some(FrameScope::Entry(self.log_index)),
+                    None => None,
+                };
+                let scopes = match scope {
+                    Some(FrameScope::Entry(index)) => {
+                        let variables = self.entry_variables(index);
+                        let line = self.match_at(index).map(|m| m.line as i64);
+                        vec![Scope {
+                            name: "Locals".to_string(),
+                            presentation_hint: Some(ScopePresentationhint::Locals),
+                            named_variables: Some(variables.len() as i64),
+                            variables_reference: self.allocate_variables(variables),
+                            line,
+                            ..Default::default()
+                        }]
+                    }
+                    Some(FrameScope::Span(fields)) => {
+                        let variables: Vec<_> = fields
+                            .into_iter()
+                            .map(|(name, value)| variable(&name, value))
+                            .collect();
+                        vec![Scope {
+                            name: "Span".to_string(),
+                            presentation_hint: Some(ScopePresentationhint::Locals),
+                            named_variables: Some(variables.len() as i64),
+                            variables_reference: self.allocate_variables(variables),
+                            ..Default::default()
+                        }]
+                    }
+                    None => Vec::new(),
+                };
+                dap_server::write(Sendable::Response(
+                    request.success(ResponseBody::Scopes(ScopesResponse { scopes })),
+                ));
             }
-            Command::Variables(_) => {
-                let var = Variable {
-                    name: "Variable name".to_string(),
-                    value: self.get_log_line_search()?.message,
-                    ..Default::default()
+            Command::Variables(ref args) => {
+                let reference = args.variables_reference;
+                let variables = match (reference as usize)
+                    .checked_sub(1)
+                    .and_then(|i| self.variables.get(i))
+                {
+                    Some(VariableScope::Variables(variables)) => Some(variables.clone()),
+                    Some(VariableScope::History(name, index)) => {
+                        Some(self.history_variables(name, *index))
+                    }
+                    None => None,
                 };
-                dap_server::write(Sendable::Response(request.success(
-                    ResponseBody::Variables(VariablesResponse {
-                        variables: vec![var],
-                    }),
-                )));
+                let resp = match variables {
+                    Some(variables) => {
+                        request.success(ResponseBody::Variables(VariablesResponse { variables }))
+                    }
+                    None => request.error(&format!("Unknown variables reference {}", reference)),
+                };
+                dap_server::write(Sendable::Response(resp));
             }
             Command::SetBreakpoints(ref args) => {
                 self.clear_breakpoints_for_source(&args.source);
-                if let Some(new_breakpoints) = &args.breakpoints {
-                    let breakpoints = new_breakpoints
-                        .iter()
-                        .filter_map(|b| RetreadBreakpoint::new(&args.source, b));
-
-                    self.breakpoints.extend(breakpoints);
+                // Breakpoints that cannot be set, like those with a bad condition, are sent back unverified
+                let mut breakpoints = Vec::new();
+                for breakpoint in args.breakpoints.iter().flatten() {
+                    let message = match RetreadBreakpoint::new(&args.source, breakpoint) {
+                        Ok(b) => {
+                            self.breakpoints.push(b);
+                            None
+                        }
+                        Err(e) => Some(e.to_string()),
+                    };
+                    breakpoints.push(Breakpoint {
+                        verified: message.is_none(),
+                        message,
+                        source: Some(args.source.clone()),
+                        line: Some(breakpoint.line),
+                        ..Default::default()
+                    });
                 }
+                dap_server::write(Sendable::Response(request.success(
+                    ResponseBody::SetBreakpoints(SetBreakpointsResponse { breakpoints }),
+                )));
+            }
+            Command::SetFunctionBreakpoints(ref args) => {
+                self.breakpoints
+                    .retain(|b| !matches!(b.location, BreakpointLocation::Function(_)));
+                let mut breakpoints = Vec::new();
+                for breakpoint in &args.breakpoints {
+                    let message = match RetreadBreakpoint::function(breakpoint) {
+                        Ok(b) => {
+                            self.breakpoints.push(b);
+                            None
+                        }
+                        Err(e) => Some(e.to_string()),
+                    };
+                    breakpoints.push(Breakpoint {
+                        verified: message.is_none(),
+                        message,
+                        ..Default::default()
+                    });
+                }
+                dap_server::write(Sendable::Response(request.success(
+                    ResponseBody::SetFunctionBreakpoints(SetFunctionBreakpointsResponse {
+                        breakpoints,
+                    }),
+                )));
             }
             Command::Disconnect(_) => {
+                self.log.terminate();
                 dap_server::write(Sendable::Response(request.ack()?));
                 return Ok(Some(AppState::Exit));
             }
@@ -471,7 +1834,7 @@ pub struct App {
 impl App {
     pub fn init() -> Self {
         App {
-            state: AppState::Uninitialized(UninitializedState::new()),
+            state: AppState::Uninitialized(Box::default()),
         }
     }
 
@@ -0,0 +1,544 @@
+use std::cmp::Ordering;
+
+use anyhow::*;
+use regex::Regex;
+
+use crate::log_level::LogLevel;
+
+/*
+The condition of a breakpoint, over the variables of an entry, e.g.
+`level == "ERROR" && conn_id == 17` or `message =~ /timeout/i`.
+Values are compared as numbers when both sides are numbers, as log levels
+when one side is the `level`, and as text otherwise. Comparisons with a
+variable the entry does not have never hold.
+*/
+#[derive(Clone, Debug)]
+pub enum Condition {
+    Or(Box<Condition>, Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+    Compare(Operand, Operator, Operand),
+    /// A variable on its own holds if the entry has it, and it is not empty, `false` or `0`
+    Truthy(Operand),
+}
+
+#[derive(Clone, Debug)]
+pub enum Operand {
+    Variable(String),
+    Text(String),
+    Pattern(Regex),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Matches,
+    NotMatches,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Variable(String),
+    Text(String),
+    Pattern(String),
+    Operator(Operator),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let condition = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            Some(token) => Err(anyhow!(
+                "Unexpected {} in condition [{}]",
+                describe(token),
+                text
+            )),
+            None => Ok(condition),
+        }
+    }
+
+    /// Whether the condition holds, given the value of each variable of the entry
+    pub fn evaluate(&self, lookup: &dyn Fn(&str) -> Option<String>) -> bool {
+        match self {
+            Condition::Or(a, b) => a.evaluate(lookup) || b.evaluate(lookup),
+            Condition::And(a, b) => a.evaluate(lookup) && b.evaluate(lookup),
+            Condition::Not(c) => !c.evaluate(lookup),
+            Condition::Truthy(operand) => operand
+                .value(lookup)
+                .is_some_and(|v| !["", "false", "0"].contains(&v.trim())),
+            Condition::Compare(left, operator, right) => {
+                let Some(value) = left.value(lookup) else {
+                    return false;
+                };
+                match (operator, right) {
+                    (Operator::Matches, Operand::Pattern(pattern)) => pattern.is_match(&value),
+                    (Operator::NotMatches, Operand::Pattern(pattern)) => !pattern.is_match(&value),
+                    (operator, right) => {
+                        let Some(other) = right.value(lookup) else {
+                            return false;
+                        };
+                        let is_level = [left, right]
+                            .iter()
+                            .any(|o| matches!(o, Operand::Variable(v) if v == "level"));
+                        let ordering = compare(&value, &other, is_level);
+                        match operator {
+                            Operator::Equal => ordering == Ordering::Equal,
+                            Operator::NotEqual => ordering != Ordering::Equal,
+                            Operator::Less => ordering == Ordering::Less,
+                            Operator::LessOrEqual => ordering != Ordering::Greater,
+                            Operator::Greater => ordering == Ordering::Greater,
+                            Operator::GreaterOrEqual => ordering != Ordering::Less,
+                            Operator::Matches | Operator::NotMatches => false,
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Operator {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterOrEqual => ">=",
+            Operator::Matches => "=~",
+            Operator::NotMatches => "!~",
+        }
+    }
+}
+
+impl Operand {
+    fn value(&self, lookup: &dyn Fn(&str) -> Option<String>) -> Option<String> {
+        match self {
+            Operand::Variable(name) => lookup(name),
+            Operand::Text(text) => Some(text.clone()),
+            Operand::Pattern(pattern) => Some(pattern.as_str().to_string()),
+        }
+    }
+}
+
+fn compare(a: &str, b: &str, is_level: bool) -> Ordering {
+    let (a, b) = (a.trim(), b.trim());
+    if is_level {
+        if let (Some(a), Some(b)) = (LogLevel::parse(a), LogLevel::parse(b)) {
+            return a.cmp(&b);
+        }
+    }
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Result::Ok(a), Result::Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => a.cmp(b),
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Variable(name) => format!("[{}]", name),
+        Token::Text(text) => format!("\"{}\"", text),
+        Token::Pattern(pattern) => format!("/{}/", pattern),
+        Token::Operator(operator) => operator.symbol().to_string(),
+        Token::And => "&&".to_string(),
+        Token::Or => "||".to_string(),
+        Token::Not => "!".to_string(),
+        Token::Open => "(".to_string(),
+        Token::Close => ")".to_string(),
+    }
+}
+
+/*
+Splits the condition into tokens. Variables are named like the variables
+view names them, e.g. `self.state` or `payload.items[0]`, and numbers are
+kept as text, to be compared as numbers later.
+*/
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            '=' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::Equal),
+            '=' if chars.next_if_eq(&'~').is_some() => Token::Operator(Operator::Matches),
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::NotEqual),
+            '!' if chars.next_if_eq(&'~').is_some() => Token::Operator(Operator::NotMatches),
+            '!' => Token::Not,
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::LessOrEqual),
+            '<' => Token::Operator(Operator::Less),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::GreaterOrEqual),
+            '>' => Token::Operator(Operator::Greater),
+            '"' | '\'' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => text.extend(chars.next()),
+                        Some(q) if q == c => break,
+                        Some(c) => text.push(c),
+                        None => return Err(anyhow!("Unterminated string in condition")),
+                    }
+                }
+                Token::Text(text)
+            }
+            '/' => {
+                let mut pattern = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') if chars.peek() == Some(&'/') => pattern.extend(chars.next()),
+                        Some('\\') => {
+                            pattern.push('\\');
+                            pattern.extend(chars.next());
+                        }
+                        Some('/') => break,
+                        Some(c) => pattern.push(c),
+                        None => return Err(anyhow!("Unterminated regex in condition")),
+                    }
+                }
+                if chars.next_if_eq(&'i').is_some() {
+                    pattern.insert_str(0, "(?i)");
+                }
+                Token::Pattern(pattern)
+            }
+            c if c.is_alphanumeric() || "_$-.".contains(c) => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || "_$.[]:".contains(*c))
+                {
+                    word.push(c);
+                }
+                match word.parse::<f64>() {
+                    Result::Ok(_) => Token::Text(word),
+                    Err(_) => match word.as_str() {
+                        "true" | "false" => Token::Text(word),
+                        _ => Token::Variable(word),
+                    },
+                }
+            }
+            c => return Err(anyhow!("Unexpected [{}] in condition", c)),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// A recursive descent parser, where `||` binds loosest and `!` tightest
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next_if_eq(&mut self, token: &Token) -> bool {
+        let matches = self.tokens.get(self.pos) == Some(token);
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    fn or(&mut self) -> Result<Condition> {
+        let mut condition = self.and()?;
+        while self.next_if_eq(&Token::Or) {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        Ok(condition)
+    }
+
+    fn and(&mut self) -> Result<Condition> {
+        let mut condition = self.not()?;
+        while self.next_if_eq(&Token::And) {
+            condition = Condition::And(Box::new(condition), Box::new(self.not()?));
+        }
+        Ok(condition)
+    }
+
+    fn not(&mut self) -> Result<Condition> {
+        if self.next_if_eq(&Token::Not) {
+            return Ok(Condition::Not(Box::new(self.not()?)));
+        }
+        if self.next_if_eq(&Token::Open) {
+            let condition = self.or()?;
+            if !self.next_if_eq(&Token::Close) {
+                return Err(anyhow!("Missing ) in condition"));
+            }
+            return Ok(condition);
+        }
+
+        let left = self.operand()?;
+        let Some(Token::Operator(operator)) = self.tokens.get(self.pos).cloned() else {
+            return Ok(Condition::Truthy(left));
+        };
+        self.pos += 1;
+        let right = match (operator, self.operand()?) {
+            (Operator::Matches | Operator::NotMatches, Operand::Text(pattern)) => {
+                Operand::Pattern(Regex::new(&pattern)?)
+            }
+            (Operator::Matches | Operator::NotMatches, Operand::Variable(name)) => {
+                return Err(anyhow!(
+                    "Expected a regex like /{}/ after {}",
+                    name,
+                    operator.symbol()
+                ))
+            }
+            (_, right) => right,
+        };
+        Ok(Condition::Compare(left, operator, right))
+    }
+
+    fn operand(&mut self) -> Result<Operand> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Variable(name)) => Ok(Operand::Variable(name)),
+            Some(Token::Text(text)) => Ok(Operand::Text(text)),
+            Some(Token::Pattern(pattern)) => Ok(Operand::Pattern(
+                Regex::new(&pattern).context(format!("Invalid regex /{}/", pattern))?,
+            )),
+            Some(token) => Err(anyhow!("Expected a value, found {}", describe(&token))),
+            None => Err(anyhow!("Expected a value at the end of the condition")),
+        }
+    }
+}
+
+/// When a breakpoint stops, by how many times it was hit, e.g. `== 100`, `>= 5` or `% 10 == 0`.
+#[derive(Clone, Copy, Debug)]
+pub enum HitCondition {
+    Compare(Operator, u64),
+    /// Every n-th hit, offset by the remainder
+    Every(u64, u64),
+}
+
+impl HitCondition {
+    /// A number on its own, like `100`, stops at that hit only
+    pub fn parse(text: &str) -> Result<Self> {
+        let number = |n: &str| {
+            n.trim().parse::<u64>().context(format!(
+                "Invalid hit count [{}], expected a number",
+                n.trim()
+            ))
+        };
+        let text = text.trim();
+        if let Some(every) = text.strip_prefix('%') {
+            let (n, remainder) = match every.split_once("==") {
+                Some((n, remainder)) => (number(n)?, number(remainder)?),
+                None => (number(every)?, 0),
+            };
+            if n == 0 {
+                return Err(anyhow!(
+                    "Invalid hit condition [{}], cannot count by 0",
+                    text
+                ));
+            }
+            return Ok(HitCondition::Every(n, remainder));
+        }
+        let operators = [
+            (">=", Operator::GreaterOrEqual),
+            ("<=", Operator::LessOrEqual),
+            ("==", Operator::Equal),
+            ("!=", Operator::NotEqual),
+            (">", Operator::Greater),
+            ("<", Operator::Less),
+            ("=", Operator::Equal),
+        ];
+        for (symbol, operator) in operators {
+            if let Some(n) = text.strip_prefix(symbol) {
+                return Ok(HitCondition::Compare(operator, number(n)?));
+            }
+        }
+        Ok(HitCondition::Compare(Operator::Equal, number(text)?))
+    }
+
+    pub fn matches(&self, hits: u64) -> bool {
+        match *self {
+            HitCondition::Compare(operator, n) => match operator {
+                Operator::Equal => hits == n,
+                Operator::NotEqual => hits != n,
+                Operator::Less => hits < n,
+                Operator::LessOrEqual => hits <= n,
+                Operator::Greater => hits > n,
+                Operator::GreaterOrEqual => hits >= n,
+                Operator::Matches | Operator::NotMatches => false,
+            },
+            HitCondition::Every(n, remainder) => hits % n == remainder,
+        }
+    }
+}
+
+/// The message of a logpoint, with each `{name}` replaced by the value of the variable
+pub fn interpolate(template: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        // `{{` and `}}` are literal braces
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            output.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+        match rest.find('}').filter(|_| rest.starts_with('{')) {
+            Some(end) => {
+                let name = rest[1..end].trim();
+                match lookup(name) {
+                    Some(value) => output.push_str(&value),
+                    // Unknown variables are left as they are, to show what was missing
+                    None => output.push_str(&rest[..=end]),
+                }
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn holds(condition: &str, values: &[(&str, &str)]) -> bool {
+        let lookup = |name: &str| {
+            values
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.to_string())
+        };
+        Condition::parse(condition).unwrap().evaluate(&lookup)
+    }
+
+    fn error(condition: &str) -> String {
+        Condition::parse(condition).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let values = [("a", "1"), ("b", "0"), ("c", "0")];
+        assert!(holds("a == 1 || b == 1 && c == 1", &values));
+        assert!(!holds("(a == 1 || b == 1) && c == 1", &values));
+        assert!(!holds("!a == 1 || b == 1", &values));
+        assert!(holds("!(a == 2) && !b", &values));
+    }
+
+    #[test]
+    fn regexes_match_and_ignore_case() {
+        let values = [("message", "Read TIMEOUT after 3s")];
+        assert!(holds("message =~ /timeout/i", &values));
+        assert!(!holds("message =~ /timeout/", &values));
+        assert!(holds("message !~ /^write/", &values));
+        assert!(holds(r"message =~ /\d+s$/", &values));
+        assert!(holds("message =~ 'after'", &values));
+    }
+
+    #[test]
+    fn levels_are_ordered_by_severity() {
+        assert!(holds("level >= \"WARN\"", &[("level", "E")]));
+        assert!(holds("level >= 'warning'", &[("level", "ERROR")]));
+        assert!(!holds("level >= \"WARN\"", &[("level", "info")]));
+        assert!(holds("level < \"info\"", &[("level", "DEBUG")]));
+        // Outside of the level, the same text is compared as text
+        assert!(!holds("kind >= \"WARN\"", &[("kind", "E")]));
+    }
+
+    #[test]
+    fn numbers_are_compared_as_numbers() {
+        let values = [("conn_id", "17"), ("ms", "9.5"), ("name", "b")];
+        assert!(holds("conn_id == 17", &values));
+        assert!(holds("conn_id == 17.0", &values));
+        assert!(holds("ms < 10", &values));
+        assert!(holds("conn_id > 9", &values));
+        assert!(holds("name > \"a\"", &values));
+        assert!(holds("-1 < ms", &values));
+    }
+
+    #[test]
+    fn missing_variables_never_hold() {
+        let values = [("state", "Idle")];
+        assert!(!holds("missing == 1", &values));
+        assert!(!holds("missing != 1", &values));
+        assert!(!holds("missing =~ /x/", &values));
+        assert!(!holds("missing", &values));
+        assert!(holds("!missing", &values));
+        assert!(!holds("state == missing", &values));
+    }
+
+    #[test]
+    fn variables_on_their_own_are_truthy() {
+        assert!(holds("retry", &[("retry", "true")]));
+        assert!(!holds("retry", &[("retry", "false")]));
+        assert!(!holds("retry", &[("retry", "0")]));
+        assert!(!holds("retry", &[("retry", " ")]));
+    }
+
+    #[test]
+    fn variables_are_named_like_the_variables_view() {
+        let values = [("payload.items[0]", "3"), ("self.state", "Idle")];
+        assert!(holds("payload.items[0] == 3", &values));
+        assert!(holds("self.state == 'Idle'", &values));
+    }
+
+    #[test]
+    fn invalid_conditions_are_explained() {
+        assert_eq!(error("(a == 1"), "Missing ) in condition");
+        assert_eq!(error("a == 1)"), "Unexpected ) in condition [a == 1)]");
+        assert_eq!(error("a == \"x"), "Unterminated string in condition");
+        assert_eq!(error("a =~ /x"), "Unterminated regex in condition");
+        assert_eq!(error("a == &&"), "Expected a value, found &&");
+        assert_eq!(error("a =="), "Expected a value at the end of the condition");
+        assert_eq!(error("a =~ /(/"), "Invalid regex /(/");
+        assert_eq!(error("a =~ b"), "Expected a regex like /b/ after =~");
+        assert_eq!(error("a # b"), "Unexpected [#] in condition");
+        assert_eq!(error("a b"), "Unexpected [b] in condition [a b]");
+    }
+
+    #[test]
+    fn hit_conditions() {
+        let matching = |condition: &str| -> Vec<u64> {
+            let condition = HitCondition::parse(condition).unwrap();
+            (1..=10).filter(|h| condition.matches(*h)).collect()
+        };
+        assert_eq!(matching("3"), vec![3]);
+        assert_eq!(matching("== 3"), vec![3]);
+        assert_eq!(matching("=3"), vec![3]);
+        assert_eq!(matching(">= 8"), vec![8, 9, 10]);
+        assert_eq!(matching("< 3"), vec![1, 2]);
+        assert_eq!(matching("% 4"), vec![4, 8]);
+        assert_eq!(matching("% 4 == 1"), vec![1, 5, 9]);
+        assert_eq!(
+            HitCondition::parse("% 0").unwrap_err().to_string(),
+            "Invalid hit condition [% 0], cannot count by 0"
+        );
+        assert_eq!(
+            HitCondition::parse(">= many").unwrap_err().to_string(),
+            "Invalid hit count [many], expected a number"
+        );
+    }
+
+    #[test]
+    fn interpolate_replaces_known_variables() {
+        let lookup = |name: &str| (name == "id").then(|| "17".to_string());
+        assert_eq!(interpolate("conn {id} is {state}", &lookup), "conn 17 is {state}");
+        assert_eq!(interpolate("{{id}} = { id }", &lookup), "{id} = 17");
+        assert_eq!(interpolate("unclosed {id", &lookup), "unclosed {id");
+    }
+}
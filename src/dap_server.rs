@@ -1,14 +1,14 @@
 use crossbeam::channel::unbounded;
 use crossbeam::channel::Receiver;
+#[cfg(feature = "test-server")]
 use crossbeam::channel::Sender;
 use crossbeam::channel::TryRecvError;
-use std::io::{stdin, stdout, BufReader, BufWriter, Stdin, Stdout};
+use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Stdin, Stdout};
 use std::sync::Mutex;
 use std::thread::spawn;
 
 use dap::base_message::*;
 use dap::requests::*;
-use dap::responses::*;
 use dap::server::*;
 
 use lazy_static::lazy_static;
@@ -37,9 +37,9 @@ impl DapServer {
     pub fn new() -> Self {
         let (tx, rx) = unbounded::<Request>();
         spawn(move || {
-            let mut server = Server::new(BufReader::new(stdin()), BufWriter::new(stdout()));
+            let mut input = BufReader::new(stdin());
             loop {
-                let req = match server.poll_request() {
+                let req = match read_request(&mut input) {
                     Ok(Some(req)) => req,
                     Ok(None) => continue,
                     Err(_) => return,
@@ -70,6 +70,56 @@ impl DapServer {
     }
 }
 
+/*
+Reads the next request sent by the client. The dap crate expects the
+fields of a `SourceBreakpoint` in snake_case, while clients send them in
+camelCase, so they are renamed before a setBreakpoints request is parsed.
+*/
+#[cfg(not(feature = "test-server"))]
+fn read_request(input: &mut impl BufRead) -> std::io::Result<Option<Request>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        match header.trim_end().split_once(':') {
+            Some(("Content-Length", length)) => content_length = length.trim().parse().ok(),
+            Some(_) => {}
+            None if header.trim_end().is_empty() => break,
+            None => {
+                return Err(std::io::Error::other(format!(
+                    "Invalid header [{}]",
+                    header
+                )))
+            }
+        }
+    }
+    let content_length = content_length.ok_or(std::io::Error::other("Missing Content-Length"))?;
+    let mut content = vec![0; content_length];
+    input.read_exact(&mut content)?;
+
+    let mut request: serde_json::Value = serde_json::from_slice(&content)?;
+    // Only setBreakpoints, as the function breakpoints of setFunctionBreakpoints are parsed in camelCase
+    let breakpoints = match request.get("command").and_then(|c| c.as_str()) {
+        Some("setBreakpoints") => request.pointer_mut("/arguments/breakpoints"),
+        _ => None,
+    };
+    if let Some(serde_json::Value::Array(breakpoints)) = breakpoints {
+        for breakpoint in breakpoints.iter_mut().filter_map(|b| b.as_object_mut()) {
+            for (camel, snake) in [
+                ("hitCondition", "hit_condition"),
+                ("logMessage", "log_message"),
+            ] {
+                if let Some(value) = breakpoint.remove(camel) {
+                    breakpoint.insert(snake.to_string(), value);
+                }
+            }
+        }
+    }
+    Ok(Some(serde_json::from_value(request)?))
+}
+
 #[cfg(not(feature = "test-server"))]
 pub fn read() -> Option<Request> {
     match SERVER.incoming.try_recv() {
@@ -111,3 +161,57 @@ pub fn read_server() -> Option<Sendable> {
 pub fn write_server(message: Request) {
     SERVER.to_client.send(message).unwrap();
 }
+
+#[cfg(all(test, not(feature = "test-server")))]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn parse(body: &str) -> Request {
+        let message = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        read_request(&mut Cursor::new(message)).unwrap().unwrap()
+    }
+
+    #[test]
+    fn source_breakpoints_keep_their_camel_case_fields() {
+        let request = parse(
+            r#"{"seq": 3, "type": "request", "command": "setBreakpoints", "arguments": {
+                "source": {"path": "src/app.c"},
+                "breakpoints": [{"line": 4, "condition": "n > 1", "hitCondition": ">= 2", "logMessage": "n is {n}"}]
+            }}"#,
+        );
+        let Command::SetBreakpoints(args) = request.command else {
+            panic!("Expected setBreakpoints, got {:?}", request.command);
+        };
+        let breakpoint = &args.breakpoints.unwrap()[0];
+        assert_eq!(breakpoint.line, 4);
+        assert_eq!(breakpoint.condition.as_deref(), Some("n > 1"));
+        assert_eq!(breakpoint.hit_condition.as_deref(), Some(">= 2"));
+        assert_eq!(breakpoint.log_message.as_deref(), Some("n is {n}"));
+    }
+
+    #[test]
+    fn function_breakpoints_keep_their_hit_condition() {
+        let request = parse(
+            r#"{"seq": 4, "type": "request", "command": "setFunctionBreakpoints", "arguments": {
+                "breakpoints": [{"name": "handle_*", "condition": "level >= warn", "hitCondition": "% 3"}]
+            }}"#,
+        );
+        let Command::SetFunctionBreakpoints(args) = request.command else {
+            panic!("Expected setFunctionBreakpoints, got {:?}", request.command);
+        };
+        let breakpoint = &args.breakpoints[0];
+        assert_eq!(breakpoint.name, "handle_*");
+        assert_eq!(breakpoint.condition.as_deref(), Some("level >= warn"));
+        assert_eq!(breakpoint.hit_condition.as_deref(), Some("% 3"));
+    }
+
+    #[test]
+    fn invalid_headers_are_errors() {
+        let mut input = Cursor::new("Content-Length 3\r\n\r\n{}");
+        assert!(read_request(&mut input).is_err());
+        let mut input = Cursor::new("Content-Type: json\r\n\r\n{}");
+        assert!(read_request(&mut input).is_err());
+        assert!(read_request(&mut Cursor::new("")).unwrap().is_none());
+    }
+}
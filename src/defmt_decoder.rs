@@ -0,0 +1,782 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::*;
+use defmt_parser::{
+    get_max_bitfield_range, DisplayHint, Fragment, Parameter, ParserMode, TimePrecision, Type,
+};
+use gimli::{AttributeValue, EndianSlice, Operation, RunTimeEndian};
+use object::{Object, ObjectSection, ObjectSymbol};
+use serde_json::{Map, Value};
+
+/// Frames larger than this are garbage, and skipped instead of waited for
+const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// An interned string of the firmware, and where it was logged.
+#[derive(Debug)]
+struct DefmtString {
+    tag: String,
+    format: String,
+    location: Option<(String, u64)>,
+}
+
+/*
+The format strings of a firmware's defmt logs, read from its ELF file.
+defmt interns every string as a symbol in the `.defmt` section, named with a
+JSON description of the string, and the symbol's address is the index sent
+over the wire. The debug info tells us where each of those symbols is
+declared, which is the file and line of the log statement.
+https://defmt.ferrous-systems.com/
+*/
+#[derive(Debug)]
+pub struct DefmtTable {
+    strings: HashMap<u16, DefmtString>,
+    /// The format of the timestamp that precedes the arguments of each entry
+    timestamp: Option<String>,
+    /// Frames are rzCOBS encoded and separated by 0, unless the firmware uses the raw encoding
+    rzcobs: bool,
+}
+
+impl DefmtTable {
+    pub fn load(path: &str) -> Result<Self> {
+        let bytes = std::fs::read(path).context(format!("Unable to read {}", path))?;
+        let elf = object::File::parse(&*bytes).context(format!("{} is not an ELF file", path))?;
+
+        let mut strings = HashMap::new();
+        let mut timestamp = None;
+        let mut rzcobs = true;
+        for symbol in elf.symbols() {
+            let Result::Ok(name) = symbol.name() else {
+                continue;
+            };
+            if let Some(encoding) = name.strip_prefix("_defmt_encoding_ = ") {
+                rzcobs = encoding == "rzcobs";
+                continue;
+            }
+            let in_defmt_section = symbol
+                .section_index()
+                .and_then(|i| elf.section_by_index(i).ok())
+                .is_some_and(|s| s.name().is_ok_and(|n| n.starts_with(".defmt")));
+            if !in_defmt_section {
+                continue;
+            }
+            let Result::Ok(Value::Object(description)) = serde_json::from_str::<Value>(name) else {
+                continue;
+            };
+            let get = |key: &str| description.get(key).and_then(|v| v.as_str());
+            let (Some(tag), Some(format)) = (get("tag"), get("data")) else {
+                continue;
+            };
+            match tag {
+                "defmt_timestamp" => timestamp = Some(format.to_string()),
+                _ => {
+                    strings.insert(
+                        symbol.address() as u16,
+                        DefmtString {
+                            tag: tag.to_string(),
+                            format: format.to_string(),
+                            location: None,
+                        },
+                    );
+                }
+            }
+        }
+        if strings.is_empty() {
+            return Err(anyhow!(
+                "{} has no defmt symbols, was it built with defmt?",
+                path
+            ));
+        }
+
+        let addresses: HashSet<u64> = strings.keys().map(|i| *i as u64).collect();
+        match find_locations(&elf, &addresses) {
+            Result::Ok(locations) => {
+                for (address, location) in locations {
+                    if let Some(string) = strings.get_mut(&(address as u16)) {
+                        string.location = Some(location);
+                    }
+                }
+            }
+            Err(e) => warn!("Unable to read the debug info of {}: {}", path, e),
+        }
+        info!("Loaded {} defmt strings from {}", strings.len(), path);
+
+        Ok(DefmtTable {
+            strings,
+            timestamp,
+            rzcobs,
+        })
+    }
+
+    pub fn decoder(self: &Arc<Self>) -> DefmtDecoder {
+        DefmtDecoder {
+            table: self.clone(),
+            pending: Vec::new(),
+        }
+    }
+
+    /*
+    A frame is the index of the log statement's format string, the
+    arguments of the timestamp, and then the arguments of the message.
+    Returns the entry as JSON, and how many bytes it took, or None if the
+    frame is cut short.
+    */
+    fn decode_frame(&self, bytes: &[u8]) -> Option<(String, usize)> {
+        let mut frame = Frame { bytes, pos: 0 };
+        let index = frame.u16()?;
+        let mut entry = Map::new();
+        let Some(string) = self.strings.get(&index) else {
+            entry.insert(
+                "message".to_string(),
+                Value::String(format!("Unknown defmt string index {}", index)),
+            );
+            return Some((Value::Object(entry).to_string(), frame.pos));
+        };
+
+        if let Some(timestamp) = &self.timestamp {
+            let timestamp = self.format(timestamp, &mut frame)?;
+            entry.insert("timestamp".to_string(), Value::String(timestamp));
+        }
+        let message = self.format(&string.format, &mut frame)?;
+        entry.insert("message".to_string(), Value::String(message));
+        if let Some(level) = string.tag.strip_prefix("defmt_") {
+            if level != "println" {
+                entry.insert("level".to_string(), Value::String(level.to_uppercase()));
+            }
+        }
+        if let Some((file, line)) = &string.location {
+            entry.insert("file".to_string(), Value::String(file.clone()));
+            entry.insert("line".to_string(), Value::from(*line));
+        }
+        entry.insert("format".to_string(), Value::String(string.format.clone()));
+        Some((Value::Object(entry).to_string(), frame.pos))
+    }
+
+    // Formats the interned string at the next index, with its arguments
+    fn format_nested(&self, frame: &mut Frame) -> Option<String> {
+        let index = frame.u16()?;
+        self.format_index(index, frame)
+    }
+
+    fn format_index(&self, index: u16, frame: &mut Frame) -> Option<String> {
+        let string = self.strings.get(&index)?;
+        // A derived enum lists its variants, and is preceded by the variant that was logged
+        if string.tag == "defmt_derived" {
+            let variants = split_variants(&string.format);
+            if variants.len() > 1 {
+                let variant = match variants.len() {
+                    0..=0xFF => frame.uint(1)?,
+                    0x100..=0xFFFF => frame.uint(2)?,
+                    _ => frame.uint(4)?,
+                };
+                return self.format(variants.get(variant as usize)?, frame);
+            }
+        }
+        self.format(&string.format, frame)
+    }
+
+    fn format(&self, format: &str, frame: &mut Frame) -> Option<String> {
+        let Result::Ok(fragments) = defmt_parser::parse(format, ParserMode::ForwardsCompatible)
+        else {
+            return Some(format.to_string());
+        };
+        let params: Vec<&Parameter> = fragments
+            .iter()
+            .filter_map(|f| match f {
+                Fragment::Parameter(p) => Some(p),
+                Fragment::Literal(_) => None,
+            })
+            .collect();
+
+        // Arguments are sent in the order of their index, once each
+        let count = params.iter().map(|p| p.index + 1).max().unwrap_or(0);
+        let mut args = Vec::with_capacity(count);
+        for index in 0..count {
+            let uses = params.iter().copied().filter(|p| p.index == index);
+            let arg = match uses.clone().next().map(|p| &p.ty) {
+                Some(Type::BitField(_)) => {
+                    // Only the bytes that hold any of the fields are sent
+                    let (lowest, highest) = get_max_bitfield_range(uses)?;
+                    let (lowest_byte, highest_byte) = (lowest / 8, highest.saturating_sub(1) / 8);
+                    let size = match highest_byte - lowest_byte + 1 {
+                        1 => 1,
+                        2 => 2,
+                        3..=4 => 4,
+                        5..=8 => 8,
+                        _ => 16,
+                    };
+                    Arg::Uint(frame.uint(size)? << (lowest_byte * 8))
+                }
+                Some(ty) => self.decode_arg(ty, frame)?,
+                None => Arg::Text(String::new()),
+            };
+            args.push(arg);
+        }
+
+        let mut text = String::new();
+        for fragment in fragments {
+            match fragment {
+                Fragment::Literal(literal) => text.push_str(&literal),
+                Fragment::Parameter(p) => {
+                    let arg = match (&p.ty, &args[p.index]) {
+                        (Type::BitField(bits), Arg::Uint(value)) => {
+                            Arg::Uint(bitfield(*value, bits))
+                        }
+                        (_, arg) => arg.clone(),
+                    };
+                    text.push_str(&arg.render(p.hint.as_ref()));
+                }
+            }
+        }
+        Some(text)
+    }
+
+    fn decode_arg(&self, ty: &Type, frame: &mut Frame) -> Option<Arg> {
+        Some(match ty {
+            Type::Bool => Arg::Bool(frame.uint(1)? != 0),
+            Type::U8 => Arg::Uint(frame.uint(1)?),
+            Type::U16 => Arg::Uint(frame.uint(2)?),
+            Type::U32 | Type::Usize => Arg::Uint(frame.uint(4)?),
+            Type::U64 => Arg::Uint(frame.uint(8)?),
+            Type::U128 => Arg::Uint(frame.uint(16)?),
+            Type::I8 => Arg::Int(frame.int(1)?),
+            Type::I16 => Arg::Int(frame.int(2)?),
+            Type::I32 | Type::Isize => Arg::Int(frame.int(4)?),
+            Type::I64 => Arg::Int(frame.int(8)?),
+            Type::I128 => Arg::Int(frame.int(16)?),
+            Type::F32 => Arg::Float(f32::from_bits(frame.uint(4)? as u32) as f64),
+            Type::F64 => Arg::Float(f64::from_bits(frame.uint(8)? as u64)),
+            Type::Char => Arg::Char(char::from_u32(frame.uint(4)? as u32)?),
+            Type::Str => {
+                let len = frame.uint(4)? as usize;
+                Arg::Str(String::from_utf8_lossy(frame.take(len)?).to_string())
+            }
+            Type::IStr => Arg::Str(self.strings.get(&frame.u16()?)?.format.clone()),
+            Type::U8Slice => {
+                let len = frame.uint(4)? as usize;
+                Arg::Bytes(frame.take(len)?.to_vec())
+            }
+            Type::U8Array(len) => Arg::Bytes(frame.take(*len)?.to_vec()),
+            // Formatted on the device with core::fmt, and terminated with 0xFF
+            Type::Debug | Type::Display => {
+                let len = frame.bytes[frame.pos..].iter().position(|b| *b == 0xFF)?;
+                let text = String::from_utf8_lossy(frame.take(len)?).to_string();
+                frame.take(1)?;
+                Arg::Text(text)
+            }
+            Type::Format => Arg::Text(self.format_nested(frame)?),
+            Type::FormatSlice => {
+                let len = frame.uint(4)? as usize;
+                let index = frame.u16()?;
+                let items = (0..len)
+                    .map(|_| self.format_index(index, frame))
+                    .collect::<Option<Vec<_>>>()?;
+                Arg::Text(format!("[{}]", items.join(", ")))
+            }
+            Type::FormatArray(len) => {
+                let index = frame.u16()?;
+                let items = (0..*len)
+                    .map(|_| self.format_index(index, frame))
+                    .collect::<Option<Vec<_>>>()?;
+                Arg::Text(format!("[{}]", items.join(", ")))
+            }
+            // A number of `write!`s, each with their own index, ended by index 0
+            Type::FormatSequence => {
+                let mut text = String::new();
+                loop {
+                    match frame.u16()? {
+                        0 => break,
+                        index => text.push_str(&self.format_index(index, frame)?),
+                    }
+                }
+                Arg::Text(text)
+            }
+            Type::BitField(_) => Arg::Uint(frame.uint(1)?),
+        })
+    }
+}
+
+// Splits the format of a derived enum into its variants, e.g. `None|Some({=u8})`
+fn split_variants(format: &str) -> Vec<&str> {
+    let mut variants = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in format.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '|' if depth == 0 => {
+                variants.push(&format[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    variants.push(&format[start..]);
+    variants
+}
+
+struct Frame<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Frame<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn uint(&mut self, size: usize) -> Option<u128> {
+        let mut value = [0; 16];
+        value[..size].copy_from_slice(self.take(size)?);
+        Some(u128::from_le_bytes(value))
+    }
+
+    fn int(&mut self, size: usize) -> Option<i128> {
+        // Sign extend, by shifting the value up to the top and back down
+        let shift = 128 - 8 * size as u32;
+        Some(((self.uint(size)? << shift) as i128) >> shift)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(self.uint(2)? as u16)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Arg {
+    Uint(u128),
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+    Bytes(Vec<u8>),
+    /// Already formatted, e.g. a nested format string
+    Text(String),
+}
+
+impl Arg {
+    fn render(&self, hint: Option<&DisplayHint>) -> String {
+        match (self, hint) {
+            (Arg::Uint(v), Some(hint)) => render_int(*v, *v as i128, hint),
+            (Arg::Int(v), Some(hint)) => render_int(*v as u128, *v, hint),
+            (Arg::Uint(v), None) => v.to_string(),
+            (Arg::Int(v), None) => v.to_string(),
+            (Arg::Float(v), _) => v.to_string(),
+            (Arg::Bool(v), _) => v.to_string(),
+            (Arg::Char(c), Some(DisplayHint::Debug)) => format!("{:?}", c),
+            (Arg::Char(c), _) => c.to_string(),
+            (Arg::Str(s), Some(DisplayHint::Debug)) => format!("{:?}", s),
+            (Arg::Str(s) | Arg::Text(s), _) => s.clone(),
+            (Arg::Bytes(bytes), Some(DisplayHint::Ascii)) => {
+                let ascii: String = bytes
+                    .iter()
+                    .flat_map(|b| std::ascii::escape_default(*b))
+                    .map(|b| b as char)
+                    .collect();
+                format!("b\"{}\"", ascii)
+            }
+            (Arg::Bytes(bytes), hint) => {
+                let items: Vec<_> = bytes
+                    .iter()
+                    .map(|b| Arg::Uint(*b as u128).render(hint))
+                    .collect();
+                format!("[{}]", items.join(", "))
+            }
+        }
+    }
+}
+
+fn render_int(unsigned: u128, signed: i128, hint: &DisplayHint) -> String {
+    match *hint {
+        DisplayHint::Hexadecimal {
+            alternate,
+            uppercase,
+            zero_pad,
+        } => match (alternate, uppercase) {
+            (false, false) => format!("{:01$x}", unsigned, zero_pad),
+            (false, true) => format!("{:01$X}", unsigned, zero_pad),
+            (true, false) => format!("{:#01$x}", unsigned, zero_pad),
+            (true, true) => format!("{:#01$X}", unsigned, zero_pad),
+        },
+        DisplayHint::Octal {
+            alternate: true,
+            zero_pad,
+        } => format!("{:#01$o}", unsigned, zero_pad),
+        DisplayHint::Octal { zero_pad, .. } => format!("{:01$o}", unsigned, zero_pad),
+        DisplayHint::Binary {
+            alternate: true,
+            zero_pad,
+        } => format!("{:#01$b}", unsigned, zero_pad),
+        DisplayHint::Binary { zero_pad, .. } => format!("{:01$b}", unsigned, zero_pad),
+        DisplayHint::NoHint { zero_pad } => format!("{:01$}", signed, zero_pad),
+        DisplayHint::Seconds(ref precision) => match precision {
+            TimePrecision::Micros => {
+                format!("{}.{:06}", unsigned / 1_000_000, unsigned % 1_000_000)
+            }
+            TimePrecision::Millis => format!("{}.{:03}", unsigned / 1_000, unsigned % 1_000),
+            TimePrecision::Seconds => unsigned.to_string(),
+        },
+        DisplayHint::Time(ref precision) => {
+            let (seconds, fraction) = match precision {
+                TimePrecision::Micros => (
+                    unsigned / 1_000_000,
+                    format!(".{:06}", unsigned % 1_000_000),
+                ),
+                TimePrecision::Millis => (unsigned / 1_000, format!(".{:03}", unsigned % 1_000)),
+                TimePrecision::Seconds => (unsigned, String::new()),
+            };
+            format!(
+                "{:02}:{:02}:{:02}{}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60,
+                fraction
+            )
+        }
+        DisplayHint::ISO8601(ref precision) => {
+            let time = match precision {
+                TimePrecision::Millis => chrono::DateTime::from_timestamp_millis(signed as i64),
+                _ => chrono::DateTime::from_timestamp(signed as i64, 0),
+            };
+            match time {
+                Some(time) => time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+                None => signed.to_string(),
+            }
+        }
+        _ => signed.to_string(),
+    }
+}
+
+/// The bits of the range, shifted down. An empty range, or one past the 128th bit, is 0
+fn bitfield(value: u128, bits: &Range<u8>) -> u128 {
+    let width = bits.end.saturating_sub(bits.start) as u32;
+    let mask = u128::MAX
+        .checked_shr(128u32.saturating_sub(width))
+        .unwrap_or(0);
+    value.checked_shr(bits.start as u32).unwrap_or(0) & mask
+}
+
+/*
+Finds where each of the interned strings was declared. defmt's macros put
+every string in a static, so we look for variables in the debug info whose
+location is one of the addresses we know.
+*/
+fn find_locations(
+    elf: &object::File,
+    addresses: &HashSet<u64>,
+) -> Result<HashMap<u64, (String, u64)>> {
+    let endian = match elf.is_little_endian() {
+        true => RunTimeEndian::Little,
+        false => RunTimeEndian::Big,
+    };
+    let sections = gimli::DwarfSections::load(|id| -> Result<Cow<[u8]>> {
+        Ok(match elf.section_by_name(id.name()) {
+            Some(section) => section.uncompressed_data()?,
+            None => Cow::Borrowed(&[]),
+        })
+    })?;
+    let dwarf = sections.borrow(|section| EndianSlice::new(section, endian));
+
+    let mut locations = HashMap::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let Some(program) = &unit.line_program else {
+            continue;
+        };
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() != gimli::DW_TAG_variable {
+                continue;
+            }
+            let Some(AttributeValue::Exprloc(location)) =
+                entry.attr_value(gimli::DW_AT_location)?
+            else {
+                continue;
+            };
+            let address = match location.operations(unit.encoding()).next() {
+                Result::Ok(Some(Operation::Address { address })) => address,
+                _ => continue,
+            };
+            if !addresses.contains(&address) {
+                continue;
+            }
+            let Some(AttributeValue::FileIndex(file)) = entry.attr_value(gimli::DW_AT_decl_file)?
+            else {
+                continue;
+            };
+            let Some(line) = entry
+                .attr(gimli::DW_AT_decl_line)?
+                .and_then(|a| a.udata_value())
+            else {
+                continue;
+            };
+            let Some(file) = program.header().file(file) else {
+                continue;
+            };
+
+            let mut path = PathBuf::new();
+            if let Some(dir) = &unit.comp_dir {
+                path.push(dir.to_string_lossy().as_ref());
+            }
+            if let Some(dir) = file.directory(program.header()) {
+                path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
+            }
+            path.push(
+                dwarf
+                    .attr_string(&unit, file.path_name())?
+                    .to_string_lossy()
+                    .as_ref(),
+            );
+            locations.insert(address, (path.display().to_string(), line));
+        }
+    }
+    Ok(locations)
+}
+
+/*
+Splits the stream into frames as they arrive. rzCOBS frames end with a 0,
+and are decoded on their own. Raw frames follow each other without a
+separator, so how long a frame is only becomes clear while decoding it.
+*/
+pub struct DefmtDecoder {
+    table: Arc<DefmtTable>,
+    pending: Vec<u8>,
+}
+
+impl DefmtDecoder {
+    pub fn push(&mut self, bytes: &[u8], last: bool) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+        let mut entries = Vec::new();
+        let mut start = 0;
+        if self.table.rzcobs {
+            while let Some(end) = self.pending[start..].iter().position(|b| *b == 0) {
+                let frame = &self.pending[start..start + end];
+                start += end + 1;
+                if frame.is_empty() {
+                    continue;
+                }
+                match rzcobs_decode(frame).and_then(|f| self.table.decode_frame(&f)) {
+                    Some((entry, _)) => entries.push(entry),
+                    None => warn!("Skipped a malformed defmt frame"),
+                }
+            }
+        } else {
+            while start < self.pending.len() {
+                match self.table.decode_frame(&self.pending[start..]) {
+                    Some((entry, len)) => {
+                        entries.push(entry);
+                        start += len;
+                    }
+                    None if last || self.pending.len() - start > MAX_FRAME_LEN => {
+                        warn!("Skipped a malformed defmt frame");
+                        start = self.pending.len();
+                    }
+                    None => break,
+                }
+            }
+        }
+        self.pending.drain(..start);
+        entries
+    }
+}
+
+/*
+rzCOBS is decoded back to front. A byte with the top bit clear tells which
+of the 7 bytes before it are zeros, one with the top bit set is a run of
+non-zero bytes followed by a zero, and 0xFF is a run without a zero.
+*/
+fn rzcobs_decode(frame: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(frame.len() * 2);
+    let mut bytes = frame.iter().rev().copied();
+    while let Some(code) = bytes.next() {
+        match code {
+            0 => return None,
+            0x01..=0x7F => {
+                for bit in (0..7).rev() {
+                    match code & (1 << bit) {
+                        0 => decoded.push(bytes.next()?),
+                        _ => decoded.push(0),
+                    }
+                }
+            }
+            0x80..=0xFE => {
+                decoded.push(0);
+                for _ in 0..(code & 0x7F) + 7 {
+                    decoded.push(bytes.next()?);
+                }
+            }
+            0xFF => {
+                for _ in 0..134 {
+                    decoded.push(bytes.next()?);
+                }
+            }
+        }
+    }
+    decoded.reverse();
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+    The firmware in firmware/ is a small defmt 1.0 program, built for the host so it can
+    run and write its frames to stdout, which is what the capture is. Its
+    compilation directory is remapped to `firmware`.
+    */
+    const FIRMWARE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/defmt-firmware.elf"
+    );
+    const CAPTURE: &[u8] = include_bytes!("../tests/fixtures/defmt-capture.bin");
+
+    fn load() -> DefmtTable {
+        DefmtTable::load(FIRMWARE).unwrap()
+    }
+
+    fn decode(table: DefmtTable, bytes: &[u8], chunk: usize) -> Vec<Value> {
+        let mut decoder = Arc::new(table).decoder();
+        let mut entries = Vec::new();
+        for chunk in bytes.chunks(chunk) {
+            entries.extend(decoder.push(chunk, false));
+        }
+        entries.extend(decoder.push(&[], true));
+        entries
+            .iter()
+            .map(|e| serde_json::from_str(e).unwrap())
+            .collect()
+    }
+
+    fn messages(entries: &[Value]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|e| e["message"].as_str().unwrap())
+            .collect()
+    }
+
+    const MESSAGES: [&str; 9] = [
+        "booting firmware v1.4",
+        "sensor 3 read -1250 mV",
+        "state is Busy { job: 7 }",
+        "state is Idle",
+        "register 0x5 flags 1010",
+        "readings [Reading { channel: 1, millivolts: 3300 }, Reading { channel: 2, millivolts: -5 }]",
+        "name pump ok true ratio 0.5",
+        "bytes [de, ad] and Empty",
+        "done after 42 ticks",
+    ];
+
+    #[test]
+    fn firmware_strings_are_loaded_with_their_location() {
+        let table = load();
+        assert!(table.rzcobs);
+        assert_eq!(table.timestamp.as_deref(), Some("{=u64:us}"));
+        let boot = table
+            .strings
+            .values()
+            .find(|s| s.format == "booting firmware v{=u8}.{=u8}")
+            .unwrap();
+        assert_eq!(boot.tag, "defmt_info");
+        assert_eq!(
+            boot.location,
+            Some(("firmware/src/main.rs".to_string(), 72))
+        );
+    }
+
+    #[test]
+    fn capture_is_decoded() {
+        let entries = decode(load(), CAPTURE, CAPTURE.len());
+        assert_eq!(messages(&entries), MESSAGES);
+
+        let levels: Vec<_> = entries.iter().map(|e| e["level"].as_str()).collect();
+        assert_eq!(
+            levels,
+            [
+                Some("INFO"),
+                Some("DEBUG"),
+                Some("WARN"),
+                Some("INFO"),
+                Some("ERROR"),
+                Some("INFO"),
+                Some("TRACE"),
+                Some("INFO"),
+                None
+            ]
+        );
+        assert_eq!(entries[0]["timestamp"], "0.001500");
+        assert_eq!(entries[8]["timestamp"], "0.013500");
+        assert_eq!(
+            entries[4]["format"],
+            "register {0=0..4:#x} flags {0=4..8:b}"
+        );
+        for entry in &entries {
+            assert_eq!(entry["file"], "firmware/src/main.rs");
+        }
+        assert_eq!(entries[0]["line"], 72);
+        assert_eq!(entries[8]["line"], 86);
+    }
+
+    #[test]
+    fn capture_is_decoded_as_it_streams_in() {
+        let entries = decode(load(), CAPTURE, 1);
+        assert_eq!(messages(&entries), MESSAGES);
+    }
+
+    #[test]
+    fn raw_frames_are_decoded() {
+        // The same frames, without the rzCOBS encoding and separators. rzCOBS
+        // pads a frame with zeros, so only the bytes the entry took are kept.
+        let table = load();
+        let raw: Vec<u8> = CAPTURE
+            .split(|b| *b == 0)
+            .filter(|f| !f.is_empty())
+            .flat_map(|f| {
+                let frame = rzcobs_decode(f).unwrap();
+                let (_, len) = table.decode_frame(&frame).unwrap();
+                frame[..len].to_vec()
+            })
+            .collect();
+        let mut table = table;
+        table.rzcobs = false;
+        assert_eq!(messages(&decode(table, &raw, 5)), MESSAGES);
+    }
+
+    #[test]
+    fn malformed_frames_are_skipped() {
+        let mut bytes = vec![0x12, 0x00, 0x00];
+        bytes.extend_from_slice(CAPTURE);
+        let entries = decode(load(), &bytes, 7);
+        assert_eq!(messages(&entries), MESSAGES);
+
+        let mut table = load();
+        table.strings.clear();
+        table.strings.insert(
+            1,
+            DefmtString {
+                tag: "defmt_info".to_string(),
+                format: "x".to_string(),
+                location: None,
+            },
+        );
+        let entries = decode(table, CAPTURE, CAPTURE.len());
+        assert_eq!(entries[0]["message"], "Unknown defmt string index 4");
+    }
+
+    #[test]
+    fn bitfields() {
+        assert_eq!(bitfield(0xA5, &(0..4)), 0x5);
+        assert_eq!(bitfield(0xA5, &(4..8)), 0xA);
+        assert_eq!(bitfield(u128::MAX, &(0..128)), u128::MAX);
+        assert_eq!(bitfield(u128::MAX, &(127..128)), 1);
+        assert_eq!(bitfield(u128::MAX, &(3..3)), 0);
+        assert_eq!(bitfield(u128::MAX, &(128..128)), 0);
+    }
+}
@@ -0,0 +1,475 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    // Rust 1.73 and later put the message on the next line, earlier versions quote it
+    static ref RUST_PANIC: Regex = Regex::new(
+        r"^\s*thread '(?P<thread>[^']*)' panicked at (?:'(?P<message>.*)', )?(?P<file>[^\s:]+):(?P<line>\d+):(?P<column>\d+):?$"
+    )
+    .unwrap();
+    static ref RUST_FRAME: Regex =
+        Regex::new(r"^\s*\d+:\s+(?:0x[0-9a-fA-F]+ - )?(?P<func>.+?)(?:::h[0-9a-f]{16})?$").unwrap();
+    static ref RUST_LOCATION: Regex =
+        Regex::new(r"^\s+at (?P<file>.+?):(?P<line>\d+)(?::(?P<column>\d+))?$").unwrap();
+    static ref JAVA_EXCEPTION: Regex = Regex::new(
+        r#"^\s*(?:Exception in thread "[^"]*" |Caused by: |Suppressed: )?(?P<type>[A-Za-z_$][\w$]*(?:\.[A-Za-z_$][\w$]*)+)(?::\s?(?P<message>.*))?$"#
+    )
+    .unwrap();
+    // Modules and class loaders come before the class, e.g. `java.base/java.lang.Thread.run`
+    static ref JAVA_FRAME: Regex = Regex::new(
+        r"^\s*at (?:[\w.$@-]+/)*(?P<func>[\w$.<>]+)\((?:(?P<file>[^:)]+):(?P<line>\d+)|[^)]*)\)"
+    )
+    .unwrap();
+    static ref JAVA_MORE: Regex = Regex::new(r"^\s*\.\.\. \d+ (?:more|common frames omitted)").unwrap();
+    static ref PYTHON_FRAME: Regex =
+        Regex::new(r#"^\s*File "(?P<file>[^"]+)", line (?P<line>\d+)(?:, in (?P<func>.+))?$"#).unwrap();
+    static ref PYTHON_EXCEPTION: Regex =
+        Regex::new(r"^(?P<type>[A-Za-z_][\w.]*)(?::\s?(?P<message>.*))?$").unwrap();
+    static ref GO_PANIC: Regex =
+        Regex::new(r"^\s*(?P<type>panic|fatal error): (?P<message>.*?)(?: \[recovered\])?$").unwrap();
+    static ref GO_GOROUTINE: Regex = Regex::new(r"^\s*goroutine \d+ \[[^\]]*\]:$").unwrap();
+    static ref GO_FRAME: Regex =
+        Regex::new(r"^(?:created by )?(?P<func>\S+?)(?:\([^()]*\))?(?: in goroutine \d+)?$").unwrap();
+    static ref GO_LOCATION: Regex =
+        Regex::new(r"^\s+(?P<file>\S+):(?P<line>\d+)(?: \+0x[0-9a-f]+)?$").unwrap();
+}
+
+#[derive(Clone, Debug)]
+pub struct TraceFrame {
+    pub function: String,
+    pub file: Option<String>,
+    pub line: Option<u64>,
+    pub column: Option<u64>,
+    /// Frames in the standard library or dependencies, which are not looked up in the sources
+    pub library: bool,
+}
+
+/// A panic or exception, with the frames it was raised from.
+#[derive(Clone, Debug)]
+pub struct Trace {
+    /// The type of the exception, e.g. `java.lang.IllegalStateException` or `ValueError`
+    pub exception: String,
+    pub message: String,
+    /// Innermost first
+    pub frames: Vec<TraceFrame>,
+    /// The exception that caused this one
+    pub cause: Option<Box<Trace>>,
+    /// The lines of the trace, as they were logged
+    pub text: String,
+}
+
+// The lines of a trace, consumed as they are parsed
+struct Lines<'a> {
+    lines: &'a [String],
+    pos: usize,
+}
+
+impl<'a> Lines<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.lines.get(self.pos).map(|l| l.as_str())
+    }
+
+    fn next_if(&mut self, pattern: &Regex) -> Option<regex::Captures<'a>> {
+        let captures = pattern.captures(self.peek()?)?;
+        self.pos += 1;
+        Some(captures)
+    }
+
+    fn skip_while(&mut self, f: impl Fn(&str) -> bool) {
+        while self.peek().is_some_and(&f) {
+            self.pos += 1;
+        }
+    }
+}
+
+/*
+Parses the trace starting at the first of the lines: a Rust panic, a Java
+exception, a Python traceback or a Go panic. The trace ends at the first
+line that is not part of it.
+*/
+pub fn parse_trace(lines: &[String]) -> Option<Trace> {
+    let mut lines = Lines { lines, pos: 0 };
+    let parsers: [fn(&mut Lines) -> Option<Trace>; 4] =
+        [parse_rust, parse_java, parse_python, parse_go];
+    let mut trace = parsers.iter().find_map(|parse| {
+        lines.pos = 0;
+        parse(&mut lines)
+    })?;
+    trace.text = lines.lines[..lines.pos].join("\n");
+    Some(trace)
+}
+
+fn number(captures: &regex::Captures, name: &str) -> Option<u64> {
+    captures.name(name)?.as_str().parse().ok()
+}
+
+fn parse_rust(lines: &mut Lines) -> Option<Trace> {
+    let panic = lines.next_if(&RUST_PANIC)?;
+    let message = match panic.name("message") {
+        Some(message) => message.as_str().to_string(),
+        // The log may end before the message
+        None => match lines.peek() {
+            Some(message) => {
+                lines.pos += 1;
+                message.trim().to_string()
+            }
+            None => String::new(),
+        },
+    };
+
+    let mut frames = Vec::new();
+    if lines.peek().is_some_and(|l| l.trim() == "stack backtrace:") {
+        lines.pos += 1;
+        while let Some(frame) = lines.next_if(&RUST_FRAME) {
+            let location = lines.next_if(&RUST_LOCATION);
+            let file = location.as_ref().map(|l| l["file"].to_string());
+            frames.push(TraceFrame {
+                function: frame["func"].to_string(),
+                library: file
+                    .as_ref()
+                    .is_none_or(|f| f.starts_with("/rustc/") || f.contains("/.cargo/registry/")),
+                file,
+                line: location.as_ref().and_then(|l| number(l, "line")),
+                column: location.as_ref().and_then(|l| number(l, "column")),
+            });
+        }
+    }
+    lines.skip_while(|l| l.trim_start().starts_with("note: "));
+
+    // Without a backtrace, all we know is where it panicked
+    if frames.is_empty() {
+        frames.push(TraceFrame {
+            function: format!("thread '{}'", &panic["thread"]),
+            file: Some(panic["file"].to_string()),
+            line: number(&panic, "line"),
+            column: number(&panic, "column"),
+            library: false,
+        });
+    }
+    Some(Trace {
+        exception: "panic".to_string(),
+        message,
+        frames,
+        cause: None,
+        text: String::new(),
+    })
+}
+
+/*
+Java only logs the file name, which we find under the directory of the
+package, e.g. `com.example.Foo.bar(Foo.java:42)` is in
+`com/example/Foo.java`.
+*/
+fn parse_java(lines: &mut Lines) -> Option<Trace> {
+    let start = lines.pos;
+    let exception = lines.next_if(&JAVA_EXCEPTION)?;
+    let mut frames = Vec::new();
+    while let Some(frame) = lines.next_if(&JAVA_FRAME) {
+        let function = frame["func"].to_string();
+        let class = function.rsplit_once('.').map_or("", |(class, _)| class);
+        let package = class.rsplit_once('.').map_or("", |(package, _)| package);
+        frames.push(TraceFrame {
+            file: frame.name("file").map(|f| match package {
+                "" => f.as_str().to_string(),
+                p => format!("{}/{}", p.replace('.', "/"), f.as_str()),
+            }),
+            line: number(&frame, "line"),
+            column: None,
+            library: ["java.", "javax.", "jdk.", "sun.", "kotlin.", "scala."]
+                .iter()
+                .any(|p| function.starts_with(p)),
+            function,
+        });
+        lines.skip_while(|l| JAVA_MORE.is_match(l));
+    }
+    // Anything with a dot in it looks like an exception, until it is followed by its frames
+    if frames.is_empty() {
+        lines.pos = start;
+        return None;
+    }
+
+    let cause = match lines.peek() {
+        Some(l) if l.trim_start().starts_with("Caused by: ") => parse_java(lines).map(Box::new),
+        _ => None,
+    };
+    Some(Trace {
+        exception: exception["type"].to_string(),
+        message: exception
+            .name("message")
+            .map_or("", |m| m.as_str())
+            .to_string(),
+        frames,
+        cause,
+        text: String::new(),
+    })
+}
+
+/*
+Python prints the innermost frame last, after the code of each frame.
+Chained exceptions are printed as a traceback of the cause, followed by
+the traceback of the exception it caused.
+*/
+fn parse_python(lines: &mut Lines) -> Option<Trace> {
+    if lines.peek()?.trim() != "Traceback (most recent call last):" {
+        return None;
+    }
+    lines.pos += 1;
+    let mut frames = Vec::new();
+    while let Some(frame) = lines.next_if(&PYTHON_FRAME) {
+        let file = frame["file"].to_string();
+        frames.push(TraceFrame {
+            function: frame
+                .name("func")
+                .map_or("<module>", |f| f.as_str())
+                .to_string(),
+            library: file.contains("/site-packages/")
+                || file.contains("/lib/python")
+                || file.starts_with('<'),
+            file: Some(file),
+            line: number(&frame, "line"),
+            column: None,
+        });
+        // The code of the frame, its `^^^^` markers and `[Previous line repeated 996 more times]`
+        lines.skip_while(|l| l.starts_with(char::is_whitespace) && !PYTHON_FRAME.is_match(l));
+    }
+    frames.reverse();
+    let exception = lines.next_if(&PYTHON_EXCEPTION)?;
+    let trace = Trace {
+        exception: exception["type"].to_string(),
+        message: exception
+            .name("message")
+            .map_or("", |m| m.as_str())
+            .to_string(),
+        frames,
+        cause: None,
+        text: String::new(),
+    };
+
+    let chained = |l: &str| {
+        l.starts_with("During handling of the above exception")
+            || l.starts_with("The above exception was the direct cause")
+    };
+    let start = lines.pos;
+    lines.skip_while(|l| l.trim().is_empty());
+    if lines.peek().is_some_and(chained) {
+        lines.pos += 1;
+        lines.skip_while(|l| l.trim().is_empty());
+        if let Some(mut effect) = parse_python(lines) {
+            effect.cause = Some(Box::new(trace));
+            return Some(effect);
+        }
+    }
+    lines.pos = start;
+    Some(trace)
+}
+
+// Only the goroutine that panicked is parsed, which Go prints first
+fn parse_go(lines: &mut Lines) -> Option<Trace> {
+    let panic = lines.next_if(&GO_PANIC)?;
+    // Blank lines, `[signal SIGSEGV: segmentation violation ...]` and panics while panicking
+    lines.skip_while(|l| {
+        l.trim().is_empty() || l.trim_start().starts_with('[') || GO_PANIC.is_match(l)
+    });
+    lines.next_if(&GO_GOROUTINE)?;
+
+    let mut frames = Vec::new();
+    while let Some(frame) = lines.next_if(&GO_FRAME) {
+        let Some(location) = lines.next_if(&GO_LOCATION) else {
+            lines.pos -= 1;
+            break;
+        };
+        let file = location["file"].to_string();
+        frames.push(TraceFrame {
+            function: frame["func"].to_string(),
+            library: file.contains("/src/runtime/") || file.contains("/pkg/mod/"),
+            file: Some(file),
+            line: number(&location, "line"),
+            column: None,
+        });
+    }
+    lines.skip_while(|l| l.starts_with("exit status "));
+    Some(Trace {
+        exception: panic["type"].to_string(),
+        message: panic["message"].to_string(),
+        frames,
+        cause: None,
+        text: String::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(text: &str) -> Trace {
+        let lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+        parse_trace(&lines).unwrap()
+    }
+
+    fn frames(trace: &Trace) -> Vec<(&str, Option<&str>, Option<u64>, bool)> {
+        trace
+            .frames
+            .iter()
+            .map(|f| (f.function.as_str(), f.file.as_deref(), f.line, f.library))
+            .collect()
+    }
+
+    #[test]
+    fn rust_panics() {
+        let panic = trace(
+            "thread 'main' panicked at src/main.rs:4:5:\n\
+             index out of bounds\n\
+             stack backtrace:\n\
+             \x20  0: std::panicking::begin_panic\n\
+             \x20            at /rustc/abc/library/std/src/panicking.rs:616:12\n\
+             \x20  1: app::parse::h0123456789abcdef\n\
+             \x20            at ./src/main.rs:4:5\n\
+             note: Some details are omitted\n\
+             next entry",
+        );
+        assert_eq!(panic.message, "index out of bounds");
+        assert_eq!(
+            frames(&panic),
+            [
+                (
+                    "std::panicking::begin_panic",
+                    Some("/rustc/abc/library/std/src/panicking.rs"),
+                    Some(616),
+                    true
+                ),
+                ("app::parse", Some("./src/main.rs"), Some(4), false),
+            ]
+        );
+        assert_eq!(panic.text.lines().count(), 8);
+
+        let old = trace("thread 'worker' panicked at 'oh no', src/lib.rs:10:3");
+        assert_eq!(old.message, "oh no");
+        assert_eq!(
+            frames(&old),
+            [("thread 'worker'", Some("src/lib.rs"), Some(10), false)]
+        );
+
+        // The log ends before the message
+        let cut = trace("thread 'main' panicked at src/main.rs:4:5:");
+        assert_eq!(cut.message, "");
+        assert_eq!(cut.text, "thread 'main' panicked at src/main.rs:4:5:");
+    }
+
+    #[test]
+    fn java_exceptions() {
+        let exception = trace(
+            "Exception in thread \"main\" java.lang.IllegalStateException: bad state\n\
+             \tat com.example.Foo.bar(Foo.java:42)\n\
+             \tat java.base/java.lang.Thread.run(Thread.java:833)\n\
+             Caused by: java.io.IOException: disk full\n\
+             \tat com.example.Disk.write(Native Method)\n\
+             \t... 2 more\n\
+             next entry",
+        );
+        assert_eq!(exception.exception, "java.lang.IllegalStateException");
+        assert_eq!(exception.message, "bad state");
+        assert_eq!(
+            frames(&exception),
+            [
+                (
+                    "com.example.Foo.bar",
+                    Some("com/example/Foo.java"),
+                    Some(42),
+                    false
+                ),
+                (
+                    "java.lang.Thread.run",
+                    Some("java/lang/Thread.java"),
+                    Some(833),
+                    true
+                ),
+            ]
+        );
+        let cause = exception.cause.as_ref().unwrap();
+        assert_eq!(cause.exception, "java.io.IOException");
+        assert_eq!(
+            frames(cause),
+            [("com.example.Disk.write", None, None, false)]
+        );
+        assert_eq!(exception.text.lines().count(), 6);
+
+        // A dotted message is no exception without frames
+        let lines = vec!["config.yaml: loaded".to_string()];
+        assert!(parse_trace(&lines).is_none());
+    }
+
+    #[test]
+    fn python_tracebacks() {
+        let traceback = trace(
+            "Traceback (most recent call last):\n\
+             \x20 File \"app.py\", line 3, in <module>\n\
+             \x20   main()\n\
+             \x20 File \"/usr/lib/python3.12/json/__init__.py\", line 346, in loads\n\
+             \x20   return _default_decoder.decode(s)\n\
+             \x20          ^^^^^^^^^^^^^^^^^^^^^^^^^^\n\
+             ValueError: bad json\n\
+             \n\
+             During handling of the above exception, another exception occurred:\n\
+             \n\
+             Traceback (most recent call last):\n\
+             \x20 File \"app.py\", line 5, in main\n\
+             KeyError: 'x'\n\
+             next entry",
+        );
+        assert_eq!(traceback.exception, "KeyError");
+        assert_eq!(traceback.message, "'x'");
+        assert_eq!(
+            frames(&traceback),
+            [("main", Some("app.py"), Some(5), false)]
+        );
+        let cause = traceback.cause.as_ref().unwrap();
+        assert_eq!(cause.exception, "ValueError");
+        assert_eq!(
+            frames(cause),
+            [
+                (
+                    "loads",
+                    Some("/usr/lib/python3.12/json/__init__.py"),
+                    Some(346),
+                    true
+                ),
+                ("<module>", Some("app.py"), Some(3), false),
+            ]
+        );
+        assert_eq!(traceback.text.lines().count(), 13);
+    }
+
+    #[test]
+    fn go_panics() {
+        let panic = trace(
+            "panic: runtime error: invalid memory address or nil pointer dereference\n\
+             [signal SIGSEGV: segmentation violation code=0x1 addr=0x0 pc=0x4553a1]\n\
+             \n\
+             goroutine 1 [running]:\n\
+             main.(*Server).handle(0x0, {0x4b2c60, 0x5})\n\
+             \t/home/me/app/server.go:27 +0x21\n\
+             main.main()\n\
+             \t/home/me/app/main.go:9 +0x1d\n\
+             exit status 2\n\
+             next entry",
+        );
+        assert_eq!(panic.exception, "panic");
+        assert_eq!(
+            panic.message,
+            "runtime error: invalid memory address or nil pointer dereference"
+        );
+        assert_eq!(
+            frames(&panic),
+            [
+                (
+                    "main.(*Server).handle",
+                    Some("/home/me/app/server.go"),
+                    Some(27),
+                    false
+                ),
+                ("main.main", Some("/home/me/app/main.go"), Some(9), false),
+            ]
+        );
+        assert_eq!(panic.text.lines().count(), 9);
+    }
+}
@@ -0,0 +1,350 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    // `{}`, `{:?}`, `{0}`, `{name:>8}` and defmt's `{=u8}`, but not the escaped `{{` and `}}`
+    static ref BRACE: Regex = Regex::new(r"\{\{|\}\}|\{(?P<spec>[^{}]*)\}").unwrap();
+    // `%d`, `%-5.2f`, `%llu`, `%*s`, Go's `%+v`, but not the escaped `%%`
+    static ref PRINTF: Regex = Regex::new(
+        r"%%|%[-+ #0]*(?P<width>\*|\d+)?(?:\.(?P<precision>\*|\d+))?(?:hh|h|ll|l|j|z|t|L|q)?[diouxXeEfFgGaAcspnvqTwtb]"
+    )
+    .unwrap();
+}
+
+/// How many lines after the matched line a log call may span
+const MAX_CALL_LINES: usize = 10;
+
+// A format string, split into the text between the placeholders and the placeholders
+struct Format {
+    text: Vec<String>,
+    /// The argument each placeholder shows, either by position or by name
+    placeholders: Vec<Placeholder>,
+}
+
+enum Placeholder {
+    Next,
+    /// A `*` width or precision, which takes an argument without showing it
+    Hidden,
+    Index(usize),
+    Name(String),
+}
+
+/*
+Recovers the values of the arguments of the log call at `line` of the
+source, e.g. `info!("conn {} state={:?}", id, state)` logging
+`conn 17 state=Idle` gives us `id = 17` and `state = Idle`. The values are
+found by turning the format string into a regex, with a group for each
+placeholder, that is matched against the message.
+*/
+pub fn recover_arguments(
+    source: &str,
+    line: usize,
+    message: &str,
+    printf: bool,
+) -> Vec<(String, String)> {
+    let call: String = source
+        .lines()
+        .skip(line.saturating_sub(1))
+        .take(MAX_CALL_LINES)
+        .collect::<Vec<_>>()
+        .join("\n");
+    let Some((format, arguments)) = find_call(&call, printf) else {
+        return Vec::new();
+    };
+
+    // Messages are trimmed, while formats often end in a newline
+    let last = format.text.len() - 1;
+    let mut pattern = String::from("^");
+    for (i, text) in format.text.iter().enumerate() {
+        let text = match i {
+            0 => text.trim_start(),
+            i if i == last => text.trim_end(),
+            _ => text,
+        };
+        pattern.push_str(&regex::escape(text));
+        match format.placeholders.get(i) {
+            None | Some(Placeholder::Hidden) => {}
+            // Values may contain anything, but the last one runs to the end of the message
+            Some(_)
+                if i + 1 == format.placeholders.len()
+                    && format.text[i + 1].trim_end().is_empty() =>
+            {
+                pattern.push_str("(.*)")
+            }
+            Some(_) => pattern.push_str("(.*?)"),
+        }
+    }
+    pattern.push('$');
+    let Some(captures) = Regex::new(&pattern).ok().and_then(|r| r.captures(message)) else {
+        return Vec::new();
+    };
+
+    let (named, positional): (Vec<_>, Vec<_>) =
+        arguments.iter().partition(|a| named_argument(a).is_some());
+    let mut next = 0;
+    let mut values = captures.iter().skip(1);
+    format
+        .placeholders
+        .iter()
+        .filter_map(|placeholder| {
+            let name = match placeholder {
+                Placeholder::Hidden => {
+                    next += 1;
+                    return None;
+                }
+                Placeholder::Next => {
+                    next += 1;
+                    positional.get(next - 1).map(|a| a.to_string())
+                }
+                Placeholder::Index(n) => positional.get(*n).map(|a| a.to_string()),
+                // A named argument, or a variable captured by the format string itself
+                Placeholder::Name(name) => Some(
+                    named
+                        .iter()
+                        .find_map(|a| named_argument(a).filter(|(n, _)| n == name))
+                        .map_or(name.clone(), |(_, expr)| expr.to_string()),
+                ),
+            };
+            let value = values.next()??.as_str().trim();
+            Some((expression_name(&name?), value.to_string()))
+        })
+        .collect()
+}
+
+// `name = expr` in Rust's format arguments
+fn named_argument(argument: &str) -> Option<(&str, &str)> {
+    let (name, expr) = argument.split_once('=')?;
+    let name = name.trim();
+    (!expr.starts_with('=')
+        && !name.is_empty()
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_'))
+    .then(|| (name, expr.trim()))
+}
+
+// The argument as it is written, on a single line, e.g. `self.conn.id()`
+fn expression_name(argument: &str) -> String {
+    let name = argument.split_whitespace().collect::<Vec<_>>().join(" ");
+    name.trim_start_matches('&').to_string()
+}
+
+/*
+Finds the format string and arguments of the call in the source. The
+format string is the first string literal with placeholders, as loggers
+may take other strings first, like the `target` of Rust's log macros.
+The arguments follow it, either in the same call or in Python's
+`.format(...)`.
+*/
+fn find_call(source: &str, printf: bool) -> Option<(Format, Vec<String>)> {
+    let mut rest = source;
+    loop {
+        let start = rest.find('"')?;
+        let (literal, after) = string_literal(&rest[start..])?;
+        rest = after;
+        let Some(format) = parse_format(&literal, printf) else {
+            continue;
+        };
+        let after = after.trim_start();
+        let arguments = match after.strip_prefix(".format(") {
+            Some(arguments) => split_arguments(arguments),
+            None => match after.strip_prefix(',') {
+                Some(arguments) => split_arguments(arguments),
+                None => Vec::new(),
+            },
+        };
+        return Some((format, arguments));
+    }
+}
+
+/*
+Reads the string literal at the start of the text, joining literals that
+follow each other like C does, e.g. `"conn %d " "state=%s"`.
+Returns the text of the literal and the source after it.
+*/
+fn string_literal(source: &str) -> Option<(String, &str)> {
+    let mut literal = String::new();
+    let mut rest = source;
+    while let Some(text) = rest.strip_prefix('"') {
+        let mut chars = text.char_indices();
+        let end = loop {
+            match chars.next()? {
+                (i, '"') => break i,
+                (_, '\\') => match chars.next()?.1 {
+                    'n' => literal.push('\n'),
+                    't' => literal.push('\t'),
+                    'r' => literal.push('\r'),
+                    // Rust continues a literal on the next line after a backslash
+                    '\n' => {
+                        while chars.clone().next().is_some_and(|(_, c)| c.is_whitespace()) {
+                            chars.next();
+                        }
+                    }
+                    c => literal.push(c),
+                },
+                (_, c) => literal.push(c),
+            }
+        };
+        rest = &text[end + 1..];
+        let next = rest.trim_start();
+        match next.starts_with('"') {
+            true => rest = next,
+            false => break,
+        }
+    }
+    Some((literal, rest))
+}
+
+fn parse_format(literal: &str, printf: bool) -> Option<Format> {
+    let mut format = Format {
+        text: vec![String::new()],
+        placeholders: Vec::new(),
+    };
+    let pattern: &Regex = match printf && PRINTF.find_iter(literal).any(|m| m.as_str() != "%%") {
+        true => &PRINTF,
+        false => &BRACE,
+    };
+    let mut last = 0;
+    for m in pattern.captures_iter(literal) {
+        let whole = m.get(0)?;
+        let text = format.text.last_mut()?;
+        text.push_str(&literal[last..whole.start()]);
+        last = whole.end();
+        match whole.as_str() {
+            "%%" => text.push('%'),
+            "{{" => text.push('{'),
+            "}}" => text.push('}'),
+            _ => {
+                for star in ["width", "precision"] {
+                    if m.name(star).is_some_and(|s| s.as_str() == "*") {
+                        format.placeholders.push(Placeholder::Hidden);
+                        format.text.push(String::new());
+                    }
+                }
+                let spec = m.name("spec").map_or("", |s| s.as_str());
+                let argument = spec
+                    .split([':', '=', '!'])
+                    .next()
+                    .unwrap_or_default()
+                    .trim();
+                format.placeholders.push(match argument.parse() {
+                    _ if argument.is_empty() => Placeholder::Next,
+                    Ok(n) => Placeholder::Index(n),
+                    Err(_) => Placeholder::Name(argument.to_string()),
+                });
+                format.text.push(String::new());
+            }
+        }
+    }
+    format.text.last_mut()?.push_str(&literal[last..]);
+    (!format.placeholders.is_empty()).then_some(format)
+}
+
+// Splits the arguments of a call at its top level commas, up to its closing parenthesis
+fn split_arguments(source: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut argument = String::new();
+    let mut depth = 0;
+    let mut chars = source.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' if depth == 0 => break,
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                arguments.push(std::mem::take(&mut argument));
+                continue;
+            }
+            ';' if depth == 0 => break,
+            '"' | '\'' => {
+                // Commas and parentheses in strings do not count
+                argument.push(c);
+                while let Some(s) = chars.next() {
+                    argument.push(s);
+                    match s {
+                        '\\' => argument.extend(chars.next()),
+                        s if s == c => break,
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            _ => {}
+        }
+        argument.push(c);
+    }
+    arguments.push(argument);
+    arguments
+        .into_iter()
+        .map(|a| a.trim().to_string())
+        .filter(|a| !a.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recover(source: &str, message: &str, printf: bool) -> Vec<(String, String)> {
+        recover_arguments(source, 2, message, printf)
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn rust_arguments() {
+        let source = "fn handle() {\n    info!(target: \"net\", \"conn {} state={:?}\", self.conn.id(), &state);\n}";
+        assert_eq!(
+            recover(source, "conn 17 state=Idle { since: 3 }", false),
+            pairs(&[("self.conn.id()", "17"), ("state", "Idle { since: 3 }")])
+        );
+
+        let source = "\n    debug!(\n        \"{1} of {0} took {ms:>5}ms, {{raw}} {user}\",\n        total,\n        done,\n        ms = elapsed.as_millis(),\n    );";
+        assert_eq!(
+            recover(source, "3 of 10 took    42ms, {raw} alice", false),
+            pairs(&[
+                ("done", "3"),
+                ("total", "10"),
+                ("elapsed.as_millis()", "42"),
+                ("user", "alice"),
+            ])
+        );
+    }
+
+    #[test]
+    fn printf_arguments() {
+        let source = "\n  printf(\"read %d bytes \" \"from %s (%.*f%%)\\n\", count, dev_name(d, \"a,b\"), prec, ratio);";
+        assert_eq!(
+            recover(source, "read 512 bytes from sda (0.50%)", true),
+            pairs(&[
+                ("count", "512"),
+                ("dev_name(d, \"a,b\")", "sda"),
+                ("ratio", "0.50")
+            ])
+        );
+        // Without printf, the same call has no placeholders
+        assert!(recover(source, "read 512 bytes from sda (0.50%)", false).is_empty());
+    }
+
+    #[test]
+    fn python_arguments() {
+        let source = "\nlog.info(\"user {name} logged in from {}\".format(addr, name=user.name))";
+        assert_eq!(
+            recover(source, "user bob logged in from 10.0.0.1", false),
+            pairs(&[("user.name", "bob"), ("addr", "10.0.0.1")])
+        );
+    }
+
+    #[test]
+    fn nothing_is_recovered_from_other_messages() {
+        let source = "\ninfo!(\"conn {} opened\", id);";
+        assert!(recover(source, "conn closed", false).is_empty());
+        assert!(recover("\ninfo!(\"no placeholders\");", "no placeholders", false).is_empty());
+        assert!(recover("\ninfo!(\"unterminated {}", "unterminated 1", false).is_empty());
+        assert_eq!(recover(source, "conn 1 opened", false).len(), 1);
+        assert!(recover_arguments(source, 40, "conn 1 opened", false).is_empty());
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use anyhow::*;
+use gimli::{EndianArcSlice, RunTimeEndian};
+use lazy_static::lazy_static;
+use object::{Object, ObjectSection, ObjectSymbol, SymbolKind};
+use regex::Regex;
+
+lazy_static! {
+    // `ext4_readdir+0x1a/0x40`, optionally followed by the module, e.g. `[ext4]`
+    static ref SYMBOL: Regex = Regex::new(
+        r"(?P<func>[A-Za-z_][\w.$]*)\+0x(?P<offset>[0-9a-fA-F]+)/0x[0-9a-fA-F]+(?:\s+\[(?P<module>[\w-]+)\])?"
+    )
+    .unwrap();
+    // `[<ffffffff8100abcd>]`
+    static ref ADDRESS: Regex = Regex::new(r"\[<(?P<address>[0-9a-fA-F]{8,16})>\]").unwrap();
+    // The parts of an oops between the frames, which do not end the backtrace
+    static ref OOPS_LINE: Regex = Regex::new(
+        r"^\s*(?:\[\s*\d+\.\d+\]\s*)?(?:Call [Tt]race:|Backtrace:|Stack:|Code:|Modules linked in:|CPU:|Hardware name:|Workqueue:|Internal error:|Oops|BUG:|WARNING:|Kernel panic|Unable to handle|RIP:|RSP:|EIP:|pc :|lr :|sp :|[A-Z][A-Z0-9]{1,3}:\s|x\d+\s*:|</?(?:TASK|IRQ|NMI|SOFTIRQ)>)"
+    )
+    .unwrap();
+}
+
+/// A frame of a kernel backtrace, as it was logged.
+#[derive(Clone, Debug)]
+pub struct RawFrame {
+    /// The function and offset, if the kernel symbolized the frame
+    pub symbol: Option<(String, u64)>,
+    pub module: Option<String>,
+    pub address: Option<u64>,
+    /// The kernel marks frames it is not sure about with `?`
+    pub unreliable: bool,
+    /// The faulting instruction itself, rather than a return address
+    pub exact: bool,
+}
+
+impl RawFrame {
+    pub fn name(&self) -> String {
+        match (&self.symbol, &self.module, self.address) {
+            (Some((func, offset)), Some(module), _) => {
+                format!("{}+0x{:x} [{}]", func, offset, module)
+            }
+            (Some((func, offset)), None, _) => format!("{}+0x{:x}", func, offset),
+            (None, _, Some(address)) => format!("0x{:x}", address),
+            (None, _, None) => "??".to_string(),
+        }
+    }
+
+    /// The frame as the kernel logged it, for when there is no symbol file
+    pub fn unresolved(&self) -> KernelFrame {
+        KernelFrame {
+            name: self.name(),
+            function: self.symbol.as_ref().map(|(func, _)| func.clone()),
+            file: None,
+            line: None,
+            address: self.address,
+            unreliable: self.unreliable,
+        }
+    }
+}
+
+/// A frame resolved with the symbol file.
+#[derive(Clone, Debug)]
+pub struct KernelFrame {
+    pub name: String,
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u64>,
+    pub address: Option<u64>,
+    pub unreliable: bool,
+}
+
+/// Whether a line belongs in an oops, without being a frame itself
+pub fn is_oops_line(message: &str) -> bool {
+    OOPS_LINE.is_match(message)
+}
+
+/*
+Finds the frames in a line of a kernel backtrace, e.g.
+` ? show_regs+0x6d/0x80`, `RIP: 0010:ext4_readdir+0x1a/0x40 [ext4]`,
+` [<ffffffff8100abcd>] iterate_dir+0x17f/0x1c0` or ARM's
+`[<c0102a4c>] (dump_stack+0x14/0x1c) from [<c0456d30>] (panic+0xc0/0x2a8)`.
+*/
+pub fn parse_frames(message: &str) -> Vec<RawFrame> {
+    let exact = ["RIP:", "EIP:", "pc :", "PC is at"]
+        .iter()
+        .any(|p| message.trim_start().starts_with(p));
+    // `?` comes right before the frame it marks
+    let unreliable = |start: usize| message[..start].trim_end().ends_with('?');
+    let symbols: Vec<_> = SYMBOL.captures_iter(message).collect();
+    let addresses: Vec<_> = ADDRESS
+        .captures_iter(message)
+        .filter_map(|c| {
+            Some((
+                c.get(0)?.start(),
+                u64::from_str_radix(&c["address"], 16).ok()?,
+            ))
+        })
+        .collect();
+
+    // Pair the symbols with their addresses, if every symbol has one
+    if !symbols.is_empty() {
+        return symbols
+            .iter()
+            .enumerate()
+            .map(|(i, c)| RawFrame {
+                symbol: Some((
+                    c["func"].to_string(),
+                    u64::from_str_radix(&c["offset"], 16).unwrap_or(0),
+                )),
+                module: c.name("module").map(|m| m.as_str().to_string()),
+                address: match symbols.len() == addresses.len() {
+                    true => Some(addresses[i].1),
+                    false => None,
+                },
+                unreliable: unreliable(c.get(0).map_or(0, |m| m.start())),
+                exact,
+            })
+            .collect();
+    }
+    addresses
+        .into_iter()
+        .map(|(start, address)| RawFrame {
+            symbol: None,
+            module: None,
+            address: Some(address),
+            unreliable: unreliable(start),
+            exact,
+        })
+        .collect()
+}
+
+type Functions = Vec<(u64, String)>;
+type DebugInfo = addr2line::Context<EndianArcSlice<RunTimeEndian>>;
+
+/*
+The symbols of a kernel: either a System.map, which only has the address of
+each function, or an ELF file like vmlinux, whose debug info also tells the
+file and line of every address.
+*/
+pub struct SymbolTable {
+    /// Functions sorted by address
+    functions: Functions,
+    addresses: HashMap<String, u64>,
+    /// The lookups of addr2line parse the debug info lazily, and are not thread safe
+    debug_info: Option<Mutex<DebugInfo>>,
+}
+
+impl fmt::Debug for SymbolTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SymbolTable")
+            .field("functions", &self.functions.len())
+            .field("debug_info", &self.debug_info.is_some())
+            .finish()
+    }
+}
+
+impl SymbolTable {
+    pub fn load(path: &str) -> Result<Arc<Self>> {
+        let bytes = std::fs::read(path).context(format!("Unable to read {}", path))?;
+        let (mut functions, debug_info) = match bytes.starts_with(b"\x7fELF") {
+            true => read_elf(&bytes).context(format!("Unable to read symbols from {}", path))?,
+            false => (read_system_map(&String::from_utf8_lossy(&bytes)), None),
+        };
+        if functions.is_empty() {
+            return Err(anyhow!("{} does not contain any functions", path));
+        }
+        functions.sort();
+        let mut addresses = HashMap::new();
+        for (address, name) in &functions {
+            addresses.entry(name.clone()).or_insert(*address);
+        }
+        info!(
+            "Loaded {} symbols from {}{}",
+            functions.len(),
+            path,
+            match debug_info.is_some() {
+                true => " with debug info",
+                false => "",
+            }
+        );
+
+        Ok(Arc::new(SymbolTable {
+            functions,
+            addresses,
+            debug_info: debug_info.map(Mutex::new),
+        }))
+    }
+
+    /*
+    Resolves a frame to its function, file and line. Functions inlined at
+    the address become frames of their own, innermost first.
+    Return addresses point after the call, so we look up the byte before.
+    */
+    pub fn resolve(&self, frame: &RawFrame) -> Vec<KernelFrame> {
+        let address = match &frame.symbol {
+            // Modules are loaded elsewhere, and are not in the kernel's symbols
+            Some((func, offset)) if frame.module.is_none() => self
+                .addresses
+                .get(func)
+                .and_then(|a| a.checked_add(*offset))
+                .or(frame.address),
+            Some(_) => None,
+            None => frame.address,
+        };
+        // Frames with only an address are named after the function they are in
+        let unresolved = match (&frame.symbol, address.and_then(|a| self.function_at(a))) {
+            (None, Some((func, offset))) => KernelFrame {
+                name: format!("{}+0x{:x}", func, offset),
+                function: Some(func.to_string()),
+                address,
+                ..frame.unresolved()
+            },
+            _ => KernelFrame {
+                address,
+                ..frame.unresolved()
+            },
+        };
+        let (Some(address), Some(debug_info)) = (address, &self.debug_info) else {
+            return vec![unresolved];
+        };
+        let Result::Ok(debug_info) = debug_info.lock() else {
+            return vec![unresolved];
+        };
+
+        let probe = match frame.exact {
+            true => address,
+            false => address.saturating_sub(1),
+        };
+        let mut frames = Vec::new();
+        if let Result::Ok(mut iter) = debug_info.find_frames(probe).skip_all_loads() {
+            while let Result::Ok(Some(f)) = iter.next() {
+                let function = f
+                    .function
+                    .as_ref()
+                    .and_then(|n| n.raw_name().ok())
+                    .map(|n| n.to_string());
+                let location = f.location.as_ref();
+                frames.push(KernelFrame {
+                    name: match &function {
+                        Some(function) => format!("{} (inlined)", function),
+                        None => unresolved.name.clone(),
+                    },
+                    function: function.or(unresolved.function.clone()),
+                    file: location.and_then(|l| l.file).map(|f| f.to_string()),
+                    line: location.and_then(|l| l.line).map(|l| l as u64),
+                    address: Some(address),
+                    unreliable: frame.unreliable,
+                });
+            }
+        }
+        // The outermost function is the one the kernel named, the others were inlined into it
+        if let Some(outermost) = frames.last_mut() {
+            outermost.name = unresolved.name.clone();
+        }
+        match frames.is_empty() {
+            true => vec![unresolved],
+            false => frames,
+        }
+    }
+
+    // The function containing the address, and the offset into it
+    fn function_at(&self, address: u64) -> Option<(&str, u64)> {
+        let i = self.functions.partition_point(|(a, _)| *a <= address);
+        let (start, name) = self.functions.get(i.checked_sub(1)?)?;
+        Some((name, address - start))
+    }
+}
+
+// `ffffffff81000000 T _stext`, as written by nm. Only functions are kept.
+fn read_system_map(text: &str) -> Functions {
+    text.lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let address = u64::from_str_radix(parts.next()?, 16).ok()?;
+            let kind = parts.next()?;
+            let name = parts.next()?;
+            ["t", "T", "w", "W"]
+                .contains(&kind)
+                .then(|| (address, name.to_string()))
+        })
+        .collect()
+}
+
+fn read_elf(bytes: &[u8]) -> Result<(Functions, Option<DebugInfo>)> {
+    let elf = object::File::parse(bytes)?;
+    let functions = elf
+        .symbols()
+        .filter(|s| s.kind() == SymbolKind::Text && s.address() != 0)
+        .filter_map(|s| Some((s.address(), s.name().ok()?.to_string())))
+        .collect();
+
+    if elf.section_by_name(".debug_info").is_none() {
+        return Ok((functions, None));
+    }
+    let endian = match elf.is_little_endian() {
+        true => RunTimeEndian::Little,
+        false => RunTimeEndian::Big,
+    };
+    let dwarf = gimli::Dwarf::load(|id| -> Result<EndianArcSlice<RunTimeEndian>> {
+        let data = match elf.section_by_name(id.name()) {
+            Some(section) => section.uncompressed_data()?,
+            None => Default::default(),
+        };
+        Ok(EndianArcSlice::new(Arc::from(&*data), endian))
+    })?;
+    let debug_info = addr2line::Context::from_dwarf(dwarf)?;
+    Ok((functions, Some(debug_info)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Built from tests/fixtures/kernel/kernel-symbols.c, with ext4_readdir at 0x401000
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/");
+
+    fn load(name: &str) -> Arc<SymbolTable> {
+        SymbolTable::load(&format!("{}{}", FIXTURES, name)).unwrap()
+    }
+
+    fn frame(message: &str) -> RawFrame {
+        let mut frames = parse_frames(message);
+        assert_eq!(frames.len(), 1, "{}", message);
+        frames.remove(0)
+    }
+
+    #[test]
+    fn frames_are_parsed() {
+        let rip = frame("RIP: 0010:ext4_readdir+0x1a/0x40 [ext4]");
+        assert_eq!(rip.name(), "ext4_readdir+0x1a [ext4]");
+        assert!(rip.exact && !rip.unreliable);
+
+        let unreliable = frame(" ? show_regs+0x6d/0x80");
+        assert_eq!(unreliable.symbol, Some(("show_regs".to_string(), 0x6d)));
+        assert!(unreliable.unreliable && !unreliable.exact);
+
+        let both = frame(" [<ffffffff8100abcd>] iterate_dir+0x17f/0x1c0");
+        assert_eq!(both.address, Some(0xffffffff8100abcd));
+
+        let arm = parse_frames(
+            "[<c0102a4c>] (dump_stack+0x14/0x1c) from [<c0456d30>] (panic+0xc0/0x2a8)",
+        );
+        assert_eq!(arm.len(), 2);
+        assert_eq!(arm[1].name(), "panic+0xc0");
+        assert_eq!(arm[1].address, Some(0xc0456d30));
+
+        assert_eq!(frame("[<ffffffff8100abcd>]").name(), "0xffffffff8100abcd");
+        assert!(parse_frames("no frames here").is_empty());
+    }
+
+    #[test]
+    fn oops_lines() {
+        assert!(is_oops_line("[   12.345678] Call Trace:"));
+        assert!(is_oops_line(" <TASK>"));
+        assert!(is_oops_line("RAX: 0000000000000000 RBX: ffff888003e0c000"));
+        assert!(!is_oops_line("usb 1-1: new device"));
+    }
+
+    #[test]
+    fn frames_are_resolved_with_a_system_map() {
+        let table = load("kernel-symbols.map");
+        assert!(table.debug_info.is_none());
+        let resolved = table.resolve(&frame("[<0000000000401034>]"));
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].name, "iterate_dir+0x4");
+        assert_eq!(resolved[0].function.as_deref(), Some("iterate_dir"));
+        assert_eq!(resolved[0].file, None);
+
+        let resolved = table.resolve(&frame("iterate_dir+0x5/0x15"));
+        assert_eq!(resolved[0].address, Some(0x401035));
+        // Modules are not in the kernel's symbols
+        let resolved = table.resolve(&frame("iterate_dir+0x5/0x15 [ext4]"));
+        assert_eq!(resolved[0].address, None);
+        let resolved = table.resolve(&frame("iterate_dir+0xffffffffffffffff/0x15"));
+        assert_eq!(resolved[0].address, None);
+    }
+
+    #[test]
+    fn inlined_functions_are_resolved_with_debug_info() {
+        let table = load("kernel-symbols.elf");
+        assert!(table.debug_info.is_some());
+
+        // The faulting instruction is inside `bump`, which was inlined into ext4_readdir
+        let resolved = table.resolve(&frame("RIP: 0010:ext4_readdir+0x14/0x2d"));
+        let frames: Vec<_> = resolved
+            .iter()
+            .map(|f| (f.name.as_str(), f.function.as_deref(), f.line))
+            .collect();
+        assert_eq!(
+            frames,
+            [
+                ("bump (inlined)", Some("bump"), Some(12)),
+                ("ext4_readdir+0x14", Some("ext4_readdir"), Some(17)),
+            ]
+        );
+        assert!(resolved[0]
+            .file
+            .as_deref()
+            .is_some_and(|f| f.ends_with("kernel-symbols.c")));
+
+        // A return address is looked up at the call before it
+        let resolved = table.resolve(&frame(" iterate_dir+0x5/0x15"));
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].line, Some(23));
+    }
+}
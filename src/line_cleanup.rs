@@ -0,0 +1,92 @@
+use std::borrow::Cow;
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+
+/*
+Turns a line captured from a terminal into what the terminal would show:
+ANSI escape sequences (colors, cursor movement, titles) are removed,
+a carriage return starts overwriting the line from the start, as progress
+bars do, a backspace erases the character before it, and any other control
+characters are dropped. Tabs are kept.
+*/
+pub fn clean_line(line: &str) -> Cow<'_, str> {
+    if !line.chars().any(|c| c.is_control() && c != '\t') {
+        return Cow::Borrowed(line);
+    }
+
+    let mut shown: Vec<char> = Vec::with_capacity(line.len());
+    let mut cursor: usize = 0;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ESC => match chars.next() {
+                // CSI, e.g. `ESC[1;31m`, ends with a byte in @ to ~
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC, e.g. a window title, ends with BEL or `ESC\`
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == BEL || (c == ESC && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                // Anything else is a two character sequence
+                _ => {}
+            },
+            '\r' => cursor = 0,
+            '\x08' => cursor = cursor.saturating_sub(1),
+            '\t' => put(&mut shown, &mut cursor, c),
+            c if c.is_control() => {}
+            c => put(&mut shown, &mut cursor, c),
+        }
+    }
+    Cow::Owned(shown.into_iter().collect())
+}
+
+fn put(shown: &mut Vec<char>, cursor: &mut usize, c: char) {
+    match shown.get_mut(*cursor) {
+        Some(old) => *old = c,
+        None => shown.push(c),
+    }
+    *cursor += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_lines_are_borrowed() {
+        assert!(matches!(clean_line("a\tplain line"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn escapes_are_removed() {
+        assert_eq!(
+            clean_line("\x1b[1;31mERROR\x1b[0m disk full"),
+            "ERROR disk full"
+        );
+        assert_eq!(clean_line("\x1b]0;title\x07ready"), "ready");
+        assert_eq!(clean_line("\x1b]0;title\x1b\\ready"), "ready");
+        assert_eq!(clean_line("\x1b7saved\x1b8"), "saved");
+        // A sequence cut short ends the line
+        assert_eq!(clean_line("done\x1b[1;3"), "done");
+    }
+
+    #[test]
+    fn lines_are_overwritten_like_a_terminal() {
+        assert_eq!(clean_line("loading 10%\rloading 100%"), "loading 100%");
+        assert_eq!(clean_line("loading 100%\rdone"), "doneing 100%");
+        assert_eq!(clean_line("crlf line\r"), "crlf line");
+        assert_eq!(clean_line("typo\x08\x08po"), "typo");
+        assert_eq!(clean_line("\x08\x08start"), "start");
+        assert_eq!(clean_line("bell\x07 and\x00 nul"), "bell and nul");
+    }
+}
@@ -0,0 +1,478 @@
+use anyhow::*;
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::log_level::LogLevel;
+use crate::log_search::{parse_line_number, LogLineSearch};
+
+/// The fields Retread knows how to use, that can be mapped from a structured log entry.
+pub const KNOWN_FIELDS: [&str; 8] = [
+    "message",
+    "file",
+    "line",
+    "func",
+    "level",
+    "timestamp",
+    "thread",
+    "pid",
+];
+
+/// How each line of the log is dissected into a `LogLineSearch`.
+#[derive(Clone, Debug)]
+pub enum LogFormat {
+    /// Regexes with named capture groups, tried in order until one matches.
+    Regex(Vec<Regex>),
+    /// One JSON object per line, with fields picked out using JSON pointers.
+    Json(JsonFields),
+    /// `key=value` pairs, as emitted by logfmt style loggers.
+    Logfmt(LogfmtFields),
+}
+
+impl LogFormat {
+    pub fn parse(&self, log_line: &str) -> Result<LogLineSearch> {
+        let search = match self {
+            LogFormat::Regex(patterns) => patterns
+                .iter()
+                .enumerate()
+                .find_map(|(i, pattern)| {
+                    let search = LogLineSearch::new(pattern, log_line).ok()?;
+                    Some(LogLineSearch {
+                        pattern: Some(i),
+                        ..search
+                    })
+                })
+                .context(format!("No log_pattern matched line: {}", log_line))?,
+            LogFormat::Json(fields) => fields.parse(log_line),
+            LogFormat::Logfmt(fields) => fields.parse(log_line),
+        };
+        Ok(normalize_level(search))
+    }
+}
+
+// Puts every logger's levels on the same scale, e.g. `W` and `warning` both become WARN.
+// Syslog lines only have a priority, which the level is taken from.
+fn normalize_level(mut search: LogLineSearch) -> LogLineSearch {
+    let level = search.fields.iter_mut().find(|(f, _)| f == "level");
+    match level {
+        Some((_, value)) => {
+            if let Some(level) = LogLevel::parse(value) {
+                *value = level.to_string();
+            }
+        }
+        None => {
+            let priority = search.fields.iter().find(|(f, _)| f == "priority");
+            if let Some(level) = priority.and_then(|(_, p)| LogLevel::from_priority(p)) {
+                search.fields.insert(0, ("level".to_string(), level.to_string()));
+            }
+        }
+    }
+    search
+}
+
+/// Maps the known fields to JSON pointers, e.g. `"message": "/fields/message"`.
+/// Unmapped fields default to a top level key of the same name, unless a default is given.
+#[derive(Clone, Debug)]
+pub struct JsonFields {
+    pointers: Vec<(&'static str, String)>,
+}
+
+impl JsonFields {
+    pub fn new(mapping: Option<&Map<String, Value>>) -> Result<Self> {
+        JsonFields::with_defaults(&[], mapping)
+    }
+
+    pub fn with_defaults(
+        defaults: &[(&str, &'static str)],
+        mapping: Option<&Map<String, Value>>,
+    ) -> Result<Self> {
+        check_mapping(mapping)?;
+
+        let pointers: Result<Vec<_>> = KNOWN_FIELDS
+            .iter()
+            .map(|field| {
+                let default = defaults.iter().find(|(f, _)| f == field).map(|(_, p)| *p);
+                let pointer = match mapping.and_then(|m| m.get(*field)) {
+                    Some(p) => p
+                        .as_str()
+                        .context(format!("Log field [{}] is not a valid string", field))?,
+                    None => default.unwrap_or(field),
+                };
+                // Allow plain keys as a shorthand for top level pointers
+                let pointer = match pointer.starts_with('/') {
+                    true => pointer.to_string(),
+                    false => format!("/{}", pointer.replace('~', "~0").replace('/', "~1")),
+                };
+                Ok((*field, pointer))
+            })
+            .collect();
+
+        Ok(JsonFields {
+            pointers: pointers?,
+        })
+    }
+
+    fn pointer(&self, field: &str) -> &str {
+        self.pointers
+            .iter()
+            .find(|(f, _)| *f == field)
+            .map(|(_, p)| p.as_str())
+            .unwrap()
+    }
+
+    /*
+    Lines that are not JSON objects are kept as plain messages, so a stray
+    panic or print in between structured logs is still searchable.
+    */
+    pub fn parse(&self, log_line: &str) -> LogLineSearch {
+        let object = match serde_json::from_str::<Value>(log_line) {
+            Result::Ok(v @ Value::Object(_)) => v,
+            _ => return LogLineSearch::plain(log_line),
+        };
+
+        let get = |field: &str| object.pointer(self.pointer(field)).map(value_to_string);
+
+        // level, timestamp, thread and pid are shown first, under their own names
+        let mut fields: Vec<_> = ["level", "timestamp", "thread", "pid"]
+            .iter()
+            .filter_map(|f| get(f).map(|v| (f.to_string(), v)))
+            .collect();
+        let mapped: Vec<_> = self.pointers.iter().map(|(_, p)| p.as_str()).collect();
+        flatten("", "", &object, &mapped, &mut fields);
+
+        LogLineSearch {
+            message: get("message").unwrap_or_default().trim().to_string(),
+            func: get("func"),
+            file: get("file"),
+            line: get("line").and_then(|l| parse_line_number(&l)),
+            fields,
+            ..Default::default()
+        }
+    }
+}
+
+/// Maps the known fields to logfmt keys.
+/// Unmapped fields are looked up using the keys commonly used for them.
+#[derive(Clone, Debug)]
+pub struct LogfmtFields {
+    keys: Vec<(&'static str, Vec<String>)>,
+}
+
+impl LogfmtFields {
+    pub fn new(mapping: Option<&Map<String, Value>>) -> Result<Self> {
+        check_mapping(mapping)?;
+
+        let keys: Result<Vec<_>> = KNOWN_FIELDS
+            .iter()
+            .map(|field| {
+                let keys = match mapping.and_then(|m| m.get(*field)) {
+                    Some(key) => vec![key
+                        .as_str()
+                        .context(format!("Log field [{}] is not a valid string", field))?
+                        .to_string()],
+                    None => well_known_keys(field)
+                        .iter()
+                        .map(|k| k.to_string())
+                        .collect(),
+                };
+                Ok((*field, keys))
+            })
+            .collect();
+
+        Ok(LogfmtFields { keys: keys? })
+    }
+
+    fn keys(&self, field: &str) -> &[String] {
+        self.keys
+            .iter()
+            .find(|(f, _)| *f == field)
+            .map(|(_, k)| k.as_slice())
+            .unwrap()
+    }
+
+    pub fn parse(&self, log_line: &str) -> LogLineSearch {
+        let mut pairs = parse_logfmt(log_line);
+        if !pairs.iter().any(|(_, v)| v.is_some()) {
+            return LogLineSearch::plain(log_line);
+        }
+
+        let mut take = |field: &str| {
+            let keys = self.keys(field);
+            let index = pairs.iter().position(|(k, _)| keys.contains(k))?;
+            pairs.remove(index).1
+        };
+
+        let message = take("message");
+        let mut file = take("file");
+        let mut line = take("line").and_then(|l| parse_line_number(&l));
+        let func = take("func");
+        let mut fields: Vec<_> = ["level", "timestamp", "thread", "pid"]
+            .iter()
+            .filter_map(|f| take(f).map(|v| (f.to_string(), v)))
+            .collect();
+
+        // Callers are often logged as a single `file:line` value
+        if let Some(f) = file.clone() {
+            if let Some((path, l)) = f.rsplit_once(':') {
+                if let Result::Ok(l) = l.parse::<usize>() {
+                    file = Some(path.to_string());
+                    line = line.or(Some(l).filter(|l| *l > 0));
+                }
+            }
+        }
+
+        fields.extend(
+            pairs
+                .into_iter()
+                .map(|(k, v)| (k, v.unwrap_or_else(|| "true".to_string()))),
+        );
+
+        // Without a message, the line itself is the best we have to search for
+        LogLineSearch {
+            message: message.unwrap_or_else(|| log_line.to_string()).trim().to_string(),
+            func,
+            file,
+            line,
+            fields,
+            ..Default::default()
+        }
+    }
+}
+
+fn well_known_keys(field: &str) -> &'static [&'static str] {
+    match field {
+        "message" => &["msg", "message"],
+        "file" => &["file", "caller", "source", "src"],
+        "line" => &["line", "lineno"],
+        "func" => &["func", "function", "fn"],
+        "level" => &["level", "lvl", "severity"],
+        "timestamp" => &["ts", "time", "timestamp", "t"],
+        "thread" => &["thread", "tid", "goroutine"],
+        "pid" => &["pid"],
+        _ => &[],
+    }
+}
+
+/*
+Splits a logfmt line into its pairs, e.g.
+level=info msg="conn opened" file=tcp.go:42 debug
+gives us [(level, info), (msg, conn opened), (file, tcp.go:42), (debug, None)]
+Values can be quoted, with backslash escapes. Keys without a value are kept as flags.
+*/
+pub fn parse_logfmt(text: &str) -> Vec<(String, Option<String>)> {
+    let mut pairs = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return pairs;
+        }
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=') {
+            key.push(c);
+        }
+        if chars.next_if_eq(&'=').is_none() {
+            if !key.is_empty() {
+                pairs.push((key, None));
+            }
+            continue;
+        }
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some(c) => value.push(c),
+                        None => {}
+                    },
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                value.push(c);
+            }
+        }
+
+        if !key.is_empty() {
+            pairs.push((key, Some(value)));
+        }
+    }
+}
+
+fn check_mapping(mapping: Option<&Map<String, Value>>) -> Result<()> {
+    if let Some(mapping) = mapping {
+        if let Some(unknown) = mapping.keys().find(|k| !KNOWN_FIELDS.contains(&k.as_str())) {
+            return Err(anyhow!(
+                "Unknown log field [{}], expected one of {:?}",
+                unknown,
+                KNOWN_FIELDS
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+// Collect every value that is not mapped to a known field, named by its path
+fn flatten(
+    pointer: &str,
+    name: &str,
+    value: &Value,
+    mapped: &[&str],
+    out: &mut Vec<(String, String)>,
+) {
+    if mapped.contains(&pointer) {
+        return;
+    }
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let child_pointer =
+                    format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+                let child_name = match name.is_empty() {
+                    true => key.clone(),
+                    false => format!("{}.{}", name, key),
+                };
+                flatten(&child_pointer, &child_name, value, mapped, out);
+            }
+        }
+        value => out.push((name.to_string(), value_to_string(value))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(line: &str) -> LogLineSearch {
+        LogFormat::Json(JsonFields::new(None).unwrap())
+            .parse(line)
+            .unwrap()
+    }
+
+    fn logfmt(line: &str) -> LogLineSearch {
+        LogFormat::Logfmt(LogfmtFields::new(None).unwrap())
+            .parse(line)
+            .unwrap()
+    }
+
+    fn field<'a>(search: &'a LogLineSearch, name: &str) -> Option<&'a str> {
+        search
+            .fields
+            .iter()
+            .find(|(f, _)| f == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn json_fields_are_picked_out() {
+        let search = json(
+            r#"{"message": " conn opened ", "file": "src/net.rs", "line": 42, "level": "warning", "span": {"id": 3}}"#,
+        );
+        assert_eq!(search.message, "conn opened");
+        assert_eq!(search.file.as_deref(), Some("src/net.rs"));
+        assert_eq!(search.line, Some(42));
+        assert_eq!(field(&search, "level"), Some("WARN"));
+        assert_eq!(field(&search, "span.id"), Some("3"));
+        assert_eq!(field(&search, "message"), None);
+    }
+
+    #[test]
+    fn json_fields_can_be_mapped_to_pointers() {
+        let mapping = serde_json::json!({"message": "/fields/msg", "line": "lineno"});
+        let fields = JsonFields::new(mapping.as_object()).unwrap();
+        let search = LogFormat::Json(fields)
+            .parse(r#"{"fields": {"msg": "hello"}, "lineno": "7"}"#)
+            .unwrap();
+        assert_eq!(search.message, "hello");
+        assert_eq!(search.line, Some(7));
+
+        let mapping = serde_json::json!({"msg": "/fields/msg"});
+        assert!(JsonFields::new(mapping.as_object()).is_err());
+    }
+
+    #[test]
+    fn lines_that_are_not_json_are_plain_messages() {
+        let search = json("thread 'main' panicked at src/main.rs:3:5");
+        assert_eq!(search.message, "thread 'main' panicked at src/main.rs:3:5");
+        assert_eq!(search.line, None);
+        assert!(search.fields.is_empty());
+    }
+
+    #[test]
+    fn line_zero_is_no_line() {
+        assert_eq!(json(r#"{"message": "x", "line": 0}"#).line, None);
+        assert_eq!(logfmt("msg=x line=0").line, None);
+
+        let search = logfmt("msg=x file=main.go:0");
+        assert_eq!(search.file.as_deref(), Some("main.go"));
+        assert_eq!(search.line, None);
+
+        let pattern = Regex::new(r"(?P<file>\w+\.c):(?P<line>\d+) (?P<message>.*)").unwrap();
+        let search = LogFormat::Regex(vec![pattern]).parse("app.c:0 x").unwrap();
+        assert_eq!(search.line, None);
+    }
+
+    #[test]
+    fn logfmt_callers_are_split_into_file_and_line() {
+        let search = logfmt(
+            r#"time=2024-01-01T10:00:00Z level=info msg="conn opened" caller=tcp.go:42 id=7 debug"#,
+        );
+        assert_eq!(search.message, "conn opened");
+        assert_eq!(search.file.as_deref(), Some("tcp.go"));
+        assert_eq!(search.line, Some(42));
+        assert_eq!(field(&search, "level"), Some("INFO"));
+        assert_eq!(field(&search, "timestamp"), Some("2024-01-01T10:00:00Z"));
+        assert_eq!(field(&search, "id"), Some("7"));
+        assert_eq!(field(&search, "debug"), Some("true"));
+    }
+
+    #[test]
+    fn logfmt_values_can_be_quoted() {
+        assert_eq!(
+            parse_logfmt(r#"a=1 b="two words" c="say \"hi\"\n" flag d="#),
+            vec![
+                ("a".to_string(), Some("1".to_string())),
+                ("b".to_string(), Some("two words".to_string())),
+                ("c".to_string(), Some("say \"hi\"\n".to_string())),
+                ("flag".to_string(), None),
+                ("d".to_string(), Some(String::new())),
+            ]
+        );
+    }
+
+    #[test]
+    fn patterns_are_tried_in_order() {
+        let patterns = vec![
+            Regex::new(r"^\[boot\] (?P<message>.*)").unwrap(),
+            Regex::new(r"^(?P<level>[A-Z]) (?P<message>.*)").unwrap(),
+        ];
+        let format = LogFormat::Regex(patterns);
+        assert_eq!(format.parse("[boot] loading").unwrap().pattern, Some(0));
+
+        let search = format.parse("W low memory").unwrap();
+        assert_eq!(search.pattern, Some(1));
+        assert_eq!(search.message, "low memory");
+        assert_eq!(field(&search, "level"), Some("WARN"));
+
+        assert!(format.parse("something else").is_err());
+    }
+
+    #[test]
+    fn syslog_priorities_become_levels() {
+        let pattern = Regex::new(r"^<(?P<priority>\d+)>(?P<message>.*)").unwrap();
+        let search = LogFormat::Regex(vec![pattern]).parse("<11>disk full").unwrap();
+        assert_eq!(field(&search, "level"), Some("ERROR"));
+    }
+}
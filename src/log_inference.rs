@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::line_cleanup::clean_line;
+use crate::log_preset::{LogPreset, PRESETS};
+use crate::text_encoding::TextEncoding;
+
+const SAMPLE_LINES: usize = 200;
+const MAX_PREFIX_ELEMENTS: usize = 6;
+/// A preset has to match at least this share of the sample to be picked
+const MIN_PRESET_COVERAGE: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Element {
+    BracketFileLine,
+    BracketTimestamp,
+    BracketLevel,
+    Timestamp,
+    Level,
+    FileLine,
+    Bracket,
+}
+
+const TIMESTAMP: &str = r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?|\d{2}:\d{2}:\d{2}(?:[.,]\d+)?";
+const LEVEL: &str = r"(?i:TRACE|DEBUG|INFO|NOTICE|WARN|WARNING|ERROR|ERR|CRIT|CRITICAL|FATAL)";
+const FILE_LINE: &str = r"(?P<file>[\w./\\-]+\.\w+):(?P<line>\d+)";
+const SEPARATOR: &str = r"[\s:|-]*";
+
+// The order matters, as the first element that matches is used
+const ELEMENTS: [Element; 7] = [
+    Element::BracketFileLine,
+    Element::BracketTimestamp,
+    Element::BracketLevel,
+    Element::Timestamp,
+    Element::Level,
+    Element::FileLine,
+    Element::Bracket,
+];
+
+impl Element {
+    fn pattern(&self) -> String {
+        match self {
+            Element::BracketFileLine => format!(r"\[{}\]", FILE_LINE),
+            // Uptime stamps like the kernel's [   12.345678] are only recognized in brackets
+            Element::BracketTimestamp => {
+                format!(r"\[\s*(?P<timestamp>{}|\d+\.\d+)\s*\]", TIMESTAMP)
+            }
+            Element::BracketLevel => format!(r"[\[<](?P<level>{})[\]>]", LEVEL),
+            Element::Timestamp => format!(r"(?P<timestamp>{})", TIMESTAMP),
+            Element::Level => format!(r"(?P<level>{})\b", LEVEL),
+            Element::FileLine => FILE_LINE.to_string(),
+            Element::Bracket => r"\[(?P<field>[^\]]*)\]".to_string(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref ELEMENT_REGEXES: Vec<(Element, Regex)> = ELEMENTS
+        .iter()
+        .map(|e| (*e, Regex::new(&format!("^(?:{})", e.pattern())).unwrap()))
+        .collect();
+    static ref SEPARATOR_REGEX: Regex = Regex::new(&format!("^{}", SEPARATOR)).unwrap();
+}
+
+/// The pattern we settled on, and how much of the sample it matched.
+#[derive(Clone, Debug)]
+pub struct Inference {
+    pub pattern: String,
+    pub preset: Option<&'static LogPreset>,
+    pub coverage: f32,
+}
+
+/// Samples the start of a log file, and finds a log_pattern for it.
+pub fn infer_log_pattern(
+    path: &str,
+    encoding: TextEncoding,
+    clean_lines: bool,
+) -> Result<Inference> {
+    let mut file = File::open(path).context(format!("Unable to open {}", path))?;
+    let mut decoder = encoding.decoder();
+    let mut buf = [0; 8192];
+    let mut sample: Vec<String> = Vec::new();
+    while sample.len() < SAMPLE_LINES {
+        let read = file.read(&mut buf)?;
+        let lines = decoder.push(&buf[..read], read == 0);
+        sample.extend(
+            lines
+                .iter()
+                .map(|l| match clean_lines {
+                    true => clean_line(l).trim_end().to_string(),
+                    false => l.trim_end().to_string(),
+                })
+                .filter(|l| !l.is_empty()),
+        );
+        if read == 0 {
+            break;
+        }
+    }
+    sample.truncate(SAMPLE_LINES);
+    if sample.is_empty() {
+        return Err(anyhow!(
+            "Missing log_pattern, and {} is empty so it can not be inferred",
+            path
+        ));
+    }
+
+    let inference = infer(&sample);
+    match inference.preset {
+        Some(preset) => info!(
+            "No log_pattern given, using the {} preset which matches {:.0}% of the sampled lines. Add \"log_preset\": \"{}\" to your launch config to use it by default.",
+            preset.name,
+            inference.coverage * 100.0,
+            preset.name
+        ),
+        None => info!(
+            "No log_pattern given, inferred {} which matches {:.0}% of the sampled lines. Add \"log_pattern\": {} to your launch config to use it by default.",
+            inference.pattern,
+            inference.coverage * 100.0,
+            serde_json::to_string(&inference.pattern)?
+        ),
+    }
+    Ok(inference)
+}
+
+fn coverage(pattern: &Regex, sample: &[String]) -> f32 {
+    sample.iter().filter(|l| pattern.is_match(l)).count() as f32 / sample.len() as f32
+}
+
+pub fn infer(sample: &[String]) -> Inference {
+    let best_preset = PRESETS
+        .iter()
+        .map(|p| (p, coverage(&Regex::new(p.pattern).unwrap(), sample)))
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+    if let Some((preset, coverage)) = best_preset {
+        if coverage >= MIN_PRESET_COVERAGE {
+            return Inference {
+                pattern: preset.pattern.to_string(),
+                preset: Some(preset),
+                coverage,
+            };
+        }
+    }
+
+    let pattern = build_pattern(&common_prefix(sample));
+    Inference {
+        coverage: coverage(&Regex::new(&pattern).unwrap(), sample),
+        pattern,
+        preset: None,
+    }
+}
+
+// The elements found at the start of a line, before the message
+fn prefix_of(line: &str) -> Vec<Element> {
+    let mut rest = line;
+    let mut prefix = Vec::new();
+    while prefix.len() < MAX_PREFIX_ELEMENTS {
+        rest = &rest[SEPARATOR_REGEX.find(rest).map_or(0, |m| m.end())..];
+        let found = ELEMENT_REGEXES
+            .iter()
+            .find_map(|(e, r)| r.find(rest).map(|m| (*e, m.end())));
+        match found {
+            Some((element, end)) if end > 0 => {
+                prefix.push(element);
+                rest = &rest[end..];
+            }
+            _ => break,
+        }
+    }
+    prefix
+}
+
+/*
+Lines of the same log rarely share every element, e.g. a continuation line
+has no prefix at all. So we pick the prefix shared by most of the sample,
+preferring the longer one when it is a tie, and then the one seen first,
+so the same log always gives the same pattern.
+*/
+fn common_prefix(sample: &[String]) -> Vec<Element> {
+    // How many lines have each prefix, and the first line that has it
+    let mut counts: HashMap<Vec<Element>, (usize, usize)> = HashMap::new();
+    for (i, line) in sample.iter().enumerate() {
+        counts.entry(prefix_of(line)).or_insert((0, i)).0 += 1;
+    }
+    counts
+        .into_iter()
+        .max_by(|(a, (a_count, a_first)), (b, (b_count, b_first))| {
+            a_count
+                .cmp(b_count)
+                .then(a.len().cmp(&b.len()))
+                .then(b_first.cmp(a_first))
+        })
+        .map(|(prefix, _)| prefix)
+        .unwrap_or_default()
+}
+
+fn build_pattern(prefix: &[Element]) -> String {
+    let mut pattern = "^".to_string();
+    let mut used: Vec<&str> = Vec::new();
+    let mut fields = 0;
+    for element in prefix {
+        let mut element_pattern = element.pattern();
+        if *element == Element::Bracket {
+            fields += 1;
+            element_pattern = element_pattern.replace("(?P<field>", &format!("(?P<field{}>", fields));
+        }
+        // Each group can only be captured once, so repeated ones are matched but not captured
+        for name in ["timestamp", "level", "file", "line"] {
+            let group = format!("(?P<{}>", name);
+            if element_pattern.contains(&group) {
+                if used.contains(&name) {
+                    element_pattern = element_pattern.replace(&group, "(?:");
+                } else {
+                    used.push(name);
+                }
+            }
+        }
+        pattern.push_str(&element_pattern);
+        pattern.push_str(SEPARATOR);
+    }
+    pattern.push_str("(?P<message>.*)$");
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn prefixes_are_made_of_known_elements() {
+        assert_eq!(
+            prefix_of("2024-01-01 10:00:00.123 [WARN] [main.c:12] disk low"),
+            vec![
+                Element::Timestamp,
+                Element::BracketLevel,
+                Element::BracketFileLine
+            ]
+        );
+        assert_eq!(
+            prefix_of("[   12.345678] usb 1-1: new device"),
+            vec![Element::BracketTimestamp]
+        );
+        assert_eq!(prefix_of("just a message"), Vec::new());
+    }
+
+    #[test]
+    fn ties_go_to_the_prefix_seen_first() {
+        let lines = [
+            "[net] conn opened",
+            "ERROR conn lost",
+            "[net] conn opened",
+            "ERROR conn lost",
+        ];
+        for _ in 0..20 {
+            assert_eq!(common_prefix(&sample(&lines)), vec![Element::Bracket]);
+        }
+        let reversed: Vec<_> = lines.iter().rev().copied().collect();
+        for _ in 0..20 {
+            assert_eq!(common_prefix(&sample(&reversed)), vec![Element::Level]);
+        }
+    }
+
+    #[test]
+    fn inferred_patterns_capture_the_prefix() {
+        let inference = infer(&sample(&[
+            "2024-01-01 10:00:00 INFO src/net.rs:42 conn opened",
+            "2024-01-01 10:00:01 WARN src/net.rs:57 conn slow",
+            "    at continuation line",
+        ]));
+        assert_eq!(inference.preset, None);
+        let pattern = Regex::new(&inference.pattern).unwrap();
+        let captures = pattern
+            .captures("2024-01-01 10:00:01 WARN src/net.rs:57 conn slow")
+            .unwrap();
+        assert_eq!(&captures["timestamp"], "2024-01-01 10:00:01");
+        assert_eq!(&captures["level"], "WARN");
+        assert_eq!(&captures["file"], "src/net.rs");
+        assert_eq!(&captures["line"], "57");
+        assert_eq!(&captures["message"], "conn slow");
+        assert!((inference.coverage - 2.0 / 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn repeated_elements_are_only_captured_once() {
+        let pattern = build_pattern(&[Element::Timestamp, Element::Timestamp, Element::Bracket]);
+        let captures = Regex::new(&pattern)
+            .unwrap()
+            .captures("10:00:00 10:00:01 [worker] started")
+            .unwrap();
+        assert_eq!(&captures["timestamp"], "10:00:00");
+        assert_eq!(&captures["field1"], "worker");
+        assert_eq!(&captures["message"], "started");
+    }
+}
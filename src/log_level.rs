@@ -0,0 +1,73 @@
+use std::fmt;
+
+use anyhow::*;
+
+/// The severity of a log entry, with every logger's spelling mapped onto one scale.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl LogLevel {
+    /*
+    Accepts the names used by most loggers, their single letter forms
+    (glog, logcat, esp-idf), Zephyr's three letter forms, and syslog's
+    numeric severities, where 0 is the most severe.
+    */
+    pub fn parse(level: &str) -> Option<Self> {
+        let level = level.trim();
+        if let Result::Ok(severity) = level.parse::<u8>() {
+            return match severity {
+                0..=2 => Some(LogLevel::Fatal),
+                3 => Some(LogLevel::Error),
+                4 => Some(LogLevel::Warn),
+                5 | 6 => Some(LogLevel::Info),
+                7 => Some(LogLevel::Debug),
+                _ => None,
+            };
+        }
+        match level.to_ascii_lowercase().as_str() {
+            "trace" | "trc" | "verbose" | "v" | "t" => Some(LogLevel::Trace),
+            "debug" | "dbg" | "d" => Some(LogLevel::Debug),
+            "info" | "inf" | "i" | "notice" | "information" => Some(LogLevel::Info),
+            "warn" | "wrn" | "w" | "warning" => Some(LogLevel::Warn),
+            "error" | "err" | "e" => Some(LogLevel::Error),
+            "fatal" | "f" | "crit" | "critical" | "alert" | "emerg" | "emergency" | "panic" => {
+                Some(LogLevel::Fatal)
+            }
+            _ => None,
+        }
+    }
+
+    /// The severity held by a syslog `<priority>`, which is `facility * 8 + severity`.
+    pub fn from_priority(priority: &str) -> Option<Self> {
+        let priority: u32 = priority.trim().parse().ok()?;
+        LogLevel::parse(&(priority % 8).to_string())
+    }
+
+    pub fn new(level: &str) -> Result<Self> {
+        LogLevel::parse(level).context(format!(
+            "Unknown log level [{}], expected one of TRACE, DEBUG, INFO, WARN, ERROR or FATAL",
+            level
+        ))
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogLevel::Trace => "TRACE",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+            LogLevel::Fatal => "FATAL",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::*;
+
+/// A named log format for a common logger, so users don't have to write the regex by hand.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogPreset {
+    pub name: &'static str,
+    pub pattern: &'static str,
+    /// How the `timestamp` capture is parsed, see `TimestampFormat::new`
+    pub timestamp_format: Option<&'static str>,
+}
+
+pub const PRESETS: &[LogPreset] = &[
+    // [2023-07-01T10:00:00Z INFO  my_crate::net] message
+    LogPreset {
+        name: "env_logger",
+        pattern: r"^\[(?:(?P<timestamp>\S+)\s+)?(?P<level>TRACE|DEBUG|INFO|WARN|ERROR)\s*(?P<target>[^\]]*)\]\s?(?P<message>.*)$",
+        timestamp_format: Some("rfc3339"),
+    },
+    // 2023-07-01T10:00:00.123456Z  INFO conn{id=1}:req: my_crate::net: src/net.rs:42: message
+    LogPreset {
+        name: "tracing-fmt",
+        pattern: r"^(?P<timestamp>\S+)\s+(?P<level>TRACE|DEBUG|INFO|WARN|ERROR)\s+(?:(?P<spans>(?:[\w:]+\{[^}]*\}:|[\w:]+:)+?)\s)??(?P<target>[\w:]+):\s(?:(?P<file>[^\s:]+):(?P<line>\d+):\s)?(?P<message>.*)$",
+        timestamp_format: Some("rfc3339"),
+    },
+    // 2023-07-01 10:00:00,123 INFO  [main] MyClass:42 - message
+    LogPreset {
+        name: "log4j",
+        pattern: r"^(?P<timestamp>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}[,.]\d{3})\s+(?P<level>TRACE|DEBUG|INFO|WARN|ERROR|FATAL)\s+\[(?P<thread>[^\]]*)\]\s+(?P<class>[\w.$]+)(?::(?P<line>\d+))?\s+-\s(?P<message>.*)$",
+        timestamp_format: None,
+    },
+    // 10:00:00.123 [main] INFO  com.example.App - message
+    LogPreset {
+        name: "logback",
+        pattern: r"^(?P<timestamp>(?:\d{4}-\d{2}-\d{2}[ T])?\d{2}:\d{2}:\d{2}[.,]\d{3})\s+\[(?P<thread>[^\]]*)\]\s+(?P<level>TRACE|DEBUG|INFO|WARN|ERROR)\s+(?P<logger>\S+)\s+-\s(?P<message>.*)$",
+        timestamp_format: None,
+    },
+    // WARNING:root:message, optionally prefixed with the asctime
+    LogPreset {
+        name: "python-logging",
+        pattern: r"^(?:(?P<timestamp>\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2},\d{3})\s)?(?P<level>DEBUG|INFO|WARNING|ERROR|CRITICAL):(?P<logger>[^:]*):(?P<message>.*)$",
+        timestamp_format: Some("%Y-%m-%d %H:%M:%S,%3f"),
+    },
+    // <34>Oct 11 22:14:15 mymachine su[123]: message
+    LogPreset {
+        name: "syslog-rfc3164",
+        pattern: r"^(?:<(?P<priority>\d{1,3})>)?(?P<timestamp>[A-Z][a-z]{2}\s+\d{1,2} \d{2}:\d{2}:\d{2})\s+(?P<host>\S+)\s+(?P<tag>[^:\[\s]+)(?:\[(?P<pid>\d+)\])?:\s?(?P<message>.*)$",
+        timestamp_format: Some("%b %e %H:%M:%S"),
+    },
+    // <165>1 2003-10-11T22:14:15.003Z mymachine evntslog 123 ID47 [exampleSDID@32473 iut="3"] message
+    LogPreset {
+        name: "syslog-rfc5424",
+        pattern: r"^<(?P<priority>\d{1,3})>\d{1,2} (?P<timestamp>\S+) (?P<host>\S+) (?P<app>\S+) (?P<pid>\S+) (?P<msgid>\S+) (?P<structured_data>-|(?:\[(?:[^\]\\]|\\.)*\])+)\s?(?P<message>.*)$",
+        timestamp_format: Some("rfc3339"),
+    },
+    // 07-01 10:00:00.123  1234  5678 I Tag: message
+    LogPreset {
+        name: "android-logcat",
+        pattern: r"^(?P<timestamp>\d{2}-\d{2} \d{2}:\d{2}:\d{2}\.\d{3})\s+(?P<pid>\d+)\s+(?P<thread>\d+)\s+(?P<level>[VDIWEFS])\s+(?P<tag>.*?)\s*:\s(?P<message>.*)$",
+        timestamp_format: Some("%m-%d %H:%M:%S%.3f"),
+    },
+    // [   12.345678] message, optionally with the facility and level from dmesg -x
+    LogPreset {
+        name: "dmesg",
+        pattern: r"^(?:(?P<facility>\w+)\s*:(?P<level>\w+)\s*:\s)?\[\s*(?P<timestamp>\d+\.\d+)\]\s(?P<message>.*)$",
+        timestamp_format: Some("uptime"),
+    },
+    // [00:00:00.123,456] <inf> module: message
+    LogPreset {
+        name: "zephyr",
+        pattern: r"^\[(?P<timestamp>\d{2}:\d{2}:\d{2}\.\d{3},\d{3})\]\s+<(?P<level>err|wrn|inf|dbg)>\s+(?P<module>[^:\s]+):\s(?P<message>.*)$",
+        timestamp_format: Some("uptime"),
+    },
+    // I (1234) wifi: message
+    LogPreset {
+        name: "esp-idf",
+        pattern: r"^(?P<level>[EWIDV]) \((?P<timestamp>[\d:.]+)\) (?P<tag>[^:]+):\s(?P<message>.*)$",
+        timestamp_format: Some("uptime_ms"),
+    },
+    // I0701 10:00:00.123456  1234 file.cc:42] message
+    LogPreset {
+        name: "glog",
+        pattern: r"^(?P<level>[IWEF])(?P<timestamp>\d{4} \d{2}:\d{2}:\d{2}\.\d{6})\s+(?P<thread>\d+)\s+(?P<file>[^:\s]+):(?P<line>\d+)\]\s(?P<message>.*)$",
+        timestamp_format: Some("%m%d %H:%M:%S%.6f"),
+    },
+];
+
+pub fn find_preset(name: &str) -> Result<&'static LogPreset> {
+    PRESETS.iter().find(|p| p.name == name).context(format!(
+        "Unknown log_preset [{}], expected one of {:?}",
+        name,
+        PRESETS.iter().map(|p| p.name).collect::<Vec<_>>()
+    ))
+}
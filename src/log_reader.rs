@@ -0,0 +1,411 @@
+use std::sync::Arc;
+
+use serde_json::{Map, Value};
+
+use crate::defmt_decoder::{DefmtDecoder, DefmtTable};
+use crate::pw_tokenizer::TokenDatabase;
+use crate::text_encoding::{LineDecoder, TextEncoding};
+
+/// Size of the header of a logcat v1 entry, which has no header size field
+const LOGCAT_V1_HEADER: usize = 20;
+/// Sizes of the headers of logcat v2, v3 and v4 entries
+const LOGCAT_HEADERS: [usize; 3] = [20, 24, 28];
+/// Logcat payloads are at most 4068 bytes, depending on the version of Android
+const LOGCAT_MAX_PAYLOAD: usize = 4096;
+
+/// How the bytes of a log are split into entries.
+#[derive(Clone, Debug, Default)]
+pub enum LogReader {
+    /// One entry per line of text
+    #[default]
+    Text,
+    /// `journalctl -o export`, or `-o json` which is passed through as is
+    Journal,
+    /// `adb logcat -B`
+    LogcatBinary,
+    /// defmt frames, decoded with the format strings of the firmware's ELF file
+    Defmt(Arc<DefmtTable>),
+    /// Lines with Base64 pw_tokenizer messages, decoded with a token database
+    PwTokenizer(Arc<TokenDatabase>),
+}
+
+impl LogReader {
+    pub fn decoder(&self, encoding: TextEncoding) -> EntryDecoder {
+        match self {
+            LogReader::Text => EntryDecoder::Text(encoding.decoder()),
+            LogReader::Journal => EntryDecoder::Journal(JournalDecoder::default()),
+            LogReader::LogcatBinary => EntryDecoder::Logcat(LogcatDecoder::default()),
+            LogReader::Defmt(table) => EntryDecoder::Defmt(table.decoder()),
+            LogReader::PwTokenizer(database) => {
+                EntryDecoder::PwTokenizer(encoding.decoder(), database.clone())
+            }
+        }
+    }
+
+    /// Entries decoded with a token database know exactly where in the sources they were logged
+    pub fn is_tokenized(&self) -> bool {
+        matches!(self, LogReader::Defmt(_) | LogReader::PwTokenizer(_))
+    }
+
+    /// Where the known fields are found in the JSON of each entry
+    pub fn json_fields(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            LogReader::Text | LogReader::Defmt(_) | LogReader::PwTokenizer(_) => &[],
+            LogReader::Journal => &[
+                ("message", "MESSAGE"),
+                ("file", "CODE_FILE"),
+                ("line", "CODE_LINE"),
+                ("func", "CODE_FUNC"),
+                ("level", "PRIORITY"),
+                ("timestamp", "__REALTIME_TIMESTAMP"),
+                ("thread", "TID"),
+                ("pid", "_PID"),
+            ],
+            LogReader::LogcatBinary => &[
+                ("message", "MESSAGE"),
+                ("level", "PRIORITY"),
+                ("timestamp", "TIMESTAMP"),
+                ("thread", "TID"),
+                ("pid", "PID"),
+            ],
+        }
+    }
+}
+
+/*
+Turns a stream of bytes into log entries, as they arrive.
+Binary formats are turned into one JSON object per entry, so they can be
+parsed like any other JSON log, and an entry spanning several lines stays
+a single entry.
+*/
+pub enum EntryDecoder {
+    Text(LineDecoder),
+    Journal(JournalDecoder),
+    Logcat(LogcatDecoder),
+    Defmt(DefmtDecoder),
+    PwTokenizer(LineDecoder, Arc<TokenDatabase>),
+}
+
+impl EntryDecoder {
+    /// Decodes the next chunk of the stream, and returns the entries it completed.
+    pub fn push(&mut self, bytes: &[u8], last: bool) -> Vec<String> {
+        match self {
+            EntryDecoder::Text(decoder) => decoder.push(bytes, last),
+            EntryDecoder::Journal(decoder) => decoder.push(bytes, last),
+            EntryDecoder::Logcat(decoder) => decoder.push(bytes, last),
+            EntryDecoder::Defmt(decoder) => decoder.push(bytes, last),
+            EntryDecoder::PwTokenizer(decoder, database) => decoder
+                .push(bytes, last)
+                .iter()
+                .map(|line| database.detokenize(line))
+                .collect(),
+        }
+    }
+
+    /// Whether any invalid characters had to be replaced so far
+    pub fn lossy(&self) -> bool {
+        match self {
+            EntryDecoder::Text(decoder) | EntryDecoder::PwTokenizer(decoder, _) => decoder.lossy(),
+            _ => false,
+        }
+    }
+}
+
+/*
+The journal export format is a list of `KEY=value` lines, with an empty
+line after each entry. Values that are binary, or contain a newline, are
+written as `KEY`, a newline, a little endian u64 length, and the value.
+https://systemd.io/JOURNAL_EXPORT_FORMATS/
+*/
+#[derive(Default)]
+pub struct JournalDecoder {
+    pending: Vec<u8>,
+    entry: Map<String, Value>,
+    /// A field of the entry was corrupt, so the rest of it is skipped
+    skipping: bool,
+}
+
+impl JournalDecoder {
+    fn push(&mut self, bytes: &[u8], last: bool) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+        let mut entries = Vec::new();
+        let mut start = 0;
+        while let Some(end) = self.pending[start..].iter().position(|b| *b == b'\n') {
+            let line = &self.pending[start..start + end];
+            if self.skipping {
+                self.skipping = !line.is_empty();
+                start += end + 1;
+                continue;
+            }
+            // `journalctl -o json` already gives us one object per line
+            if line.starts_with(b"{") && self.entry.is_empty() {
+                entries.push(String::from_utf8_lossy(line).to_string());
+                start += end + 1;
+                continue;
+            }
+            if line.is_empty() {
+                if !self.entry.is_empty() {
+                    entries.push(Value::Object(std::mem::take(&mut self.entry)).to_string());
+                }
+                start += end + 1;
+                continue;
+            }
+            match line.iter().position(|b| *b == b'=') {
+                Some(eq) => {
+                    let key = String::from_utf8_lossy(&line[..eq]).to_string();
+                    let value = String::from_utf8_lossy(&line[eq + 1..]).to_string();
+                    self.entry.insert(key, Value::String(value));
+                    start += end + 1;
+                }
+                None => {
+                    let key = String::from_utf8_lossy(line).to_string();
+                    let size_at = start + end + 1;
+                    let Some(size) = self.pending.get(size_at..size_at + 8) else {
+                        break;
+                    };
+                    let size = u64::from_le_bytes(size.try_into().unwrap());
+                    let value_at = size_at + 8;
+                    // The value is followed by a newline
+                    let value_end = usize::try_from(size)
+                        .ok()
+                        .and_then(|size| value_at.checked_add(size)?.checked_add(1));
+                    let Some(value_end) = value_end else {
+                        warn!(
+                            "Skipping a journal entry, as its {} field has an invalid size of {} bytes",
+                            key, size
+                        );
+                        self.entry.clear();
+                        self.skipping = true;
+                        start = value_at;
+                        continue;
+                    };
+                    let Some(value) = self.pending.get(value_at..value_end) else {
+                        break;
+                    };
+                    let value = String::from_utf8_lossy(&value[..value.len() - 1]).to_string();
+                    self.entry.insert(key, Value::String(value));
+                    start = value_end;
+                }
+            }
+        }
+        self.pending.drain(..start);
+
+        if last && !self.entry.is_empty() {
+            entries.push(Value::Object(std::mem::take(&mut self.entry)).to_string());
+        }
+        if last && !self.pending.is_empty() {
+            warn!(
+                "Ignored the last {} bytes of the journal export, which are not a complete field",
+                self.pending.len()
+            );
+        }
+        entries
+    }
+}
+
+/// Splits `adb logcat -B` output into entries, skipping over anything that is not one
+#[derive(Default)]
+pub struct LogcatDecoder {
+    pending: Vec<u8>,
+    /// How many bytes were skipped since the last valid entry
+    skipped: usize,
+}
+
+impl LogcatDecoder {
+    fn push(&mut self, bytes: &[u8], last: bool) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+        let mut entries = Vec::new();
+        let mut start = 0;
+        loop {
+            match parse_logcat_entry(&self.pending[start..]) {
+                LogcatEntry::Complete(entry, len) => {
+                    self.warn_skipped();
+                    entries.push(entry);
+                    start += len;
+                }
+                LogcatEntry::Incomplete => break,
+                // Look for the next entry one byte further on
+                LogcatEntry::Invalid => {
+                    self.skipped += 1;
+                    start += 1;
+                }
+            }
+        }
+        self.pending.drain(..start);
+
+        if last {
+            self.skipped += std::mem::take(&mut self.pending).len();
+            self.warn_skipped();
+        }
+        entries
+    }
+
+    fn warn_skipped(&mut self) {
+        if self.skipped > 0 {
+            warn!(
+                "Skipped {} bytes of the logcat output that are not valid entries",
+                self.skipped
+            );
+            self.skipped = 0;
+        }
+    }
+}
+
+enum LogcatEntry {
+    /// The entry as JSON, and how many bytes it took
+    Complete(String, usize),
+    /// The rest of the entry has not arrived yet
+    Incomplete,
+    /// The header is not one of a logcat entry
+    Invalid,
+}
+
+/*
+A logcat binary entry is a header, followed by a payload of the priority
+byte, the tag and the message, both NUL terminated.
+The header starts with the payload length and the header size (which is 0
+in v1 entries), followed by pid, tid, seconds and nanoseconds.
+*/
+fn parse_logcat_entry(bytes: &[u8]) -> LogcatEntry {
+    let u16_at = |i: usize| Some(u16::from_le_bytes(bytes.get(i..i + 2)?.try_into().ok()?));
+    let i32_at = |i: usize| i32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+
+    let (Some(payload_len), Some(header_len)) = (u16_at(0), u16_at(2)) else {
+        return LogcatEntry::Incomplete;
+    };
+    let payload_len = payload_len as usize;
+    let header_len = match header_len as usize {
+        0 => LOGCAT_V1_HEADER,
+        len => len,
+    };
+    // The payload holds at least the priority and the end of the tag
+    if !LOGCAT_HEADERS.contains(&header_len) || !(2..=LOGCAT_MAX_PAYLOAD).contains(&payload_len) {
+        return LogcatEntry::Invalid;
+    }
+    let Some(payload) = bytes.get(header_len..header_len + payload_len) else {
+        return LogcatEntry::Incomplete;
+    };
+
+    let priority = match payload[0] {
+        2 => "V",
+        3 => "D",
+        4 => "I",
+        5 => "W",
+        6 => "E",
+        7 => "F",
+        _ => "S",
+    };
+    let mut strings = payload[1..].split(|b| *b == 0);
+    let tag = String::from_utf8_lossy(strings.next().unwrap_or_default());
+    let message = String::from_utf8_lossy(strings.next().unwrap_or_default());
+
+    let entry = serde_json::json!({
+        "TIMESTAMP": format!("{}.{:09}", i32_at(12), i32_at(16)),
+        "PID": i32_at(4).to_string(),
+        "TID": i32_at(8).to_string(),
+        "PRIORITY": priority,
+        "TAG": tag,
+        "MESSAGE": message.trim_end(),
+    });
+    LogcatEntry::Complete(entry.to_string(), header_len + payload_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JOURNAL: &[u8] = include_bytes!("../tests/fixtures/journal.export");
+    const LOGCAT: &[u8] = include_bytes!("../tests/fixtures/logcat.bin");
+
+    fn decode(reader: LogReader, bytes: &[u8]) -> Vec<Value> {
+        let mut decoder = reader.decoder(TextEncoding::default());
+        decoder
+            .push(bytes, true)
+            .iter()
+            .map(|e| serde_json::from_str(e).unwrap())
+            .collect()
+    }
+
+    // Arriving a byte at a time, like a slow pipe, gives the same entries
+    fn decode_bytewise(reader: LogReader, bytes: &[u8]) -> Vec<Value> {
+        let mut decoder = reader.decoder(TextEncoding::default());
+        let mut entries: Vec<_> = bytes
+            .iter()
+            .flat_map(|b| decoder.push(&[*b], false))
+            .collect();
+        entries.extend(decoder.push(&[], true));
+        entries
+            .iter()
+            .map(|e| serde_json::from_str(e).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn journal_exports_are_split_into_entries() {
+        let entries = decode(LogReader::Journal, JOURNAL);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0]["MESSAGE"], "accepted connection from 10.0.0.7");
+        assert_eq!(entries[0]["CODE_LINE"], "42");
+        assert_eq!(entries[0]["__REALTIME_TIMESTAMP"], "1717225200123456");
+        // Multi-line messages are stored as binary fields
+        assert_eq!(
+            entries[1]["MESSAGE"],
+            "slow request took 1532 ms\nquery: SELECT 1"
+        );
+        assert_eq!(entries[1]["CODE_FUNC"], "handle_request");
+        assert_eq!(entries[2]["PRIORITY"], "3");
+        assert_eq!(decode_bytewise(LogReader::Journal, JOURNAL), entries);
+    }
+
+    #[test]
+    fn journal_fields_with_invalid_sizes_skip_their_entry() {
+        let mut bytes = b"MESSAGE=first\n\nCODE_LINE=3\nMESSAGE\n".to_vec();
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+        bytes.extend_from_slice(b"garbage=1\n\nMESSAGE=third\n\n");
+        let entries = decode(LogReader::Journal, &bytes);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["MESSAGE"], "first");
+        assert_eq!(entries[1]["MESSAGE"], "third");
+        assert_eq!(entries[1].get("CODE_LINE"), None);
+    }
+
+    #[test]
+    fn journal_json_is_passed_through() {
+        let entries = decode(
+            LogReader::Journal,
+            b"{\"MESSAGE\": \"hi\"}\n{\"MESSAGE\": \"there\"}\n",
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1]["MESSAGE"], "there");
+    }
+
+    #[test]
+    fn logcat_entries_are_decoded() {
+        let entries = decode(LogReader::LogcatBinary, LOGCAT);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0]["TAG"], "DemoService");
+        assert_eq!(entries[0]["PRIORITY"], "I");
+        assert_eq!(entries[0]["MESSAGE"], "service started on port 8080");
+        assert_eq!(entries[0]["TIMESTAMP"], "1717225200.123000000");
+        assert_eq!(entries[1]["PID"], "1234");
+        assert_eq!(entries[1]["TID"], "1250");
+        assert_eq!(entries[2]["PRIORITY"], "E");
+        assert_eq!(entries[2]["MESSAGE"], "request id=17 failed: timeout");
+        assert_eq!(decode_bytewise(LogReader::LogcatBinary, LOGCAT), entries);
+    }
+
+    #[test]
+    fn logcat_garbage_is_skipped() {
+        let mut bytes = b"--------- beginning of main\n".to_vec();
+        bytes.extend_from_slice(LOGCAT);
+        bytes.extend_from_slice(&[0xff; 7]);
+        bytes.extend_from_slice(LOGCAT);
+        let entries = decode(LogReader::LogcatBinary, &bytes);
+        assert_eq!(entries.len(), 6);
+        assert_eq!(entries[3]["MESSAGE"], "service started on port 8080");
+
+        // A truncated entry at the end is dropped
+        let entries = decode(LogReader::LogcatBinary, &LOGCAT[..LOGCAT.len() - 5]);
+        assert_eq!(entries.len(), 2);
+    }
+}
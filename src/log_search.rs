@@ -1,9 +1,18 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use anyhow::*;
 use rayon::prelude::{ParallelBridge, ParallelIterator};
 use regex::Regex;
 
+use crate::kernel_symbols::SymbolTable;
+use crate::log_format::LogFormat;
+use crate::log_level::LogLevel;
+use crate::log_reader::LogReader;
+use crate::log_source::LogInput;
+use crate::text_encoding::TextEncoding;
+use crate::timestamp::TimestampFormat;
+
 #[derive(Clone, Debug)]
 pub struct LogMatch {
     pub file: String,
@@ -11,15 +20,51 @@ pub struct LogMatch {
     pub score: usize,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct LogLineSearch {
     pub message: String,
     pub func: Option<String>,
     pub file: Option<String>,
     pub line: Option<usize>,
+    /// Any other fields of the log line, kept for display
+    pub fields: Vec<(String, String)>,
+    /// Index of the log_pattern that matched the line
+    pub pattern: Option<usize>,
 }
 
 impl LogLineSearch {
+    /// A line without any structure, where all we know is the message
+    pub fn plain(log_line: &str) -> Self {
+        LogLineSearch {
+            message: log_line.trim().to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The normalized level of the line, if it has one
+    pub fn level(&self) -> Option<LogLevel> {
+        self.fields
+            .iter()
+            .find(|(f, _)| f == "level")
+            .and_then(|(_, l)| LogLevel::parse(l))
+    }
+
+    /// The thread that logged the line, qualified by the process id in multi-process logs
+    pub fn thread(&self) -> Option<String> {
+        let get = |name: &str| {
+            self.fields
+                .iter()
+                .find(|(f, v)| f == name && !v.is_empty() && v != "-")
+                .map(|(_, v)| v.as_str())
+        };
+        match (get("pid"), get("thread")) {
+            (Some(pid), Some(thread)) => Some(format!("{}/{}", pid, thread)),
+            (Some(pid), None) => Some(format!("pid {}", pid)),
+            (None, Some(thread)) => Some(thread.to_string()),
+            (None, None) => None,
+        }
+    }
+
     pub fn new(pattern: &Regex, log_line: &str) -> Result<Self> {
         let captures = pattern
             .captures(log_line)
@@ -40,11 +85,23 @@ impl LogLineSearch {
                 .map(|m| m.as_str().to_string().trim().to_string()),
             line: captures
                 .name("line")
-                .map(|m| m.as_str().to_string().parse().unwrap()),
+                .and_then(|m| parse_line_number(m.as_str())),
+            fields: pattern
+                .capture_names()
+                .flatten()
+                .filter(|n| !["message", "func", "file", "line"].contains(n))
+                .filter_map(|n| Some((n.to_string(), captures.name(n)?.as_str().to_string())))
+                .collect(),
+            pattern: None,
         })
     }
 }
 
+/// Line numbers count from 1, so line 0 is treated like a missing line
+pub fn parse_line_number(text: &str) -> Option<usize> {
+    text.trim().parse().ok().filter(|l| *l > 0)
+}
+
 /*
 This is the search parameter we use.
 we split the two strings up into "tokens".
@@ -80,7 +137,7 @@ fn token_lcs(haystack: &str, needle: &str) -> usize {
 
 fn best_match_in_file(
     contents: &str,
-    filename: &PathBuf,
+    filename: &Path,
     search_options: &LogLineSearch,
 ) -> Option<LogMatch> {
     if let Some(ref func) = search_options.func {
@@ -107,7 +164,7 @@ fn best_match_in_file(
             None
         }
         Some(line) => {
-            let l = contents.lines().nth(line - 1)?;
+            let l = contents.lines().nth(line.checked_sub(1)?)?;
             Some(LogMatch {
                 file: filename.to_str().unwrap().to_string(),
                 line,
@@ -122,21 +179,164 @@ fn best_match_in_file(
 #[derive(Clone, Debug)]
 pub struct LogSearchSettings {
     pub log_file_name: String,
-    pub log_file: String,
-    pub log_pattern: Regex,
+    pub input: LogInput,
+    pub log_format: LogFormat,
+    pub timestamp_format: TimestampFormat,
+    /// Entries below this level are skipped while stepping
+    pub min_level: Option<LogLevel>,
+    pub log_encoding: TextEncoding,
+    pub source_encoding: TextEncoding,
+    /// Strip ANSI escapes and control characters from the log lines
+    pub clean_lines: bool,
+    pub log_reader: LogReader,
+    /// Resolves the frames of kernel backtraces
+    pub symbols: Option<Arc<SymbolTable>>,
     pub include: Vec<String>,
     pub exclude: Vec<String>,
 }
 
+/// Locations known from a token database are exact, and outrank any search
+pub const EXACT_SCORE: usize = usize::MAX;
+
+/*
+Finds where a tokenized entry was logged, without searching for its message.
+defmt entries carry the file and line from the debug info, pw_tokenizer
+entries their format string, which is written verbatim as a string literal
+in the sources.
+*/
+pub fn locate(files: &[(PathBuf, String)], search_options: &LogLineSearch) -> Option<LogMatch> {
+    if let (Some(file), Some(line)) = (&search_options.file, search_options.line) {
+        return Some(LogMatch {
+            file: source_path(files, file)?,
+            line,
+            score: EXACT_SCORE,
+        });
+    }
+
+    let (_, format) = search_options.fields.iter().find(|(f, _)| f == "format")?;
+    let literal = format!(
+        "\"{}\"",
+        format
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+            .replace('\t', "\\t")
+    );
+    files
+        .iter()
+        .filter(|(f, _)| match &search_options.file {
+            Some(file) => same_file(Path::new(file), f),
+            None => true,
+        })
+        .find_map(|(f, contents)| {
+            let line = contents.lines().position(|l| l.contains(&literal))?;
+            Some(LogMatch {
+                file: f.to_str()?.to_string(),
+                line: line + 1,
+                score: EXACT_SCORE,
+            })
+        })
+}
+
+// Debug info has absolute paths, while the include patterns are often relative
+fn same_file(file: &Path, f: &Path) -> bool {
+    file.ends_with(f.strip_prefix(".").unwrap_or(f)) || f.ends_with(file)
+}
+
+/// The source file a path from debug info refers to, among the included files or on disk
+pub fn source_path(files: &[(PathBuf, String)], file: &str) -> Option<String> {
+    let file = Path::new(file);
+    let path = files
+        .iter()
+        .map(|(f, _)| f.as_path())
+        .find(|f| same_file(file, f))
+        .or_else(|| file.is_file().then_some(file))?;
+    Some(path.to_str()?.to_string())
+}
+
 use lazy_static::lazy_static;
 use moka::sync::Cache;
 lazy_static! {
     static ref SEARCH_CACHE: Cache<String, Option<LogMatch>> = Cache::new(10_000);
+    static ref FUNCTION_CACHE: Cache<String, Option<LogMatch>> = Cache::new(10_000);
+}
+
+/*
+A regex for the line defining a function, where `name` is a regex for its
+name. C definitions start a line with the signature, e.g.
+`static void foo(void)`, and do not end in `;` like declarations do.
+Methods of Java and the like are indented, but start with a modifier such
+as `public`.
+*/
+fn function_definition(name: &str) -> Option<Regex> {
+    Regex::new(&format!(
+        concat!(
+            r"^(?:(?:[A-Za-z_][\w\s*()]*[\s*])?{0}\s*\([^;]*$",
+            r#"|\s*(?:pub(?:\([^)]*\))?\s+)?(?:(?:async|const|unsafe|extern\s+"[^"]*")\s+)*fn\s+{0}\b"#,
+            r"|\s*(?:async\s+)?def\s+{0}\s*\(",
+            r"|func\s+(?:\([^)]*\)\s*)?{0}\s*[\[(]",
+            r"|\s+(?:(?:public|private|protected|internal|static|final|synchronized|abstract|override|virtual|async)\s+)+[\w<>\[\],.?\s]*\b{0}\s*\()"
+        ),
+        name
+    ))
+    .ok()
+}
+
+// Finds where a function is defined, for frames we only know the name of
+pub fn find_function(files: &[(PathBuf, String)], name: &str) -> Option<LogMatch> {
+    let cache = FUNCTION_CACHE.clone();
+
+    #[cfg(feature = "test-server")]
+    cache.invalidate_all();
+
+    cache.get_with(name.to_string(), || {
+        // Qualified names, like `Foo::bar` or `com.example.Foo.bar`, are defined as just `bar`
+        let name = name.rsplit([':', '.']).next().unwrap_or(name);
+        let definition = function_definition(&regex::escape(name))?;
+        files.iter().find_map(|(f, contents)| {
+            let line = contents.lines().position(|l| definition.is_match(l))?;
+            Some(LogMatch {
+                file: f.to_str()?.to_string(),
+                line: line + 1,
+                score: EXACT_SCORE,
+            })
+        })
+    })
+}
+
+/// The name of the function the line of the source is in, by the nearest definition above it
+pub fn enclosing_function(contents: &str, line: usize) -> Option<String> {
+    lazy_static! {
+        static ref DEFINITION: Regex = function_definition(r"([A-Za-z_]\w*)").unwrap();
+    }
+    // Statements at the start of a line look like C definitions too
+    const KEYWORDS: [&str; 6] = ["if", "for", "while", "switch", "return", "sizeof"];
+    let lines: Vec<&str> = contents.lines().take(line).collect();
+    lines.iter().rev().find_map(|l| {
+        let captures = DEFINITION.captures(l)?;
+        let name = captures.iter().skip(1).flatten().next()?.as_str();
+        (!KEYWORDS.contains(&name)).then(|| name.to_string())
+    })
+}
+
+// Finds where a span is created from its name, e.g. `info_span!("request")`
+pub fn find_span(files: &[(PathBuf, String)], name: &str) -> Option<LogMatch> {
+    let literal = format!("\"{}\"", name);
+    files.iter().find_map(|(f, contents)| {
+        let line = contents
+            .lines()
+            .position(|l| l.contains(&literal) && l.to_lowercase().contains("span"))?;
+        Some(LogMatch {
+            file: f.to_str()?.to_string(),
+            line: line + 1,
+            score: EXACT_SCORE,
+        })
+    })
 }
 
 pub fn search_files(
     files: &[(PathBuf, String)],
-    log_pattern: &Regex,
+    log_format: &LogFormat,
     log_line: &str,
 ) -> Option<LogMatch> {
     let cache = SEARCH_CACHE.clone();
@@ -145,14 +345,15 @@ pub fn search_files(
     cache.invalidate_all();
 
     cache.get_with(log_line.to_string(), || {
-        let search_options = LogLineSearch::new(log_pattern, log_line).ok()?;
+        let search_options = log_format.parse(log_line).ok()?;
 
         let matches = files
             .iter()
             .par_bridge()
             .filter(|(f, _)| {
+                // Loggers often only give us part of the path, e.g. just the file name
                 if let Some(file) = &search_options.file {
-                    return *f == PathBuf::from(file);
+                    return f.ends_with(Path::new(file));
                 }
                 true
             })
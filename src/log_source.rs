@@ -0,0 +1,457 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+
+use anyhow::*;
+use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
+
+use crate::line_cleanup::clean_line;
+use crate::log_reader::{EntryDecoder, LogReader};
+use crate::log_search::LogSearchSettings;
+use crate::text_encoding::TextEncoding;
+
+#[derive(Clone, Debug)]
+pub struct ProgramSettings {
+    pub program: String,
+    pub args: Vec<String>,
+    pub cwd: Option<String>,
+    pub env: HashMap<String, String>,
+    pub pause_on_breakpoint: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum SocketAddress {
+    Tcp(String),
+    Unix(String),
+}
+
+impl SocketAddress {
+    /// Parses `host:port` (optionally prefixed with `tcp:`) or `unix:/path/to/socket`.
+    pub fn parse(address: &str) -> Self {
+        match address.strip_prefix("unix:") {
+            Some(path) => SocketAddress::Unix(path.to_string()),
+            None => SocketAddress::Tcp(address.trim_start_matches("tcp:").to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SocketSettings {
+    pub address: SocketAddress,
+    /// Listen for incoming connections, instead of connecting to the address
+    pub listen: bool,
+}
+
+/// Where the log lines come from.
+#[derive(Clone, Debug)]
+pub enum LogInput {
+    /// Replay a log file that is read in full at launch.
+    File(String),
+    /// Spawn a program and use its stdout/stderr as the log.
+    /// If a path is given, the captured output is also written there.
+    Program(ProgramSettings, Option<String>),
+    /// Receive lines over a TCP or Unix socket.
+    /// If a path is given, the received lines are also written there.
+    Socket(SocketSettings, Option<String>),
+}
+
+/*
+The lines of the log we are replaying.
+For files, every line is known up front. For live sources, lines are pushed
+from reader threads into a channel, and pulled into `lines` by `poll`.
+Once every reader has hung up, the source is no longer live and behaves
+just like a file.
+Lines are cleaned up as they are added, and the original of any line that
+changed is kept in `raw`.
+*/
+#[derive(Clone, Debug)]
+pub struct LogSource {
+    lines: Vec<String>,
+    raw: HashMap<usize, String>,
+    clean_lines: bool,
+    incoming: Option<Receiver<String>>,
+    path: Option<String>,
+    program: Option<RunningProgram>,
+    socket_file: Option<Arc<SocketFile>>,
+}
+
+/*
+A program we launched. It is only reaped by `poll`, while holding the lock,
+so a signal sent while `try_wait` says it is running always reaches it, and
+never a process that reused its pid.
+*/
+#[derive(Clone, Debug)]
+struct RunningProgram {
+    name: String,
+    child: Arc<Mutex<Child>>,
+}
+
+impl RunningProgram {
+    /// Whether the program exited, reaping it if so
+    fn reap(&self) -> bool {
+        match self.child.lock().unwrap().try_wait() {
+            Result::Ok(None) => false,
+            Result::Ok(Some(status)) => {
+                info!("{} exited with {}", self.name, status);
+                true
+            }
+            Err(e) => {
+                error!("Unable to wait for {}: {}", self.name, e);
+                true
+            }
+        }
+    }
+}
+
+/*
+The file of a Unix socket we listen on. It is removed once no copy of the
+source needs it any more, or the source is terminated, so the next session
+is able to listen on the same path.
+*/
+#[derive(Debug)]
+#[cfg_attr(not(unix), allow(dead_code))]
+struct SocketFile(String);
+
+impl SocketFile {
+    fn remove(&self) {
+        match fs::remove_file(&self.0) {
+            Result::Ok(()) => info!("Removed {}", self.0),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Unable to remove {}: {}", self.0, e),
+        }
+    }
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+impl LogSource {
+    /// Opens the input, stripping escapes and control characters from each line if `clean_lines` is set.
+    pub fn open(settings: &LogSearchSettings) -> Result<Self> {
+        let (reader, encoding) = (settings.log_reader.clone(), settings.log_encoding);
+        let mut source = match &settings.input {
+            LogInput::File(path) => {
+                let bytes = fs::read(path).context(format!("Unable to read {}", path))?;
+                let mut decoder = reader.decoder(encoding);
+                let entries = decoder.push(&bytes, true);
+                if decoder.lossy() {
+                    warn!(
+                        "{} is not valid {}, invalid characters were replaced",
+                        path,
+                        encoding.name()
+                    );
+                }
+                let mut source = LogSource::new(None, Some(path.clone()), None);
+                source.clean_lines = settings.clean_lines;
+                entries.into_iter().for_each(|l| source.push(l));
+                source
+            }
+            LogInput::Program(program, output) => {
+                LogSource::spawn(program, output, reader, encoding)?
+            }
+            LogInput::Socket(socket, output) => {
+                LogSource::connect(socket, output, reader, encoding)?
+            }
+        };
+        source.clean_lines = settings.clean_lines;
+        Ok(source)
+    }
+
+    fn new(
+        incoming: Option<Receiver<String>>,
+        path: Option<String>,
+        program: Option<RunningProgram>,
+    ) -> Self {
+        LogSource {
+            lines: Vec::new(),
+            raw: HashMap::new(),
+            clean_lines: false,
+            incoming,
+            path,
+            program,
+            socket_file: None,
+        }
+    }
+
+    fn push(&mut self, line: String) {
+        if self.clean_lines {
+            if let Cow::Owned(cleaned) = clean_line(&line) {
+                self.raw.insert(self.lines.len(), line);
+                self.lines.push(cleaned);
+                return;
+            }
+        }
+        self.lines.push(line);
+    }
+
+    fn connect(
+        settings: &SocketSettings,
+        output: &Option<String>,
+        reader: LogReader,
+        encoding: TextEncoding,
+    ) -> Result<Self> {
+        let output_file = Arc::new(Mutex::new(create_output_file(output)?));
+        let (tx, rx) = unbounded::<String>();
+        let mut socket_file = None;
+
+        match (&settings.address, settings.listen) {
+            (SocketAddress::Tcp(address), true) => {
+                let listener = TcpListener::bind(address)
+                    .context(format!("Unable to listen on {}", address))?;
+                info!("Listening for log lines on {}", address);
+                spawn(move || {
+                    for stream in listener.incoming().flatten() {
+                        if let Result::Ok(peer) = stream.peer_addr() {
+                            info!("Accepted log connection from {}", peer);
+                        }
+                        forward_lines(stream, reader.decoder(encoding), tx.clone(), output_file.clone());
+                    }
+                });
+            }
+            (SocketAddress::Tcp(address), false) => {
+                let stream = TcpStream::connect(address)
+                    .context(format!("Unable to connect to {}", address))?;
+                info!("Connected to {}", address);
+                forward_lines(stream, reader.decoder(encoding), tx, output_file);
+            }
+            #[cfg(unix)]
+            (SocketAddress::Unix(path), true) => {
+                remove_stale_socket(path)?;
+                let listener =
+                    UnixListener::bind(path).context(format!("Unable to listen on {}", path))?;
+                socket_file = Some(Arc::new(SocketFile(path.clone())));
+                info!("Listening for log lines on {}", path);
+                spawn(move || {
+                    for stream in listener.incoming().flatten() {
+                        info!("Accepted log connection");
+                        forward_lines(stream, reader.decoder(encoding), tx.clone(), output_file.clone());
+                    }
+                });
+            }
+            #[cfg(unix)]
+            (SocketAddress::Unix(path), false) => {
+                let stream =
+                    UnixStream::connect(path).context(format!("Unable to connect to {}", path))?;
+                info!("Connected to {}", path);
+                forward_lines(stream, reader.decoder(encoding), tx, output_file);
+            }
+            #[cfg(not(unix))]
+            (SocketAddress::Unix(path), _) => {
+                return Err(anyhow!(
+                    "Unix sockets are not supported on this platform: {}",
+                    path
+                ));
+            }
+        }
+
+        let mut source = LogSource::new(Some(rx), output.clone(), None);
+        source.socket_file = socket_file;
+        Ok(source)
+    }
+
+    fn spawn(
+        settings: &ProgramSettings,
+        output: &Option<String>,
+        reader: LogReader,
+        encoding: TextEncoding,
+    ) -> Result<Self> {
+        let mut command = Command::new(&settings.program);
+        command
+            .args(&settings.args)
+            .envs(&settings.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(cwd) = &settings.cwd {
+            command.current_dir(cwd);
+        }
+
+        let output_file = create_output_file(output)?;
+
+        let mut child = command
+            .spawn()
+            .context(format!("Unable to launch {}", settings.program))?;
+        let pid = child.id();
+        info!("Launched {} with pid {}", settings.program, pid);
+
+        let (tx, rx) = unbounded::<String>();
+        let output_file = Arc::new(Mutex::new(output_file));
+        let stdout = child.stdout.take().context("Unable to capture stdout")?;
+        let stderr = child.stderr.take().context("Unable to capture stderr")?;
+        forward_lines(stdout, reader.decoder(encoding), tx.clone(), output_file.clone());
+        forward_lines(stderr, reader.decoder(encoding), tx, output_file);
+
+        let program = RunningProgram {
+            name: settings.program.clone(),
+            child: Arc::new(Mutex::new(child)),
+        };
+        Ok(LogSource::new(Some(rx), output.clone(), Some(program)))
+    }
+
+    /// Pull in any lines that arrived since the last poll, and reap the program once it exited.
+    pub fn poll(&mut self) {
+        if self.program.as_ref().is_some_and(|p| p.reap()) {
+            self.program = None;
+        }
+        loop {
+            let Some(incoming) = &self.incoming else {
+                return;
+            };
+            match incoming.try_recv() {
+                Result::Ok(line) => self.push(line),
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {
+                    self.incoming = None;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// A live source may still produce more lines.
+    pub fn is_live(&self) -> bool {
+        self.incoming.is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.lines.get(index).map(|l| l.as_str())
+    }
+
+    /// The line as it was logged, if cleaning it up changed it
+    pub fn get_raw(&self, index: usize) -> Option<&str> {
+        self.raw.get(&index).map(|l| l.as_str())
+    }
+
+    /// Path of the file backing the log, if there is one the editor can open.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn pause(&self) {
+        self.signal(Signal::Stop);
+    }
+
+    pub fn resume(&self) {
+        self.signal(Signal::Continue);
+    }
+
+    pub fn terminate(&self) {
+        // Make sure a stopped program is able to act on the terminate signal
+        self.signal(Signal::Continue);
+        self.signal(Signal::Terminate);
+        if let Some(socket_file) = &self.socket_file {
+            socket_file.remove();
+        }
+    }
+
+    fn signal(&self, signal: Signal) {
+        if let Some(program) = &self.program {
+            // Holding the lock keeps the program from being reaped until the signal is sent
+            let mut child = program.child.lock().unwrap();
+            if let Result::Ok(None) = child.try_wait() {
+                send_signal(child.id(), signal);
+            }
+        }
+    }
+}
+
+fn create_output_file(output: &Option<String>) -> Result<Option<File>> {
+    match output {
+        Some(path) => Ok(Some(
+            File::create(path).context(format!("Unable to create {}", path))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/*
+Removes the file of a Unix socket nobody listens on any more, like one
+left behind by an earlier session, as binding to it would fail. A socket
+that still accepts connections is left alone.
+*/
+#[cfg(unix)]
+fn remove_stale_socket(path: &str) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let Result::Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if metadata.file_type().is_socket() && UnixStream::connect(path).is_err() {
+        fs::remove_file(path).context(format!("Unable to remove the stale socket {}", path))?;
+        info!("Removed the stale socket {}", path);
+    }
+    Ok(())
+}
+
+fn forward_lines(
+    mut stream: impl Read + Send + 'static,
+    mut decoder: EntryDecoder,
+    tx: Sender<String>,
+    output_file: Arc<Mutex<Option<File>>>,
+) {
+    spawn(move || {
+        let mut buf = [0; 8192];
+        loop {
+            let read = stream.read(&mut buf).unwrap_or(0);
+            let last = read == 0;
+
+            // Hold the lock while sending, so the output file and the line
+            // indices we hand out stay in the same order.
+            let mut output_file = output_file.lock().unwrap();
+            for line in decoder.push(&buf[..read], last) {
+                if let Some(file) = output_file.as_mut() {
+                    let _ = writeln!(file, "{}", line);
+                    let _ = file.flush();
+                }
+                if tx.send(line).is_err() {
+                    return;
+                }
+            }
+            if last {
+                return;
+            }
+        }
+    });
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Signal {
+    Stop,
+    Continue,
+    Terminate,
+}
+
+#[cfg(unix)]
+fn send_signal(pid: u32, signal: Signal) {
+    let sig = match signal {
+        Signal::Stop => libc::SIGSTOP,
+        Signal::Continue => libc::SIGCONT,
+        Signal::Terminate => libc::SIGTERM,
+    };
+    // SAFETY: kill has no memory safety requirements, it only takes a pid and a signal
+    if unsafe { libc::kill(pid as libc::pid_t, sig) } != 0 {
+        warn!("Unable to send {:?} to pid {}", signal, pid);
+    }
+}
+
+#[cfg(not(unix))]
+fn send_signal(pid: u32, signal: Signal) {
+    warn!(
+        "Sending {:?} to pid {} is not supported on this platform",
+        signal, pid
+    );
+}
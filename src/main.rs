@@ -1,7 +1,24 @@
 pub mod app_state;
 mod dap_logger;
+mod condition;
 mod dap_server;
+mod exception_trace;
+mod format_args;
+mod defmt_decoder;
+mod kernel_symbols;
+mod line_cleanup;
+mod log_format;
+mod log_inference;
+mod log_level;
+mod log_preset;
+mod log_reader;
 mod log_search;
+mod log_source;
+mod payload;
+mod pw_tokenizer;
+mod span_stack;
+mod text_encoding;
+mod timestamp;
 
 #[macro_use]
 extern crate log;
@@ -0,0 +1,143 @@
+use serde_json::{Map, Value};
+
+use crate::log_format::parse_logfmt;
+
+/// How much of a payload is shown as the value of its variable
+const MAX_SUMMARY_CHARS: usize = 80;
+
+/*
+Finds a structured payload in a message: the first JSON object in it, like
+`sending {"user": 7, "items": [1, 2]}`, or else its `key=value` pairs, like
+`request done status=200 ms=12`. Arrays only count if they hold objects or
+arrays, as `[INFO]` and `[3]` are common in plain messages.
+*/
+pub fn find_payload(message: &str) -> Option<Value> {
+    for (i, _) in message.match_indices(['{', '[']) {
+        let mut values = serde_json::Deserializer::from_str(&message[i..]).into_iter::<Value>();
+        match values.next() {
+            Some(Ok(value)) if is_structured(&value) => return Some(value),
+            _ => {}
+        }
+    }
+
+    let pairs: Map<String, Value> = parse_logfmt(message)
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let key = key.trim_end_matches(',');
+            let value = value?.trim_end_matches(',').to_string();
+            key.chars()
+                .all(|c| c.is_alphanumeric() || "_.-".contains(c))
+                .then(|| {
+                    (
+                        key.to_string(),
+                        parse_value(&value).unwrap_or(Value::String(value)),
+                    )
+                })
+        })
+        .collect();
+    // A single `state=Idle` is better shown as part of the message
+    (pairs.len() > 1).then_some(Value::Object(pairs))
+}
+
+/// The text as JSON, if it holds an object or an array
+pub fn parse_value(text: &str) -> Option<Value> {
+    let text = text.trim();
+    if !text.starts_with(['{', '[']) {
+        return None;
+    }
+    serde_json::from_str(text)
+        .ok()
+        .filter(|v: &Value| v.is_object() || v.is_array())
+}
+
+fn is_structured(value: &Value) -> bool {
+    match value {
+        Value::Object(_) => true,
+        Value::Array(items) => items.iter().any(|i| i.is_object() || i.is_array()),
+        _ => false,
+    }
+}
+
+/// The values in the payload, named by their path like `payload.items[0]`
+pub fn leaves(path: &str, value: &Value) -> Vec<(String, String)> {
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .flat_map(|(key, child)| leaves(&format!("{}.{}", path, key), child))
+            .collect(),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .flat_map(|(i, child)| leaves(&format!("{}[{}]", path, i), child))
+            .collect(),
+        value => vec![(path.to_string(), summary(value))],
+    }
+}
+
+/// The value of a variable showing the payload, shortened for the variables view
+pub fn summary(value: &Value) -> String {
+    let text = match value {
+        Value::String(s) => return s.clone(),
+        v => v.to_string(),
+    };
+    match text.char_indices().nth(MAX_SUMMARY_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn json_payloads_are_found() {
+        assert_eq!(
+            find_payload(r#"[INFO] sending {"user": 7, "items": [1, {"id": 2}]} now"#),
+            Some(json!({"user": 7, "items": [1, {"id": 2}]}))
+        );
+        assert_eq!(
+            find_payload(r#"batch [{"id": 1}, {"id": 2}]"#),
+            Some(json!([{"id": 1}, {"id": 2}]))
+        );
+        // Brackets that are no JSON are skipped
+        assert_eq!(
+            find_payload(r#"[3] {broken {"ok": true}"#),
+            Some(json!({"ok": true}))
+        );
+        assert_eq!(find_payload("[INFO] [3] plain message"), None);
+    }
+
+    #[test]
+    fn key_value_payloads_are_found() {
+        assert_eq!(
+            find_payload(r#"request done status=200, path="/a b" tags=[1,2] flag"#),
+            Some(json!({"status": "200", "path": "/a b", "tags": [1, 2]}))
+        );
+        assert_eq!(find_payload("state=Idle"), None);
+    }
+
+    #[test]
+    fn leaves_are_named_by_their_path() {
+        let payload = json!({"user": {"id": 7, "name": "bo"}, "items": [true, null]});
+        assert_eq!(
+            leaves("payload", &payload),
+            [
+                ("payload.items[0]".to_string(), "true".to_string()),
+                ("payload.items[1]".to_string(), "null".to_string()),
+                ("payload.user.id".to_string(), "7".to_string()),
+                ("payload.user.name".to_string(), "bo".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn summaries_are_shortened() {
+        let long = json!(vec!["é"; 40]);
+        let summary = summary(&long);
+        assert_eq!(summary.chars().count(), MAX_SUMMARY_CHARS + 1);
+        assert!(summary.ends_with('…'));
+        assert_eq!(super::summary(&json!("text")), "text");
+    }
+}
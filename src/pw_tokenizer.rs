@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+
+use anyhow::*;
+use base64::Engine;
+use object::{Object, ObjectSection};
+use serde_json::{Map, Value};
+
+/// Every entry in the `.pw_tokenizer.entries` section starts with this
+const ENTRY_MAGIC: u32 = 0xBAA98DEE;
+/// Widths and precisions are capped to this, so a corrupt one can not exhaust the memory, or overflow `format!`
+const MAX_WIDTH: usize = 1024;
+
+/*
+The strings of a firmware's Pigweed tokenized logs, by token.
+They are read from a token database CSV, with rows of
+`token,removal date,"string"` (optionally with a domain before the
+string), or from the `.pw_tokenizer.entries` sections of the ELF file.
+https://pigweed.dev/pw_tokenizer/
+*/
+#[derive(Debug)]
+pub struct TokenDatabase {
+    strings: HashMap<u32, String>,
+}
+
+impl TokenDatabase {
+    pub fn load(path: &str) -> Result<Self> {
+        let bytes = std::fs::read(path).context(format!("Unable to read {}", path))?;
+        let strings = match bytes.starts_with(b"\x7fELF") {
+            true => {
+                read_elf_entries(&bytes).context(format!("Unable to read tokens from {}", path))?
+            }
+            false => read_csv(&String::from_utf8_lossy(&bytes))
+                .context(format!("{} is not a valid token database", path))?,
+        };
+        if strings.is_empty() {
+            return Err(anyhow!("{} does not contain any tokens", path));
+        }
+        info!("Loaded {} tokens from {}", strings.len(), path);
+        Ok(TokenDatabase { strings })
+    }
+
+    /*
+    Replaces the Base64 tokenized messages in a line, e.g. `$kgjLdg==`, with
+    their text, and turns the line into a JSON entry. pw_log_tokenized
+    packs the module and file into the format string, as
+    `■msg♦text■module♦name■file♦path`, which become fields of their own.
+    */
+    pub fn detokenize(&self, line: &str) -> String {
+        let mut entry = Map::new();
+        let mut message = String::new();
+        let mut rest = line;
+        while let Some(start) = rest.find('$') {
+            message.push_str(&rest[..start]);
+            let encoded = &rest[start + 1..];
+            let len = encoded
+                .find(|c: char| !(c.is_ascii_alphanumeric() || "+/=".contains(c)))
+                .unwrap_or(encoded.len());
+            match self.decode(&encoded[..len], &mut entry) {
+                Some(text) => message.push_str(&text),
+                None => message.push_str(&rest[start..start + 1 + len]),
+            }
+            rest = &encoded[len..];
+        }
+        message.push_str(rest);
+        entry.insert("message".to_string(), Value::String(message));
+        Value::Object(entry).to_string()
+    }
+
+    // The text of a Base64 message. The fields of the first message in a line are added to the entry.
+    fn decode(&self, encoded: &str, entry: &mut Map<String, Value>) -> Option<String> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .ok()?;
+        let token = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?);
+        let string = self.strings.get(&token)?;
+
+        let mut fields = vec![("token".to_string(), format!("{:08x}", token))];
+        let mut format = string.as_str();
+        if string.starts_with('■') {
+            for field in string.split('■').skip(1) {
+                let (key, value) = field.split_once('♦').unwrap_or((field, ""));
+                match key {
+                    "msg" => format = value,
+                    _ => fields.push((key.to_string(), value.to_string())),
+                }
+            }
+        }
+        fields.push(("format".to_string(), format.to_string()));
+        if !entry.contains_key("token") {
+            entry.extend(fields.into_iter().map(|(k, v)| (k, Value::String(v))));
+        }
+        Some(printf(format, &bytes[4..]))
+    }
+}
+
+fn read_csv(text: &str) -> Result<HashMap<u32, String>> {
+    let mut strings = HashMap::new();
+    for row in parse_csv(text) {
+        let (Some(token), Some(string)) = (row.first(), row.last()) else {
+            continue;
+        };
+        if row.len() < 3 {
+            return Err(anyhow!(
+                "Expected token, removal date and string in row {:?}",
+                row
+            ));
+        }
+        let token = u32::from_str_radix(token.trim(), 16)
+            .context(format!("Token [{}] is not a valid hex number", token))?;
+        strings.insert(token, string.clone());
+    }
+    Ok(strings)
+}
+
+// Splits CSV into rows of fields, where quoted fields may hold commas, newlines and `""`
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.next_if_eq(&'"').is_some() => field.push('"'),
+            ('"', _) => quoted = !quoted,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            ('\r', false) => {}
+            (c, _) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows.retain(|r| r.iter().any(|f| !f.is_empty()));
+    rows
+}
+
+/*
+Each entry is the magic, the token, the length of the domain and the
+length of the string, as little endian u32s, followed by the domain and the
+string, both NUL terminated.
+*/
+fn read_elf_entries(bytes: &[u8]) -> Result<HashMap<u32, String>> {
+    let elf = object::File::parse(bytes)?;
+    let mut strings = HashMap::new();
+    for section in elf.sections() {
+        if !section
+            .name()
+            .is_ok_and(|n| n.starts_with(".pw_tokenizer.entries"))
+        {
+            continue;
+        }
+        let data = section.uncompressed_data()?;
+        let u32_at = |i: usize| Some(u32::from_le_bytes(data.get(i..i + 4)?.try_into().ok()?));
+        let mut pos = 0;
+        while let (Some(magic), Some(token), Some(domain_len), Some(string_len)) = (
+            u32_at(pos),
+            u32_at(pos + 4),
+            u32_at(pos + 8),
+            u32_at(pos + 12),
+        ) {
+            if magic != ENTRY_MAGIC {
+                return Err(anyhow!("Invalid token entry at offset {}", pos));
+            }
+            let string_at = pos + 16 + domain_len as usize;
+            let string = data
+                .get(string_at..string_at + string_len as usize)
+                .context(format!("Token entry at offset {} is cut short", pos))?;
+            let string = String::from_utf8_lossy(string);
+            strings.insert(token, string.trim_end_matches('\0').to_string());
+            pos = string_at + string_len as usize;
+        }
+    }
+    Ok(strings)
+}
+
+/*
+Formats the arguments of a tokenized message with its printf style format.
+Integers are ZigZag encoded varints, floats are little endian f32s, and
+strings are a length byte followed by the characters, where the top bit of
+the length means the string was truncated.
+Anything we can not decode is left in the message as it was written.
+*/
+fn printf(format: &str, mut args: &[u8]) -> String {
+    let mut text = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('%') {
+        text.push_str(&rest[..start]);
+        let spec = &rest[start + 1..];
+        if let Some(spec) = spec.strip_prefix('%') {
+            text.push('%');
+            rest = spec;
+            continue;
+        }
+        let Some(len) = spec.find(|c: char| c.is_ascii_alphabetic() && !"hljztL".contains(c))
+        else {
+            text.push_str(&rest[start..]);
+            return text;
+        };
+        let (flags, conversion) = (&spec[..len], spec.as_bytes()[len] as char);
+        rest = &spec[len + 1..];
+        match format_argument(flags, conversion, &mut args) {
+            Some(value) => text.push_str(&value),
+            None => text.push_str(&format!("%{}{}", flags, conversion)),
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+fn format_argument(flags: &str, conversion: char, args: &mut &[u8]) -> Option<String> {
+    let flags_len = flags.find(|c| !"-+ #0".contains(c)).unwrap_or(flags.len());
+    let (left, zero) = (
+        flags[..flags_len].contains('-'),
+        flags[..flags_len].contains('0'),
+    );
+    let size = flags[flags_len..].trim_end_matches(['h', 'l', 'j', 'z', 't', 'L']);
+    let (width, precision) = match size.split_once('.') {
+        Some((width, precision)) => (width, Some(precision)),
+        None => (size, None),
+    };
+    let width = match width {
+        "*" => usize::try_from(varint(args)?).ok()?.min(MAX_WIDTH),
+        w => w.parse::<usize>().unwrap_or(0).min(MAX_WIDTH),
+    };
+    let precision = match precision {
+        Some("*") => Some(usize::try_from(varint(args)?).ok()?.min(MAX_WIDTH)),
+        Some(p) => Some(p.parse::<usize>().unwrap_or(0).min(MAX_WIDTH)),
+        None => None,
+    };
+    let long = flags.contains("ll") || flags.contains('j');
+
+    let value = match conversion {
+        'd' | 'i' => varint(args)?.to_string(),
+        'u' | 'x' | 'X' | 'o' => {
+            let value = match long {
+                true => varint(args)? as u64,
+                false => varint(args)? as u32 as u64,
+            };
+            match conversion {
+                'x' => format!("{:x}", value),
+                'X' => format!("{:X}", value),
+                'o' => format!("{:o}", value),
+                _ => value.to_string(),
+            }
+        }
+        'c' => char::from_u32(varint(args)? as u32)?.to_string(),
+        'p' => format!("0x{:08X}", varint(args)? as u32),
+        's' => {
+            let (len, bytes) = args.split_first()?;
+            let truncated = len & 0x80 != 0;
+            let len = (len & 0x7F) as usize;
+            let string = bytes.get(..len)?;
+            *args = &bytes[len..];
+            let mut string = String::from_utf8_lossy(string).to_string();
+            if let Some(precision) = precision {
+                string = string.chars().take(precision).collect();
+            }
+            if truncated {
+                string.push_str("[...]");
+            }
+            string
+        }
+        'f' | 'F' | 'e' | 'E' | 'g' | 'G' | 'a' | 'A' => {
+            let (value, rest) = args.split_at_checked(4)?;
+            *args = rest;
+            let value = f32::from_le_bytes(value.try_into().ok()?);
+            let precision = precision.unwrap_or(6);
+            match conversion {
+                'e' | 'E' => {
+                    let value = format!("{:.1$e}", value, precision);
+                    // C always gives the exponent a sign and at least two digits
+                    let (mantissa, exponent) = value.split_once('e')?;
+                    let exponent: i32 = exponent.parse().ok()?;
+                    let value = format!("{}e{:+03}", mantissa, exponent);
+                    match conversion {
+                        'E' => value.to_uppercase(),
+                        _ => value,
+                    }
+                }
+                'g' | 'G' | 'a' | 'A' => value.to_string(),
+                _ => format!("{:.1$}", value, precision),
+            }
+        }
+        _ => return None,
+    };
+
+    Some(match (left, zero) {
+        (true, _) => format!("{:<1$}", value, width),
+        (false, true) if !"sc".contains(conversion) => match value.strip_prefix('-') {
+            Some(digits) => format!("-{:0>1$}", digits, width.saturating_sub(1)),
+            None => format!("{:0>1$}", value, width),
+        },
+        (false, _) => format!("{:>1$}", value, width),
+    })
+}
+
+// Reads a ZigZag encoded varint, the encoding pw_tokenizer uses for all integers
+fn varint(args: &mut &[u8]) -> Option<i64> {
+    let mut value: u64 = 0;
+    for (i, byte) in args.iter().enumerate().take(10) {
+        value |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *args = &args[i + 1..];
+            return Some((value >> 1) as i64 ^ -((value & 1) as i64));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str =
+        "3c1d6b8f,          ,\"■msg♦Battery at %d%%■module♦power■file♦power/battery.c\"\n\
+                       0a1b2c3d,2024-01-01,motors,\"Motor %s stalled at %u rpm\"\n\
+                       00000001,          ,\"Said \"\"hi\"\", twice\nand again\"\n";
+
+    fn database() -> TokenDatabase {
+        TokenDatabase {
+            strings: read_csv(CSV).unwrap(),
+        }
+    }
+
+    fn entry(line: &str) -> Value {
+        serde_json::from_str(&database().detokenize(line)).unwrap()
+    }
+
+    #[test]
+    fn csv_rows_are_read() {
+        let strings = read_csv(CSV).unwrap();
+        assert_eq!(strings.len(), 3);
+        assert_eq!(strings[&0x0a1b2c3d], "Motor %s stalled at %u rpm");
+        assert_eq!(strings[&1], "Said \"hi\", twice\nand again");
+        assert!(read_csv("zz,,\"x\"").is_err());
+        assert!(read_csv("1234,\"x\"").is_err());
+    }
+
+    #[test]
+    fn elf_entries_are_read() {
+        // Parsing an ELF needs aligned bytes, which include_bytes! does not promise
+        let elf = include_bytes!("../tests/fixtures/pw-tokenizer.elf").to_vec();
+        let strings = read_elf_entries(&elf).unwrap();
+        let mut expected = read_csv(CSV).unwrap();
+        expected.remove(&1);
+        assert_eq!(strings, expected);
+    }
+
+    #[test]
+    fn messages_are_detokenized() {
+        let battery = entry("I 12:00:01 $j2sdPK4B");
+        assert_eq!(battery["message"], "I 12:00:01 Battery at 87%");
+        assert_eq!(battery["token"], "3c1d6b8f");
+        assert_eq!(battery["module"], "power");
+        assert_eq!(battery["file"], "power/battery.c");
+        assert_eq!(battery["format"], "Battery at %d%%");
+
+        assert_eq!(
+            entry("$PSwbCgRsZWZ04BI=")["message"],
+            "Motor left stalled at 1200 rpm"
+        );
+        // A truncated string, and -1 as an unsigned int
+        assert_eq!(
+            entry("$PSwbCoRyaWdoAQ==")["message"],
+            "Motor righ[...] stalled at 4294967295 rpm"
+        );
+    }
+
+    #[test]
+    fn unknown_tokens_are_left_as_is() {
+        let unknown = entry("boot $eFY0Eg== then $ and $!!");
+        assert_eq!(unknown["message"], "boot $eFY0Eg== then $ and $!!");
+        assert!(unknown.get("token").is_none());
+        // Only the first message of a line gives the entry its fields
+        let both = entry("$PSwbCgRsZWZ04BI= $j2sdPK4B");
+        assert_eq!(both["token"], "0a1b2c3d");
+    }
+
+    #[test]
+    fn printf_formats_arguments() {
+        assert_eq!(printf("%d and %i", &[9, 0xFE, 0x03]), "-5 and 255");
+        assert_eq!(printf("[%5d|%-5d|%05d]", &[9, 9, 9]), "[   -5|-5   |-0005]");
+        assert_eq!(
+            printf("%x %X %o %c", &[0xFE, 0x03, 0xFE, 0x03, 0x10, 0x82, 0x01]),
+            "ff FF 10 A"
+        );
+        assert_eq!(
+            printf("%.2f %e", &[0, 0, 0xC0, 0x3F, 0, 0, 0xC0, 0x3F]),
+            "1.50 1.500000e+00"
+        );
+        assert_eq!(
+            printf("%*d|%.*s", &[8, 2, 4, 3, b'a', b'b', b'c']),
+            "   1|ab"
+        );
+        assert_eq!(
+            printf("%lld", &[0xFF, 0xFF, 0xFF, 0xFF, 0x1F]),
+            "-4294967296"
+        );
+    }
+
+    #[test]
+    fn printf_keeps_what_it_can_not_decode() {
+        assert_eq!(printf("%d and %s", &[2]), "1 and %s");
+        assert_eq!(printf("100%", &[]), "100%");
+        assert_eq!(printf("%k", &[]), "%k");
+        // A corrupt width is capped, and a negative one is ignored
+        assert_eq!(
+            printf("%*d", &[0xFE, 0xFF, 0xFF, 0xFF, 0x0F, 0]).len(),
+            MAX_WIDTH
+        );
+        assert_eq!(printf("%*d", &[1, 0]), "%*d");
+        // As is a corrupt precision, from the arguments or the format string
+        let float = [0, 0, 0xC0, 0x3F];
+        let corrupt = [&[0xFE, 0xFF, 0xFF, 0xFF, 0x0F][..], &float].concat();
+        assert_eq!(printf("%.*f", &corrupt).len(), "1.".len() + MAX_WIDTH);
+        assert_eq!(printf("%.*e", &corrupt).len(), "1.e+00".len() + MAX_WIDTH);
+        assert_eq!(printf("%.99999999f", &float).len(), "1.".len() + MAX_WIDTH);
+    }
+}
@@ -0,0 +1,175 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::log_format::parse_logfmt;
+
+lazy_static! {
+    // `outer{a=1}:inner{b=2}: message`, as logged by tracing-subscriber
+    static ref SPAN_PREFIX: Regex =
+        Regex::new(r"^(?P<spans>[A-Za-z_]\w*(?:\{[^{}]*\})?(?::[A-Za-z_]\w*(?:\{[^{}]*\})?)*): ").unwrap();
+    static ref SPAN: Regex = Regex::new(r"(?P<name>[A-Za-z_]\w*)(?:\{(?P<fields>[^{}]*)\})?").unwrap();
+    // `enter parse_header`, `-> parse_header(len=12)`, `exit parse_header`
+    static ref ENTER: Regex = Regex::new(
+        r"^(?i:enter|entering|entered|-->|->)\s+(?P<name>[A-Za-z_][\w:.$<>]*)(?:\s*\((?P<args>.*)\))?\s*$"
+    )
+    .unwrap();
+    static ref EXIT: Regex = Regex::new(
+        r"^(?i:exit|exiting|exited|leave|leaving|<--|<-)\s+(?P<name>[A-Za-z_][\w:.$<>]*)(?:\s*\(.*\))?\s*$"
+    )
+    .unwrap();
+}
+
+/// A span an entry was logged in, with the fields it was entered with.
+#[derive(Clone, Debug)]
+pub struct Span {
+    pub name: String,
+    pub fields: Vec<(String, String)>,
+    /// The entry that entered the span, if it was entered by a marker
+    pub entered_at: Option<usize>,
+}
+
+pub enum SpanMarker {
+    Enter(Span),
+    Exit(String),
+}
+
+fn pairs(text: &str) -> Vec<(String, String)> {
+    parse_logfmt(text)
+        .into_iter()
+        .map(|(key, value)| {
+            let key = key.trim_end_matches(',').to_string();
+            (
+                key,
+                value.unwrap_or_default().trim_end_matches(',').to_string(),
+            )
+        })
+        .collect()
+}
+
+/*
+The spans an entry names itself, outermost first. tracing-subscriber puts
+them before the message, e.g. `outer{a=1}:inner{b=2}: message`, and its
+JSON output lists them as `spans`. A pattern may capture that prefix as a
+`spans` field too, as the tracing-fmt preset does. A single span without
+fields, like `error: message`, is too much like an ordinary message to count.
+*/
+pub fn entry_spans(message: &str, fields: &[(String, String)]) -> Option<Vec<Span>> {
+    if let Some((_, spans)) = fields.iter().find(|(f, _)| f == "spans") {
+        if let Result::Ok(spans) = serde_json::from_str::<Vec<Map<String, Value>>>(spans) {
+            return Some(spans.into_iter().map(json_span).collect());
+        }
+        let spans = text_spans(spans.trim().trim_end_matches(':'));
+        if !spans.is_empty() {
+            return Some(spans);
+        }
+    }
+
+    let prefix = SPAN_PREFIX.captures(message)?;
+    let prefix = &prefix["spans"];
+    if !prefix.contains(['{', ':']) {
+        return None;
+    }
+    Some(text_spans(prefix))
+}
+
+fn json_span(mut span: Map<String, Value>) -> Span {
+    Span {
+        name: match span.remove("name") {
+            Some(Value::String(name)) => name,
+            _ => "span".to_string(),
+        },
+        fields: span
+            .into_iter()
+            .map(|(k, v)| match v {
+                Value::String(s) => (k, s),
+                v => (k, v.to_string()),
+            })
+            .collect(),
+        entered_at: None,
+    }
+}
+
+fn text_spans(text: &str) -> Vec<Span> {
+    SPAN.captures_iter(text)
+        .map(|span| Span {
+            name: span["name"].to_string(),
+            fields: span
+                .name("fields")
+                .map_or(Vec::new(), |f| pairs(f.as_str())),
+            entered_at: None,
+        })
+        .collect()
+}
+
+/// Whether the message marks entering or leaving a function, e.g. `enter parse_header`
+pub fn parse_marker(message: &str) -> Option<SpanMarker> {
+    if let Some(enter) = ENTER.captures(message) {
+        return Some(SpanMarker::Enter(Span {
+            name: enter["name"].to_string(),
+            fields: enter.name("args").map_or(Vec::new(), |a| pairs(a.as_str())),
+            entered_at: None,
+        }));
+    }
+    let exit = EXIT.captures(message)?;
+    Some(SpanMarker::Exit(exit["name"].to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(spans: &[Span]) -> Vec<&str> {
+        spans.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    fn field(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn spans_are_read_from_the_message() {
+        let spans = entry_spans("conn{id=1 peer=\"a b\"}:req: sent reply", &[]).unwrap();
+        assert_eq!(names(&spans), ["conn", "req"]);
+        assert_eq!(spans[0].fields, [field("id", "1"), field("peer", "a b")]);
+        assert!(entry_spans("error: disk full", &[]).is_none());
+        assert!(entry_spans("no spans here", &[]).is_none());
+    }
+
+    #[test]
+    fn spans_are_read_from_json_fields() {
+        let spans = r#"[{"name":"conn","id":1},{"name":"req","path":"/"}]"#;
+        let spans = entry_spans("sent reply", &[field("spans", spans)]).unwrap();
+        assert_eq!(names(&spans), ["conn", "req"]);
+        assert_eq!(spans[0].fields, [field("id", "1")]);
+        assert_eq!(spans[1].fields, [field("path", "/")]);
+    }
+
+    #[test]
+    fn spans_are_read_from_text_fields() {
+        // As captured by the tracing-fmt preset
+        let spans = entry_spans("sent reply", &[field("spans", "conn{id=1}:req:")]).unwrap();
+        assert_eq!(names(&spans), ["conn", "req"]);
+        assert_eq!(spans[0].fields, [field("id", "1")]);
+
+        // A field that is no list of spans falls back to the message
+        let spans = entry_spans("conn{id=2}: sent reply", &[field("spans", "")]).unwrap();
+        assert_eq!(names(&spans), ["conn"]);
+    }
+
+    #[test]
+    fn markers() {
+        let Some(SpanMarker::Enter(span)) = parse_marker("-> parse_header(len=12, kind=\"a\")")
+        else {
+            panic!("not an enter marker");
+        };
+        assert_eq!(span.name, "parse_header");
+        assert_eq!(span.fields, [field("len", "12"), field("kind", "a")]);
+        assert!(matches!(
+            parse_marker("Exiting Parser::run"),
+            Some(SpanMarker::Exit(name)) if name == "Parser::run"
+        ));
+        assert!(parse_marker("entered main").is_some());
+        assert!(parse_marker("enter the dragon now").is_none());
+    }
+}
@@ -1,255 +1,1330 @@
 pub mod app_state;
 mod dap_logger;
+mod condition;
 mod dap_server;
+mod exception_trace;
+mod format_args;
+mod defmt_decoder;
+mod kernel_symbols;
+mod line_cleanup;
+mod log_format;
+mod log_inference;
+mod log_level;
+mod log_preset;
+mod log_reader;
 mod log_search;
+mod log_source;
+mod payload;
+mod pw_tokenizer;
+mod span_stack;
+mod text_encoding;
+mod timestamp;
 
-use std::collections::HashMap;
-use std::fs;
-use std::time::Instant;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::{Duration, Instant};
 
-use dap::requests::Command::*;
-use dap::requests::*;
 use regex::Regex;
-use serde_json::Map;
-
-use dap::base_message::Sendable;
-use dap::events::*;
-use dap::types::*;
-use serde_json::Value;
+use serde_json::{json, Value};
 
 use crate::app_state::*;
 
 #[macro_use]
 extern crate log;
 
+/// The logs and sources the scenarios replay
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/");
+/// Lines like `[src/app.c:7] processing request number 1`
+const LOCATION_PATTERN: &str = r"\[(?P<file>[^:]+):(?P<line>\d+)\] (?P<message>.*)$";
+/// How long we wait for the adapter to answer, before failing the scenario
+const TIMEOUT: Duration = Duration::from_secs(10);
+
 fn main() {
-    test_breakpoints_10_lines();
-    test_breakpoints_10_lines_and_func();
-    test_breakpoints_in_source_lines();
-}
-
-fn gen_init(seq: i64) -> Request {
-    Request {
-        seq,
-        command: Initialize(InitializeArguments {
-            client_id: Some("test".to_string()),
-            client_name: Some("test".to_string()),
-            adapter_id: "retread".to_string(),
-            locale: Some("en".to_string()),
-            lines_start_at1: Some(true),
-            columns_start_at1: Some(true),
-            path_format: Some(PathFormat::Path),
-            supports_variable_type: Some(true),
-            supports_variable_paging: Some(true),
-            supports_run_in_terminal_request: Some(true),
-            supports_memory_references: Some(true),
-            supports_progress_reporting: Some(true),
-            supports_invalidated_event: Some(true),
-            supports_memory_event: Some(true),
-            supports_args_can_be_interpreted_by_shell: Some(true),
-        }),
-    }
-}
-
-fn gen_launch(seq: i64, additional_data: Map<String, Value>) -> Request {
-    Request {
-        seq,
-        command: Launch(LaunchRequestArguments {
-            no_debug: None,
-            restart_data: None,
-            additional_data: Some(serde_json::Value::Object(additional_data)),
-        }),
-    }
-}
-
-struct Breakpoint {
-    pub path: String,
-    pub line: i64,
-}
-
-fn gen_breakpoints(seq: i64, breakpoints: &Vec<Breakpoint>) -> Vec<Request> {
-    let mut seq = seq;
-    let mut breakpoint_map: HashMap<String, Vec<i64>> = HashMap::new();
-    for b in breakpoints.iter() {
-        match breakpoint_map.entry(b.path.clone()) {
-            std::collections::hash_map::Entry::Occupied(mut o) => {
-                o.get_mut().push(b.line);
+    dap_logger::init().unwrap();
+
+    let scenarios: &[(&str, fn())] = &[
+        (
+            "10 lines until breakpoint. Line numbers enabled only",
+            test_breakpoints_10_lines,
+        ),
+        (
+            "10 lines until breakpoint. Filename+Line enabled",
+            test_breakpoints_10_lines_and_func,
+        ),
+        (
+            "10 lines until breakpoint (in source). Line numbers enabled only",
+            test_breakpoints_in_source_lines,
+        ),
+        ("Launching a program", test_launch_program),
+        ("Attaching to a log stream", test_attach_socket),
+        ("Reading a JSON log", test_json_log),
+        ("Reading a logfmt log", test_logfmt_log),
+        ("Reading a log with a preset", test_log_preset),
+        ("Inferring the log_pattern", test_infer_log_pattern),
+        ("Reading a log with several patterns", test_log_patterns),
+        ("Showing the timestamps", test_timestamps),
+        ("Skipping entries below a level", test_min_level),
+        ("Stepping through a thread", test_threads),
+        ("Reading Latin-1 logs and sources", test_text_encoding),
+        ("Cleaning up terminal output", test_terminal_output),
+        ("Reading a journal export", test_journal),
+        ("Reading a binary logcat capture", test_logcat),
+        ("Decoding defmt and pw_tokenizer logs", test_tokenized_logs),
+        ("Resolving the frames of a kernel oops", test_kernel_oops),
+        ("Stopping at an exception", test_exception),
+        ("Showing spans as stack frames", test_spans),
+        ("Showing the captures as variables", test_capture_variables),
+        ("Recovering the format arguments", test_format_arguments),
+        ("Expanding the payload of a message", test_payload_variables),
+        ("Showing the history of a variable", test_variable_history),
+        (
+            "Stopping where the condition holds",
+            test_conditional_breakpoints,
+        ),
+        ("Stopping after a number of hits", test_hit_conditions),
+        ("Printing the message of logpoints", test_logpoints),
+        ("Stopping in a function", test_function_breakpoints),
+    ];
+    for (name, scenario) in scenarios {
+        println!("\n\nRunning test: {}", name);
+        let start = Instant::now();
+        scenario();
+        println!("Time taken: {:.2}s", start.elapsed().as_secs_f32());
+    }
+}
+
+fn fixture(path: &str) -> String {
+    FIXTURES.to_string() + path
+}
+
+/// The launch settings, searching the sources of the scenarios unless they say otherwise
+fn settings(mut data: Value) -> Value {
+    let map = data.as_object_mut().unwrap();
+    map.entry("include")
+        .or_insert_with(|| json!([fixture("scenarios/src/**/*")]));
+    map.entry("exclude").or_insert_with(|| json!([]));
+    data
+}
+
+/*
+A debug session with the adapter, talking to it like an editor would. The
+adapter runs on this thread, and is run whenever we wait for a message.
+Events that arrive while we wait for a response are kept, until they are
+waited for.
+*/
+struct Session {
+    app: App,
+    seq: i64,
+    events: VecDeque<Value>,
+    /// What the adapter answered to initialize
+    capabilities: Value,
+}
+
+impl Session {
+    fn new() -> Self {
+        // Anything left over by the previous scenario
+        while dap_server::read_server().is_some() {}
+
+        let mut session = Session {
+            app: App::init(),
+            seq: 0,
+            events: VecDeque::new(),
+            capabilities: Value::Null,
+        };
+        session.capabilities = session.success("initialize", json!({"adapterID": "retread"}));
+        session
+    }
+
+    /// Launches with the settings, and waits until we stopped at the first entry
+    fn launch(data: Value) -> Self {
+        let mut session = Session::new();
+        session.success("launch", settings(data));
+        session.wait_stopped("entry");
+        session
+    }
+
+    fn receive(&mut self) -> Value {
+        let start = Instant::now();
+        loop {
+            if let Err(e) = self.app.app_loop() {
+                error!("{}", e);
             }
-            std::collections::hash_map::Entry::Vacant(v) => {
-                v.insert(vec![b.line]);
+            if let Some(message) = dap_server::read_server() {
+                return serde_json::to_value(message).unwrap();
             }
+            assert!(
+                start.elapsed() < TIMEOUT,
+                "Timed out waiting for the adapter"
+            );
         }
     }
 
-    breakpoint_map
-        .iter()
-        .map(|(p, b)| {
-            seq += 1;
-            Request {
-                seq: seq - 1,
-                command: SetBreakpoints(SetBreakpointsArguments {
-                    source: dap::types::Source {
-                        name: Some(p.clone()),
-                        path: Some(p.clone()),
-                        source_reference: None,
-                        presentation_hint: None,
-                        origin: None,
-                        sources: None,
-                        adapter_data: None,
-                        checksums: None,
-                    },
-                    breakpoints: Some(
-                        b.iter()
-                            .map(|v| SourceBreakpoint {
-                                line: *v,
-                                column: None,
-                                condition: None,
-                                hit_condition: None,
-                                log_message: None,
-                            })
-                            .collect(),
-                    ),
-                    lines: None,
-                    source_modified: Some(false),
-                }),
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        self.seq += 1;
+        let mut request = json!({"seq": self.seq, "type": "request", "command": command});
+        if !arguments.is_null() {
+            request["arguments"] = arguments;
+        }
+        let request = serde_json::from_value(request)
+            .unwrap_or_else(|e| panic!("Invalid {} request: {}", command, e));
+        dap_server::write_server(request);
+
+        loop {
+            let message = self.receive();
+            if message["type"] == "response" && message["request_seq"] == self.seq {
+                return message;
             }
-        })
-        .collect()
-}
+            self.events.push_back(message);
+        }
+    }
 
-fn gen_continue() -> Request {
-    Request {
-        seq: 4,
-        command: Continue(ContinueArguments {
-            thread_id: 0,
-            single_thread: None,
-        }),
+    /// The body of the response, which has to be a success
+    fn success(&mut self, command: &str, arguments: Value) -> Value {
+        let response = self.request(command, arguments);
+        assert_eq!(
+            response["success"], true,
+            "{} failed: {}",
+            command, response
+        );
+        response["body"].clone()
     }
-}
 
-fn run_test(additional_data: Map<String, Value>, breakpoints: &Vec<Breakpoint>) {
-    let mut app = App::init();
-    dap_server::write_server(gen_init(1));
+    /// The error message of the response, which has to be a failure
+    fn failure(&mut self, command: &str, arguments: Value) -> String {
+        let response = self.request(command, arguments);
+        assert_eq!(
+            response["success"], false,
+            "{} succeeded: {}",
+            command, response
+        );
+        response["message"]["Error"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    }
 
-    dap_server::write_server(gen_launch(2, additional_data));
-    for ele in gen_breakpoints(3, &breakpoints) {
-        dap_server::write_server(ele);
+    /// The body of the next event of the kind. The events before it are dropped.
+    fn wait_event(&mut self, event: &str) -> Value {
+        loop {
+            let message = match self.events.pop_front() {
+                Some(message) => message,
+                None => self.receive(),
+            };
+            if message["type"] == "event" && message["event"] == event {
+                return message["body"].clone();
+            }
+        }
     }
-    loop {
-        app.app_loop();
-        if dap_server::read_server().is_none() {
-            break;
+
+    fn wait_stopped(&mut self, reason: &str) -> Value {
+        let stopped = self.wait_event("stopped");
+        assert_eq!(
+            stopped["reason"], reason,
+            "Stopped for another reason: {}",
+            stopped
+        );
+        stopped
+    }
+
+    /// Waits for the output that contains the text
+    fn wait_output(&mut self, text: &str) -> String {
+        loop {
+            let output = self.wait_event("output");
+            let output = output["output"].as_str().unwrap_or_default();
+            if output.contains(text) {
+                return output.to_string();
+            }
         }
     }
 
-    dap_server::write_server(gen_continue());
+    fn stack_frames(&mut self, thread_id: i64) -> Vec<Value> {
+        let body = self.success("stackTrace", json!({"threadId": thread_id}));
+        body["stackFrames"].as_array().unwrap().clone()
+    }
 
-    let start = Instant::now();
+    /// The variables of the frame, by name
+    fn variables(&mut self, frame_id: i64) -> HashMap<String, String> {
+        let body = self.success("scopes", json!({"frameId": frame_id}));
+        let reference = body["scopes"][0]["variables_reference"].clone();
+        let body = self.success("variables", json!({"variablesReference": reference}));
+        body["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| {
+                (
+                    v["name"].as_str().unwrap().to_string(),
+                    v["value"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
 
-    loop {
-        app.app_loop();
-        if let Some(Sendable::Event(Event::Stopped(stop_event))) = dap_server::read_server() {
-            println!("Got: {:?}", stop_event.description);
-            break;
-        }
+    /// The variables of the entry the thread is stopped at
+    fn locals(&mut self, thread_id: i64) -> HashMap<String, String> {
+        let frame = self.stack_frames(thread_id)[0]["id"].as_i64().unwrap();
+        self.variables(frame)
+    }
+
+    fn step(&mut self, thread_id: i64) {
+        self.success("next", json!({"threadId": thread_id}));
+        self.wait_stopped("step");
     }
 
-    let end = start.elapsed();
-    println!("Time taken: {:.2}s", end.as_secs_f32());
+    fn set_breakpoints(&mut self, path: &str, breakpoints: Value) -> Value {
+        self.success(
+            "setBreakpoints",
+            json!({"source": {"path": path}, "breakpoints": breakpoints}),
+        )
+    }
+
+    fn disconnect(mut self) {
+        self.success("disconnect", json!({}));
+        assert!(matches!(self.app.state, AppState::Exit));
+    }
+}
+
+// The Linux scenarios replay ./linux.log against the kernel sources in ./linux
+fn linux_settings(log_pattern: &str) -> Value {
+    json!({
+        "include": ["./linux/**/*.c"],
+        "exclude": [],
+        "log_file": "./linux.log",
+        "log_pattern": log_pattern,
+    })
+}
+
+fn run_linux_test(data: Value, path: &str, line: i64) {
+    let mut session = Session::launch(data);
+    session.set_breakpoints(path, json!([{"line": line}]));
+    session.success("continue", json!({"threadId": 0}));
+    let stopped = session.wait_event("stopped");
+    println!("Got: {:?}", stopped["description"]);
+    session.disconnect();
 }
 
 fn test_breakpoints_10_lines_and_func() {
-    println!("\n\nRunning test: 10 lines until breakpoint. Filename+Line enabled.");
-    let mut additional_data = serde_json::Map::new();
-    additional_data.insert(
-        "include".to_string(),
-        serde_json::Value::Array(vec![serde_json::Value::String(
-            "./linux/**/*.c".to_string(),
-        )]),
-    );
-
-    additional_data.insert("exclude".to_string(), serde_json::Value::Array(Vec::new()));
-    additional_data.insert(
-        "log_file".to_string(),
-        serde_json::Value::String("./linux.log".to_string()),
-    );
-    additional_data.insert(
-        "log_pattern".to_string(),
-        serde_json::Value::String(
-            "\\[(?P<file>[^:]+):(?P<line>\\d+)\\] (?P<message>.*)$".to_string(),
-        ),
+    let data = linux_settings(r"\[(?P<file>[^:]+):(?P<line>\d+)\] (?P<message>.*)$");
+    run_linux_test(data, "./linux.log", 30);
+}
+
+fn test_breakpoints_10_lines() {
+    let data = linux_settings(r"\[([^:]+):(?P<line>\d+)\] (?P<message>.*)$");
+    run_linux_test(data, "./linux.log", 30);
+}
+
+fn test_breakpoints_in_source_lines() {
+    let data = linux_settings(r"\[([^:]+):(?P<line>\d+)\] (?P<message>.*)$");
+
+    // Find line 30 of the log in the source
+    let contents = std::fs::read_to_string("./linux.log").unwrap();
+    let line = contents.lines().nth(30 - 1).unwrap();
+    let captures = Regex::new(LOCATION_PATTERN)
+        .unwrap()
+        .captures(line)
+        .unwrap();
+    let line_number = captures["line"].parse().unwrap();
+    run_linux_test(data, &captures["file"], line_number);
+}
+
+fn test_launch_program() {
+    let source = fixture("scenarios/src/app.c");
+    let mut session = Session::launch(json!({
+        "program": fixture("scenarios/app.sh"),
+        "log_pattern": LOCATION_PATTERN,
+    }));
+    session.set_breakpoints(&source, json!([{"line": 7}]));
+    for n in 1..=2 {
+        session.success("continue", json!({"threadId": 0}));
+        session.wait_stopped("breakpoint");
+        let frame = &session.stack_frames(0)[0];
+        assert_eq!(frame["source"]["path"], source);
+        assert_eq!(frame["line"], 7);
+        assert_eq!(
+            session.locals(0)["message"],
+            format!("processing request number {}", n)
+        );
+    }
+    session.disconnect();
+
+    // A program paused at a breakpoint only runs again to log what is stepped to
+    let mut session = Session::launch(json!({
+        "program": fixture("scenarios/slow.sh"),
+        "log_pattern": LOCATION_PATTERN,
+        "pause_on_breakpoint": true,
+    }));
+    session.set_breakpoints(&source, json!([{"line": 7}]));
+    session.success("continue", json!({"threadId": 0}));
+    session.wait_stopped("breakpoint");
+    let log_lines = |session: &mut Session| {
+        let error = session.failure("evaluate", json!({"expression": "goto 100"}));
+        error.rsplit(", ").next().unwrap().to_string()
+    };
+    assert_eq!(log_lines(&mut session), "which has 2 lines");
+    session.success("stepBack", json!({"threadId": 0}));
+    session.wait_stopped("step");
+    std::thread::sleep(Duration::from_secs(1));
+    assert_eq!(log_lines(&mut session), "which has 2 lines");
+    session.step(0);
+    assert_eq!(log_lines(&mut session), "which has 2 lines");
+    session.step(0);
+    session.success("continue", json!({"threadId": 0}));
+    session.wait_stopped("breakpoint");
+    assert_eq!(session.locals(0)["message"], "processing request number 2");
+    session.disconnect();
+
+    // The program not starting is an error of the launch request
+    let mut session = Session::new();
+    let error = session.failure(
+        "launch",
+        settings(json!({
+            "program": fixture("scenarios/missing.sh"),
+            "log_pattern": LOCATION_PATTERN,
+        })),
     );
+    assert!(error.contains("Unable to launch"), "{}", error);
+}
 
-    let breakpoints = vec![Breakpoint {
-        path: "./linux.log".to_string(),
-        line: 30,
-    }];
-    run_test(additional_data, &breakpoints);
+// A port nobody listens on
+fn free_address() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
 }
 
-fn test_breakpoints_10_lines() {
-    println!("\n\nRunning test: 10 lines until breakpoint. Line numbers enabled only");
-    let mut additional_data = serde_json::Map::new();
-    additional_data.insert(
-        "include".to_string(),
-        serde_json::Value::Array(vec![serde_json::Value::String(
-            "./linux/**/*.c".to_string(),
-        )]),
+fn test_attach_socket() {
+    let address = free_address();
+    let mut session = Session::new();
+    session.success(
+        "attach",
+        settings(json!({"listen": address, "log_pattern": LOCATION_PATTERN})),
     );
+    let source = fixture("scenarios/src/app.c");
+    session.set_breakpoints(&source, json!([{"line": 8}]));
 
-    additional_data.insert("exclude".to_string(), serde_json::Value::Array(Vec::new()));
-    additional_data.insert(
-        "log_file".to_string(),
-        serde_json::Value::String("./linux.log".to_string()),
+    // We stop at the entry once the first line arrives
+    let mut stream = TcpStream::connect(&address).unwrap();
+    stream
+        .write_all(
+            std::fs::read_to_string(fixture("scenarios/app.log"))
+                .unwrap()
+                .as_bytes(),
+        )
+        .unwrap();
+    session.wait_stopped("entry");
+    assert_eq!(session.locals(0)["message"], "starting up the engine");
+    session.success("continue", json!({"threadId": 0}));
+    session.wait_stopped("breakpoint");
+    assert_eq!(session.locals(0)["message"], "request 1 took 10 ms");
+    session.disconnect();
+
+    let mut session = Session::new();
+    let error = session.failure(
+        "attach",
+        settings(json!({"connect": free_address(), "log_pattern": LOCATION_PATTERN})),
     );
-    additional_data.insert(
-        "log_pattern".to_string(),
-        serde_json::Value::String("\\[([^:]+):(?P<line>\\d+)\\] (?P<message>.*)$".to_string()),
+    assert!(error.contains("Unable to connect"), "{}", error);
+
+    // A Unix socket is listened on again by the next session, even if a crash left its file behind
+    let path = std::env::temp_dir().join(format!("retread-{}.sock", std::process::id()));
+    drop(UnixListener::bind(&path).unwrap());
+    for _ in 0..2 {
+        let mut session = Session::new();
+        session.success(
+            "attach",
+            settings(json!({
+                "listen": format!("unix:{}", path.display()),
+                "log_pattern": LOCATION_PATTERN,
+            })),
+        );
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream
+            .write_all(b"[src/app.c:13] starting up the engine\n")
+            .unwrap();
+        session.wait_stopped("entry");
+        assert_eq!(session.locals(0)["message"], "starting up the engine");
+        session.disconnect();
+        assert!(!path.exists(), "{} was left behind", path.display());
+    }
+}
+
+fn test_json_log() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/app.jsonl"),
+        "log_format": "json",
+        "log_fields": {"message": "/fields/message", "file": "filename", "line": "/line_number"},
+    }));
+    assert_eq!(session.stack_frames(0)[0]["line"], 13);
+    let locals = session.locals(0);
+    assert_eq!(locals["message"], "starting up the engine");
+    assert_eq!(locals["level"], "INFO");
+    assert_eq!(locals["fields.port"], "8080");
+
+    session.step(0);
+    assert_eq!(session.stack_frames(0)[0]["line"], 7);
+    assert_eq!(session.locals(0)["client"], "10.0.0.7");
+
+    // Lines that are no JSON are kept as the message
+    session.step(0);
+    assert_eq!(session.stack_frames(0)[0]["line"], 16);
+    assert_eq!(
+        session.locals(0)["message"],
+        "this is not json: shutting down cleanly"
     );
+    session.disconnect();
+}
 
-    let breakpoints = vec![Breakpoint {
-        path: "./linux.log".to_string(),
-        line: 30,
-    }];
-    run_test(additional_data, &breakpoints);
+fn test_logfmt_log() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/app.logfmt"),
+        "log_format": "logfmt",
+    }));
+    // The caller is split into the file and line
+    assert_eq!(session.stack_frames(0)[0]["line"], 13);
+    let locals = session.locals(0);
+    assert_eq!(locals["message"], "starting up the engine");
+    assert_eq!(locals["port"], "80");
+
+    session.step(0);
+    assert_eq!(session.stack_frames(0)[0]["line"], 7);
+    let locals = session.locals(0);
+    assert_eq!(locals["level"], "WARN");
+    assert_eq!(locals["path"], r#"/a "b""#);
+
+    session.step(0);
+    assert_eq!(session.stack_frames(0)[0]["line"], 16);
+    assert_eq!(
+        session.locals(0)["message"],
+        "plain text line shutting down cleanly"
+    );
+    session.disconnect();
 }
 
-fn test_breakpoints_in_source_lines() {
-    println!("\n\nRunning test: 10 lines until breakpoint (in source). Line numbers enabled only");
-    let mut additional_data = serde_json::Map::new();
-    additional_data.insert(
-        "include".to_string(),
-        serde_json::Value::Array(vec![serde_json::Value::String(
-            "./linux/**/*.c".to_string(),
-        )]),
+fn test_log_preset() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/net.glog"),
+        "log_preset": "glog",
+    }));
+    // glog logs the id of the thread
+    let threads = session.success("threads", Value::Null);
+    assert_eq!(threads["threads"], json!([{"id": 1, "name": "1234"}]));
+    assert_eq!(session.stack_frames(1)[0]["name"], "net.cc:6");
+    let locals = session.locals(1);
+    assert_eq!(locals["message"], "connection opened to example.com");
+    assert_eq!(locals["level"], "INFO");
+
+    session.step(1);
+    assert_eq!(session.stack_frames(1)[0]["name"], "net.cc:7");
+    assert_eq!(session.locals(1)["level"], "WARN");
+    session.disconnect();
+
+    let mut session = Session::new();
+    let error = session.failure(
+        "launch",
+        settings(json!({"log_file": fixture("scenarios/net.glog"), "log_preset": "nope"})),
     );
-    additional_data.insert("exclude".to_string(), serde_json::Value::Array(Vec::new()));
-    additional_data.insert(
-        "log_file".to_string(),
-        serde_json::Value::String("./linux.log".to_string()),
+    assert!(error.starts_with("Unknown log_preset [nope]"), "{}", error);
+}
+
+fn test_infer_log_pattern() {
+    let mut session = Session::new();
+    session.success(
+        "launch",
+        settings(json!({"log_file": fixture("scenarios/app.log")})),
     );
-    additional_data.insert(
-        "log_pattern".to_string(),
-        serde_json::Value::String("\\[([^:]+):(?P<line>\\d+)\\] (?P<message>.*)$".to_string()),
+    let output = session.wait_output("No log_pattern given");
+    assert!(
+        output.contains("matches 100% of the sampled lines"),
+        "{}",
+        output
     );
+    session.wait_stopped("entry");
+    let locals = session.locals(0);
+    assert_eq!(locals["file"], "src/app.c");
+    assert_eq!(locals["message"], "starting up the engine");
+    session.disconnect();
 
-    // Find line 10 in the source
-    let contents = std::fs::read_to_string("./linux.log").unwrap();
-    let line = contents.lines().nth(30 - 1).unwrap();
-    let regex = Regex::new(r"\[(?P<file>[^:]+):(?P<line>\d+)\] (?P<message>.*)$").unwrap();
-    let line_number = regex
-        .captures(line)
+    // A preset is used if one fits
+    let mut session = Session::new();
+    session.success(
+        "launch",
+        settings(json!({"log_file": fixture("scenarios/net.glog")})),
+    );
+    let output = session.wait_output("No log_pattern given");
+    assert!(output.contains("using the glog preset"), "{}", output);
+    session.wait_stopped("entry");
+    assert_eq!(session.stack_frames(1)[0]["name"], "net.cc:6");
+    session.disconnect();
+
+    // Along with how the preset reads its timestamps, like the milliseconds since boot of ESP-IDF
+    let mut session = Session::new();
+    session.success(
+        "launch",
+        settings(json!({"log_file": fixture("scenarios/app.espidf")})),
+    );
+    let output = session.wait_output("No log_pattern given");
+    assert!(output.contains("using the esp-idf preset"), "{}", output);
+    session.wait_stopped("entry");
+    session.step(0);
+    let locals = session.locals(0);
+    assert_eq!(locals["time"], "1.215000s");
+    assert_eq!(locals["delta"], "+15.000ms");
+    session.step(0);
+    assert_eq!(session.locals(0)["delta"], "+25.000ms");
+    session.disconnect();
+}
+
+fn test_log_patterns() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/mixed.log"),
+        "log_pattern": [
+            LOCATION_PATTERN,
+            r"^(?P<level>[IWEF])\d{4} [\d:.]+\s+\d+ (?P<file>[^:\s]+):(?P<line>\d+)\] (?P<message>.*)$",
+        ],
+    }));
+    // The boot line matches no pattern, and is skipped
+    let expected = [
+        ("app.c:13", "0", "2"),
+        ("net.cc:6", "1", "3"),
+        ("app.c:16", "0", "4"),
+    ];
+    for (i, (name, pattern, log_line)) in expected.into_iter().enumerate() {
+        if i > 0 {
+            session.step(0);
+        }
+        assert_eq!(session.stack_frames(0)[0]["name"], name);
+        let locals = session.locals(0);
+        assert_eq!(locals["pattern"], pattern);
+        assert_eq!(locals["log_line"], log_line);
+    }
+    session.disconnect();
+}
+
+fn test_timestamps() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/app.dmesg"),
+        "log_preset": "dmesg",
+    }));
+    let locals = session.locals(0);
+    assert_eq!(locals["time"], "0.000000s");
+    assert!(!locals.contains_key("delta"));
+    session.step(0);
+    session.step(0);
+    let locals = session.locals(0);
+    assert_eq!(locals["time"], "75.250000s");
+    assert_eq!(locals["delta"], "+0h 01m 13.750000s");
+    assert_eq!(locals["elapsed"], "+0h 01m 15.250000s");
+    session.disconnect();
+
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/app.timed.log"),
+        "log_pattern": r"^(?P<timestamp>\S+ \S+) \[(?P<file>[^:]+):(?P<line>\d+)\] (?P<message>.*)$",
+        "timestamp_format": "%d/%m/%Y %H:%M:%S",
+    }));
+    session.step(0);
+    let locals = session.locals(0);
+    assert_eq!(locals["time"], "2023-07-01 10:00:02.000000");
+    assert_eq!(locals["delta"], "+2.000000s");
+    session.disconnect();
+}
+
+fn test_min_level() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/levels.glog"),
+        "log_preset": "glog",
+        "min_level": "warn",
+    }));
+    assert_eq!(session.locals(1)["level"], "WARN");
+    session.step(1);
+    let locals = session.locals(1);
+    assert_eq!(locals["level"], "ERROR");
+    assert_eq!(locals["log_line"], "4");
+
+    // The level can be changed while debugging
+    let level = |session: &mut Session, expression: &str| {
+        session.success(
+            "evaluate",
+            json!({"expression": expression, "context": "repl"}),
+        )["result"]
+            .clone()
+    };
+    assert_eq!(level(&mut session, "level"), "Skipping entries below WARN");
+    assert_eq!(
+        level(&mut session, "level info"),
+        "Skipping entries below INFO"
+    );
+    let error = session.failure(
+        "evaluate",
+        json!({"expression": "level bogus", "context": "repl"}),
+    );
+    assert!(error.starts_with("Unknown log level [bogus]"), "{}", error);
+    session.success("stepBack", json!({"threadId": 1}));
+    session.wait_stopped("step");
+    assert_eq!(session.locals(1)["log_line"], "3");
+    assert_eq!(
+        level(&mut session, "level off"),
+        "Showing entries of all levels"
+    );
+    session.disconnect();
+}
+
+fn test_threads() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/threads.glog"),
+        "log_preset": "glog",
+    }));
+    let threads = session.success("threads", Value::Null);
+    assert_eq!(
+        threads["threads"],
+        json!([{"id": 1, "name": "11"}, {"id": 2, "name": "22"}])
+    );
+
+    // Stepping a thread skips the entries of the others
+    session.success("next", json!({"threadId": 1}));
+    let stopped = session.wait_stopped("step");
+    assert_eq!(stopped["threadId"], 1);
+    assert_eq!(stopped["description"], "retrying request 2");
+    // The others show their latest entry
+    assert_eq!(session.locals(2)["log_line"], "2");
+
+    session.success("next", json!({"threadId": 1}));
+    assert_eq!(
+        session.wait_stopped("step")["description"],
+        "retrying request 3"
+    );
+    assert_eq!(session.locals(2)["log_line"], "4");
+
+    session.success("stepBack", json!({"threadId": 2}));
+    let stopped = session.wait_stopped("step");
+    assert_eq!(stopped["threadId"], 2);
+    assert_eq!(session.locals(2)["log_line"], "4");
+    session.disconnect();
+}
+
+fn test_text_encoding() {
+    let latin1 = |mut data: Value| {
+        data["log_file"] = json!(fixture("scenarios/latin1/menu.log"));
+        data["log_pattern"] = json!(LOCATION_PATTERN);
+        data["include"] = json!([fixture("scenarios/latin1/src/**/*")]);
+        data
+    };
+    let mut session = Session::launch(latin1(json!({
+        "log_encoding": "latin1",
+        "source_encoding": "latin1",
+    })));
+    assert_eq!(session.stack_frames(0)[0]["name"], "menu.c:5");
+    let locals = session.locals(0);
+    assert_eq!(locals["message"], "café ouvert 3");
+    assert_eq!(locals["n"], "3");
+    session.disconnect();
+
+    // Read as UTF-8, the invalid characters are replaced, with a warning
+    let mut session = Session::new();
+    session.success("launch", settings(latin1(json!({}))));
+    let output = session.wait_output("1 files are not valid UTF-8");
+    assert!(output.contains("menu.c"), "{}", output);
+    session.wait_stopped("entry");
+    assert_eq!(session.locals(0)["message"], "caf\u{fffd} ouvert 3");
+    session.disconnect();
+}
+
+fn test_terminal_output() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/ansi.log"),
+        "log_pattern": LOCATION_PATTERN,
+    }));
+    // Colors are stripped, and the line as it was logged is kept as `raw`
+    let locals = session.locals(0);
+    assert_eq!(locals["message"], "starting up the engine");
+    assert_eq!(
+        locals["raw"],
+        "\x1b[32m[src/app.c:13] starting up the engine\x1b[0m"
+    );
+
+    // What a carriage return overwrote is gone
+    session.step(0);
+    assert_eq!(session.stack_frames(0)[0]["line"], 7);
+    assert_eq!(session.locals(0)["n"], "1");
+    session.disconnect();
+}
+
+fn test_journal() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("journal.export"),
+        "log_format": "journal",
+        "include": [fixture("scenarios/journal/src/**/*")],
+    }));
+    let threads = session.success("threads", Value::Null);
+    assert_eq!(
+        threads["threads"],
+        json!([{"id": 1, "name": "811/812"}, {"id": 2, "name": "811/813"}])
+    );
+    assert_eq!(session.stack_frames(1)[0]["name"], "accept_conn:42");
+    let locals = session.locals(1);
+    assert_eq!(locals["level"], "INFO");
+    assert_eq!(locals["time"], "2024-06-01 07:00:00.123456");
+    assert_eq!(locals["_COMM"], "demo-server");
+
+    // Messages with a newline are binary fields of the export
+    session.step(2);
+    assert_eq!(session.stack_frames(2)[0]["name"], "handle_request:88");
+    assert_eq!(
+        session.locals(2)["message"],
+        "slow request took 1532 ms\nquery: SELECT 1"
+    );
+    session.disconnect();
+}
+
+fn test_logcat() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("logcat.bin"),
+        "log_format": "logcat-binary",
+        "include": [fixture("scenarios/logcat/src/**/*")],
+    }));
+    let threads = session.success("threads", Value::Null);
+    assert_eq!(
+        threads["threads"],
+        json!([{"id": 1, "name": "1234/1234"}, {"id": 2, "name": "1234/1250"}])
+    );
+    // Without a file and line, the message is searched for
+    assert_eq!(session.stack_frames(1)[0]["name"], "DemoService.java:7");
+    let locals = session.locals(1);
+    assert_eq!(locals["TAG"], "DemoService");
+    assert_eq!(locals["level"], "INFO");
+    assert_eq!(locals["time"], "2024-06-01 07:00:00.123000");
+
+    session.step(2);
+    assert_eq!(session.stack_frames(2)[0]["name"], "DemoService.java:11");
+    assert_eq!(session.locals(2)["level"], "DEBUG");
+    session.disconnect();
+}
+
+fn test_tokenized_logs() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("defmt-capture.bin"),
+        "log_format": "defmt",
+        "token_database": fixture("defmt-firmware.elf"),
+        "include": [fixture("firmware/src/**/*")],
+    }));
+    // The location comes with the format string, so the match is exact
+    let frame = &session.stack_frames(0)[0];
+    assert_eq!(frame["source"]["path"], fixture("firmware/src/main.rs"));
+    assert_eq!(frame["line"], 72);
+    let locals = session.locals(0);
+    assert_eq!(locals["message"], "booting firmware v1.4");
+    assert_eq!(locals["score"], "exact");
+    assert_eq!(locals["time"], "0.001500s");
+
+    session.step(0);
+    assert_eq!(session.stack_frames(0)[0]["line"], 73);
+    let locals = session.locals(0);
+    assert_eq!(locals["message"], "sensor 3 read -1250 mV");
+    assert_eq!(locals["level"], "DEBUG");
+    session.disconnect();
+
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/pw/tokens.log"),
+        "log_format": "pw_tokenizer",
+        "token_database": fixture("pw-tokenizer.elf"),
+        "include": [fixture("scenarios/pw/**/*")],
+    }));
+    assert_eq!(session.stack_frames(0)[0]["name"], "battery.c:5");
+    let locals = session.locals(0);
+    assert_eq!(locals["message"], "Battery at 87%");
+    assert_eq!(locals["percent"], "87");
+
+    session.step(0);
+    assert_eq!(session.stack_frames(0)[0]["name"], "motor.c:6");
+    let locals = session.locals(0);
+    assert_eq!(locals["message"], "Motor left stalled at 1200 rpm");
+    assert_eq!(locals["token"], "0a1b2c3d");
+    session.disconnect();
+}
+
+fn test_kernel_oops() {
+    let frames = |symbol_file: &str| {
+        let mut session = Session::launch(json!({
+            "log_file": fixture("scenarios/oops.log"),
+            "log_pattern": r"^\[\s*(?P<timestamp>[\d.]+)\] (?P<message>.*)$",
+            "symbol_file": fixture(symbol_file),
+            "include": [fixture("kernel/**/*")],
+        }));
+        // Stopped at the `BUG:` line, the backtrace below it is shown
+        session.step(0);
+        let frames: Vec<_> = session
+            .stack_frames(0)
+            .into_iter()
+            .filter(|f| f["instruction_pointer_reference"].is_string())
+            .map(|f| {
+                assert_eq!(f["source"]["path"], fixture("kernel/kernel-symbols.c"));
+                (
+                    f["name"].as_str().unwrap().to_string(),
+                    f["line"].as_i64().unwrap(),
+                )
+            })
+            .collect();
+        session.disconnect();
+        frames
+    };
+    let frame = |name: &str, line| (name.to_string(), line);
+
+    // The DWARF of the ELF has the lines, and the functions inlined into them
+    assert_eq!(
+        frames("kernel-symbols.elf"),
+        [
+            frame("bump (inlined)", 12),
+            frame("ext4_readdir+0x14", 17),
+            frame("iterate_dir+0x5", 23),
+            frame("start_kernel+0x9", 29),
+        ]
+    );
+    // A System.map only has the functions, which are searched for
+    assert_eq!(
+        frames("kernel-symbols.map"),
+        [
+            frame("ext4_readdir+0x14", 15),
+            frame("iterate_dir+0x5", 21),
+            frame("start_kernel+0x9", 27),
+        ]
+    );
+}
+
+fn test_exception() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/python/app.log"),
+        "log_pattern": r"^(?P<level>[A-Z]+) (?P<message>.*)$",
+        "include": [fixture("scenarios/python/app/**/*")],
+    }));
+    // The traceback logged after the entry makes it an exception
+    session.success("next", json!({"threadId": 0}));
+    let stopped = session.wait_stopped("exception");
+    assert_eq!(stopped["text"], "KeyError");
+    let frames: Vec<_> = session
+        .stack_frames(0)
+        .iter()
+        .map(|f| {
+            (
+                f["name"].as_str().unwrap().to_string(),
+                f["line"].as_i64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        frames[..3],
+        [
+            ("config.py:15".to_string(), 15),
+            ("load".to_string(), 7),
+            ("main".to_string(), 13),
+        ]
+    );
+
+    let info = session.success("exceptionInfo", json!({"threadId": 0}));
+    assert_eq!(info["exceptionId"], "KeyError");
+    assert_eq!(info["description"], "'cfg'");
+    assert!(info["details"]["stack_trace"]
+        .as_str()
+        .unwrap()
+        .starts_with("Traceback (most recent call last):"));
+
+    // The lines of the traceback are no entries of their own
+    session.step(0);
+    assert_eq!(session.locals(0)["message"], "shutting down");
+    let error = session.failure("exceptionInfo", json!({"threadId": 0}));
+    assert_eq!(error, "No panic or exception was logged here");
+    session.disconnect();
+}
+
+fn test_spans() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/spans/app.log"),
+        "log_pattern": r"^(?P<timestamp>\S+)\s+(?P<level>[A-Z]+) (?P<message>.*)$",
+        "include": [fixture("scenarios/spans/src/**/*")],
+    }));
+    // The frames between the entry and the log, innermost first
+    let spans = |session: &mut Session| {
+        let frames = session.stack_frames(0);
+        frames[1..frames.len() - 1]
+            .iter()
+            .map(|f| {
+                let fields = session.variables(f["id"].as_i64().unwrap());
+                (
+                    f["name"].as_str().unwrap().to_string(),
+                    f["line"].as_i64().unwrap(),
+                    fields,
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let span = |name: &str, line, fields: &[(&str, &str)]| {
+        let fields = fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        (name.to_string(), line, fields)
+    };
+
+    // tracing names the spans of the entry
+    assert_eq!(
+        spans(&mut session),
+        [
+            span("db", 5, &[("table", "users")]),
+            span("handle_request", 4, &[("id", "7")]),
+        ]
+    );
+
+    // Otherwise the markers before it do, until they are left
+    session.step(0);
+    assert_eq!(spans(&mut session), [span("parse_header", 10, &[])]);
+    session.step(0);
+    session.step(0);
+    assert_eq!(
+        spans(&mut session),
+        [
+            span("decode", 17, &[("len", "12")]),
+            span("parse_header", 10, &[]),
+        ]
+    );
+    session.step(0);
+    session.step(0);
+    assert_eq!(spans(&mut session).len(), 2);
+    session.step(0);
+    assert_eq!(spans(&mut session), [span("parse_header", 10, &[])]);
+    session.disconnect();
+}
+
+fn test_capture_variables() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/hosts.log"),
+        "log_pattern": r"^(?P<host>\S+) (?P<pid>\d+) \[(?P<file>[^:]+):(?P<line>\d+)\] (?P<message>.*)$",
+    }));
+    let scopes = session.success("scopes", json!({"frameId": 0}));
+    let scope = &scopes["scopes"][0];
+    assert_eq!(scope["name"], "Locals");
+    assert_eq!(scope["line"], 13);
+    let reference = scope["variables_reference"].clone();
+    let locals = session.locals(1);
+    assert_eq!(locals["host"], "web-1");
+    assert_eq!(locals["pid"], "311");
+    assert_eq!(locals["file"], "src/app.c");
+    assert_eq!(locals["log_line"], "1");
+    assert_eq!(locals["score"], "19");
+
+    // The references are only valid until we stop again
+    session.step(1);
+    let error = session.failure("variables", json!({"variablesReference": reference}));
+    assert!(
+        error.starts_with("Unknown variables reference"),
+        "{}",
+        error
+    );
+    assert_eq!(session.locals(1)["host"], "web-2");
+    session.disconnect();
+}
+
+fn test_format_arguments() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/app.log"),
+        "log_pattern": LOCATION_PATTERN,
+    }));
+    let source = fixture("scenarios/src/app.c");
+    session.set_breakpoints(&source, json!([{"line": 8}]));
+    session.success("continue", json!({"threadId": 0}));
+    session.wait_stopped("breakpoint");
+
+    // The placeholders are named after the arguments of the printf
+    let locals = session.locals(0);
+    assert_eq!(locals["n"], "1");
+    assert_eq!(locals["ms"], "10");
+    session.success("continue", json!({"threadId": 0}));
+    session.wait_stopped("breakpoint");
+    let locals = session.locals(0);
+    assert_eq!(locals["n"], "2");
+    assert_eq!(locals["ms"], "20");
+    session.disconnect();
+}
+
+fn test_payload_variables() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/payload/orders.log"),
+        "log_pattern": LOCATION_PATTERN,
+        "include": [fixture("scenarios/payload/src/**/*")],
+    }));
+    // The variables in the container, with their own references
+    let children = |session: &mut Session, reference: &Value| {
+        let body = session.success("variables", json!({"variablesReference": reference}));
+        body["variables"].as_array().unwrap().clone()
+    };
+    let find = |variables: &[Value], name: &str| {
+        variables
+            .iter()
+            .find(|v| v["name"] == name)
+            .unwrap_or_else(|| panic!("No variable {} in {:?}", name, variables))
+            .clone()
+    };
+
+    let body = session.success("scopes", json!({"frameId": 0}));
+    let locals = children(&mut session, &body["scopes"][0]["variables_reference"]);
+    let payload = find(&locals, "payload");
+    assert_eq!(payload["named_variables"], 2);
+    let payload = children(&mut session, &payload["variables_reference"]);
+    assert_eq!(find(&payload, "user")["value"], "7");
+    let items = find(&payload, "items");
+    assert_eq!(items["indexed_variables"], 2);
+    assert_eq!(items["evaluate_name"], "payload.items");
+    let items = children(&mut session, &items["variables_reference"]);
+    assert_eq!(find(&items, "0")["value"], "1");
+    let item = children(&mut session, &find(&items, "1")["variables_reference"]);
+    assert_eq!(find(&item, "id")["evaluate_name"], "payload.items[1].id");
+    assert_eq!(find(&item, "id")["value"], "2");
+
+    // The key=value pairs of a message are a payload as well
+    session.step(0);
+    let body = session.success("scopes", json!({"frameId": 0}));
+    let locals = children(&mut session, &body["scopes"][0]["variables_reference"]);
+    let payload = children(
+        &mut session,
+        &find(&locals, "payload")["variables_reference"],
+    );
+    assert_eq!(find(&payload, "status")["value"], "200");
+    assert_eq!(find(&payload, "ms")["value"], "12");
+    session.disconnect();
+}
+
+fn test_variable_history() {
+    let log_file = fixture("scenarios/app.log");
+    let mut session = Session::launch(json!({
+        "log_file": log_file,
+        "log_pattern": LOCATION_PATTERN,
+    }));
+    session.set_breakpoints(&fixture("scenarios/src/app.c"), json!([{"line": 8}]));
+    session.success("continue", json!({"threadId": 0}));
+    session.wait_stopped("breakpoint");
+
+    // A variable expands to its values at every entry of the same call
+    let body = session.success("scopes", json!({"frameId": 0}));
+    let reference = body["scopes"][0]["variables_reference"].clone();
+    let body = session.success("variables", json!({"variablesReference": reference}));
+    let ms = body["variables"]
+        .as_array()
         .unwrap()
-        .name("line")
+        .iter()
+        .find(|v| v["name"] == "ms")
+        .unwrap()
+        .clone();
+    let body = session.success(
+        "variables",
+        json!({"variablesReference": ms["variables_reference"]}),
+    );
+    let history: Vec<_> = body["variables"]
+        .as_array()
         .unwrap()
+        .iter()
+        .map(|v| (v["name"].as_str().unwrap(), v["value"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        history,
+        [("line 3", "10"), ("line 5", "20"), ("line 7", "30")]
+    );
+
+    // Selecting an entry evaluates its name, which jumps to it
+    let expression = body["variables"][1]["evaluate_name"].clone();
+    assert_eq!(expression, "goto 5");
+    session.success("evaluate", json!({"expression": expression}));
+    session.wait_stopped("goto");
+    assert_eq!(session.locals(0)["log_line"], "5");
+
+    // As does the history command of the debug console
+    let body = session.success("evaluate", json!({"expression": "history n"}));
+    let lines: Vec<_> = body["result"]
         .as_str()
-        .parse()
-        .unwrap();
-    let file_name = regex.captures(line).unwrap().name("file").unwrap().as_str();
+        .unwrap()
+        .lines()
+        .map(|l| l.split_whitespace().collect::<Vec<_>>())
+        .collect();
+    assert_eq!(lines, [["3", "1"], ["5", "2"], ["7", "3"]]);
+    let error = session.failure("evaluate", json!({"expression": "history missing"}));
+    assert_eq!(error, "No values of missing were logged");
 
-    let breakpoints = vec![Breakpoint {
-        path: file_name.to_string(),
-        line: line_number,
-    }];
-    run_test(additional_data, &breakpoints);
+    // An entry of the history is jumped to by its line of the log
+    let body = session.success(
+        "gotoTargets",
+        json!({"source": {"path": log_file}, "line": 7}),
+    );
+    let target = body["targets"][0]["id"].clone();
+    assert_eq!(target, 7);
+    session.success("goto", json!({"threadId": 0, "targetId": target}));
+    session.wait_stopped("goto");
+    assert_eq!(session.locals(0)["ms"], "30");
+    session.success("evaluate", json!({"expression": "goto 5"}));
+    session.wait_stopped("goto");
+    assert_eq!(session.locals(0)["ms"], "20");
+    session.disconnect();
+}
+
+fn test_conditional_breakpoints() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/app.log"),
+        "log_pattern": LOCATION_PATTERN,
+    }));
+    let source = fixture("scenarios/src/app.c");
+    let body = session.set_breakpoints(
+        &source,
+        json!([
+            {"line": 7, "condition": "message =~ /number 3$/"},
+            {"line": 8, "condition": "ms > 15 && n != 3"},
+            {"line": 16, "condition": "ms >"},
+        ]),
+    );
+    let breakpoints = body["breakpoints"].as_array().unwrap();
+    assert_eq!(breakpoints[0]["verified"], true);
+    assert_eq!(breakpoints[1]["verified"], true);
+    // A bad condition is sent back, rather than stopping at every hit
+    assert_eq!(breakpoints[2]["verified"], false);
+    assert_eq!(
+        breakpoints[2]["message"],
+        "Expected a value at the end of the condition"
+    );
+
+    let mut stops = Vec::new();
+    for _ in 0..2 {
+        session.success("continue", json!({"threadId": 0}));
+        session.wait_stopped("breakpoint");
+        stops.push(session.locals(0)["log_line"].clone());
+    }
+    assert_eq!(stops, ["5", "6"]);
+    session.success("continue", json!({"threadId": 0}));
+    // Nothing else holds, so we run to the end of the log
+    session.wait_stopped("entry");
+    assert_eq!(session.locals(0)["log_line"], "8");
+    session.disconnect();
+}
+
+fn test_hit_conditions() {
+    let log_file = fixture("scenarios/app.log");
+    let mut session = Session::launch(json!({
+        "log_file": log_file,
+        "log_pattern": LOCATION_PATTERN,
+    }));
+    // The line of the log we run to
+    let run = |session: &mut Session, command: &str, reason: &str| {
+        session.success(command, json!({"threadId": 0}));
+        session.wait_stopped(reason);
+        session.locals(0)["log_line"].clone()
+    };
+    session.set_breakpoints(
+        &fixture("scenarios/src/app.c"),
+        json!([{"line": 8, "hit_condition": "== 2"}]),
+    );
+    assert_eq!(run(&mut session, "continue", "breakpoint"), "5");
+    assert_eq!(run(&mut session, "continue", "entry"), "8");
+
+    // Turning around at the end of the log counts the hits from the start
+    assert_eq!(run(&mut session, "reverseContinue", "breakpoint"), "5");
+
+    // Lines of the log are hit once in each direction
+    session.set_breakpoints(
+        &log_file,
+        json!([
+            {"line": 2, "hit_condition": "== 2"},
+            {"line": 4, "hit_condition": "% 1 == 0"},
+        ]),
+    );
+    assert_eq!(run(&mut session, "reverseContinue", "breakpoint"), "4");
+    assert_eq!(run(&mut session, "reverseContinue", "entry"), "1");
+    session.disconnect();
+}
+
+fn test_logpoints() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/app.log"),
+        "log_pattern": LOCATION_PATTERN,
+    }));
+    let source = fixture("scenarios/src/app.c");
+    session.set_breakpoints(
+        &source,
+        json!([{"line": 8, "log_message": "took {ms}ms for {n}, {missing}"}]),
+    );
+    session.success("continue", json!({"threadId": 0}));
+
+    // Logpoints print, without stopping us before the end of the log
+    let mut outputs = Vec::new();
+    loop {
+        let message = session.receive();
+        if message["event"] == "stopped" {
+            assert_eq!(message["body"]["reason"], "entry");
+            break;
+        }
+        let body = &message["body"];
+        if message["event"] == "output" && body["category"] == "stdout" {
+            assert_eq!(body["source"]["path"], source);
+            assert_eq!(body["line"], 8);
+            outputs.push(body["output"].as_str().unwrap().to_string());
+        }
+    }
+    // Variables that are not known are kept as they are
+    assert_eq!(
+        outputs,
+        [
+            "took 10ms for 1, {missing}\n",
+            "took 20ms for 2, {missing}\n",
+            "took 30ms for 3, {missing}\n",
+        ]
+    );
+    session.disconnect();
+}
+
+fn test_function_breakpoints() {
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/funcs.log"),
+        "log_pattern": r"^(?P<func>\w+)\(\) (?P<message>.*)$",
+    }));
+    assert_eq!(session.capabilities["supportsFunctionBreakpoints"], true);
+    let body = session.success(
+        "setFunctionBreakpoints",
+        json!({"breakpoints": [{"name": "/^han/"}, {"name": "/(/"}, {"name": " "}]}),
+    );
+    let messages: Vec<_> = body["breakpoints"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|b| (b["verified"].clone(), b["message"].clone()))
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            (json!(true), Value::Null),
+            (json!(false), json!("Invalid function name [/(/]")),
+            (
+                json!(false),
+                json!("Function breakpoints need the name of a function")
+            ),
+        ]
+    );
+
+    // The func of the entries logged in handle
+    let mut stops = Vec::new();
+    for _ in 0..2 {
+        session.success("continue", json!({"threadId": 0}));
+        session.wait_stopped("breakpoint");
+        stops.push(session.locals(0)["log_line"].clone());
+    }
+    assert_eq!(stops, ["2", "3"]);
+    session.success("continue", json!({"threadId": 0}));
+    session.wait_stopped("entry");
+    session.disconnect();
+
+    // Without a func, the function around where the entry was found
+    let mut session = Session::launch(json!({
+        "log_file": fixture("scenarios/app.log"),
+        "log_pattern": LOCATION_PATTERN,
+    }));
+    session.success(
+        "setFunctionBreakpoints",
+        json!({"breakpoints": [{"name": "mai?"}]}),
+    );
+    session.success("continue", json!({"threadId": 0}));
+    session.wait_stopped("breakpoint");
+    assert_eq!(session.stack_frames(0)[0]["line"], 16);
+    session.disconnect();
 }
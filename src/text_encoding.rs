@@ -0,0 +1,99 @@
+use std::borrow::Cow;
+
+use anyhow::*;
+use encoding_rs::{CoderResult, Decoder, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+/// How many bytes are looked at when deciding if a file is binary
+const BINARY_SNIFF_LEN: usize = 8000;
+
+/// The encoding of a log or the sources, e.g. `utf-8`, `latin1`, `utf-16` or `shift_jis`.
+/// A byte order mark overrides it, and invalid sequences are replaced instead of failing.
+#[derive(Clone, Copy, Debug)]
+pub struct TextEncoding(&'static Encoding);
+
+impl Default for TextEncoding {
+    fn default() -> Self {
+        TextEncoding(UTF_8)
+    }
+}
+
+impl TextEncoding {
+    pub fn new(label: &str) -> Result<Self> {
+        Encoding::for_label(label.trim().as_bytes())
+            .map(TextEncoding)
+            .context(format!("Unknown encoding [{}]", label))
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    /// Decodes the whole of `bytes`, and whether anything had to be replaced.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> (Cow<'a, str>, bool) {
+        let (text, _, lossy) = self.0.decode(bytes);
+        (text, lossy)
+    }
+
+    pub fn decoder(&self) -> LineDecoder {
+        LineDecoder {
+            decoder: self.0.new_decoder(),
+            pending: String::new(),
+            lossy: false,
+        }
+    }
+
+    /*
+    Like git, we take a NUL byte near the start to mean the file is binary.
+    UTF-16 text is full of NUL bytes, so it is never binary.
+    */
+    pub fn is_binary(&self, bytes: &[u8]) -> bool {
+        let utf16 = [UTF_16LE, UTF_16BE].contains(&self.0)
+            || bytes.starts_with(&[0xFF, 0xFE])
+            || bytes.starts_with(&[0xFE, 0xFF]);
+        !utf16 && bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0)
+    }
+}
+
+/// Decodes a stream of bytes, as they arrive, into lines.
+pub struct LineDecoder {
+    decoder: Decoder,
+    pending: String,
+    lossy: bool,
+}
+
+impl LineDecoder {
+    /// Decodes the next chunk of the stream, and returns the lines it completed.
+    /// At the end of the stream, whatever is left is returned as the last line.
+    pub fn push(&mut self, bytes: &[u8], last: bool) -> Vec<String> {
+        let mut bytes = bytes;
+        loop {
+            let needed = self
+                .decoder
+                .max_utf8_buffer_length(bytes.len())
+                .unwrap_or(bytes.len() * 3 + 16);
+            self.pending.reserve(needed);
+            let (result, read, had_errors) =
+                self.decoder.decode_to_string(bytes, &mut self.pending, last);
+            self.lossy |= had_errors;
+            bytes = &bytes[read..];
+            if let CoderResult::InputEmpty = result {
+                break;
+            }
+        }
+
+        let mut lines = Vec::new();
+        while let Some(end) = self.pending.find('\n') {
+            lines.push(self.pending[..end].trim_end_matches('\r').to_string());
+            self.pending.drain(..=end);
+        }
+        if last && !self.pending.is_empty() {
+            lines.push(std::mem::take(&mut self.pending));
+        }
+        lines
+    }
+
+    /// Whether any invalid characters had to be replaced so far
+    pub fn lossy(&self) -> bool {
+        self.lossy
+    }
+}
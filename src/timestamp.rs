@@ -0,0 +1,319 @@
+use std::fmt;
+
+use chrono::format::{parse, Parsed, StrftimeItems};
+use chrono::DateTime;
+
+/// Formats tried, in order, when no timestamp_format is given.
+const AUTO_FORMATS: [&str; 6] = [
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S,%3f",
+    "%Y/%m/%d %H:%M:%S%.f",
+    "%b %e %H:%M:%S",
+    "%H:%M:%S%.f",
+];
+
+/// How the `timestamp` capture is turned into a point in time.
+#[derive(Clone, Debug)]
+pub enum TimestampFormat {
+    /// Try the common formats, and fall back to a number of seconds, or of
+    /// milli, micro or nanoseconds since the epoch
+    Auto,
+    Rfc3339,
+    /// A chrono strftime format string. Missing date parts default to 1970-01-01.
+    Strftime(String),
+    /// Seconds since the unix epoch, with an optional fraction
+    Epoch,
+    /// Milliseconds since the unix epoch
+    EpochMillis,
+    /// Seconds since boot, e.g. the kernel's `[   12.345678]`, or `hh:mm:ss.fff`
+    Uptime,
+    /// Milliseconds since boot
+    UptimeMillis,
+}
+
+impl TimestampFormat {
+    pub fn new(format: &str) -> Self {
+        match format {
+            "auto" => TimestampFormat::Auto,
+            "rfc3339" => TimestampFormat::Rfc3339,
+            "epoch" => TimestampFormat::Epoch,
+            "epoch_ms" => TimestampFormat::EpochMillis,
+            "uptime" => TimestampFormat::Uptime,
+            "uptime_ms" => TimestampFormat::UptimeMillis,
+            f => TimestampFormat::Strftime(f.to_string()),
+        }
+    }
+
+    pub fn parse(&self, text: &str) -> Option<Timestamp> {
+        let text = text.trim();
+        match self {
+            TimestampFormat::Auto => parse_rfc3339(text)
+                .or_else(|| AUTO_FORMATS.iter().find_map(|f| parse_strftime(text, f)))
+                .or_else(|| {
+                    // Large numbers are most likely since the epoch, small ones since boot
+                    let number = text.trim_matches(['[', ']', ' ']);
+                    let seconds: f64 = number.parse().ok()?;
+                    let nanos_per_unit = match seconds {
+                        s if s > 1e17 => 1,
+                        s if s > 1e14 => 1_000,
+                        s if s > 1e11 => 1_000_000,
+                        _ => 1_000_000_000,
+                    };
+                    let nanos = parse_nanos(number, nanos_per_unit)?;
+                    match seconds > 1e9 {
+                        true => Some(Timestamp::absolute(nanos)),
+                        false => Some(Timestamp::uptime(nanos)),
+                    }
+                }),
+            TimestampFormat::Rfc3339 => parse_rfc3339(text),
+            TimestampFormat::Strftime(format) => parse_strftime(text, format),
+            TimestampFormat::Epoch => Some(Timestamp::absolute(parse_nanos(text, 1_000_000_000)?)),
+            TimestampFormat::EpochMillis => {
+                Some(Timestamp::absolute(parse_nanos(text, 1_000_000)?))
+            }
+            TimestampFormat::Uptime => parse_uptime(text, 1e9),
+            TimestampFormat::UptimeMillis => parse_uptime(text, 1e6),
+        }
+    }
+}
+
+fn parse_rfc3339(text: &str) -> Option<Timestamp> {
+    let time = DateTime::parse_from_rfc3339(text).ok()?;
+    Some(Timestamp::absolute(time.timestamp_nanos_opt()?))
+}
+
+fn parse_strftime(text: &str, format: &str) -> Option<Timestamp> {
+    let mut parsed = Parsed::new();
+    parse(&mut parsed, text, StrftimeItems::new(format)).ok()?;
+
+    // Plenty of loggers leave out the year, or the date entirely
+    if parsed.year().is_none() {
+        parsed.set_year(1970).ok()?;
+    }
+    if parsed.month().is_none() && parsed.ordinal().is_none() {
+        parsed.set_month(1).ok()?;
+        parsed.set_day(1).ok()?;
+    }
+
+    let nanos = match parsed.offset() {
+        Some(_) => parsed.to_datetime().ok()?.timestamp_nanos_opt()?,
+        None => parsed
+            .to_naive_datetime_with_offset(0)
+            .ok()?
+            .and_utc()
+            .timestamp_nanos_opt()?,
+    };
+    Some(Timestamp::absolute(nanos))
+}
+
+/*
+The number of units in nanoseconds. Decimal numbers are scaled as they are
+written, as the nanoseconds since the epoch need more precision than an f64
+has. Others, like `1.7e9`, are parsed as a float.
+*/
+fn parse_nanos(text: &str, nanos_per_unit: i64) -> Option<i64> {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, text),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    let decimal = !whole.is_empty()
+        && whole.chars().all(|c| c.is_ascii_digit())
+        && fraction.chars().all(|c| c.is_ascii_digit());
+    if !decimal {
+        let units: f64 = text.parse().ok()?;
+        return Some((units * nanos_per_unit as f64) as i64);
+    }
+
+    let mut nanos = whole.parse::<i64>().ok()?.checked_mul(nanos_per_unit)?;
+    let mut scale = nanos_per_unit;
+    for digit in fraction.chars().filter_map(|c| c.to_digit(10)) {
+        scale /= 10;
+        nanos = nanos.checked_add(digit as i64 * scale)?;
+    }
+    Some(sign * nanos)
+}
+
+// Accepts `12.345678`, `[   12.345678]` and `00:00:12.345,678` style uptimes
+fn parse_uptime(text: &str, nanos_per_unit: f64) -> Option<Timestamp> {
+    let text = text.trim_matches(['[', ']', '(', ')', ' ']);
+    if !text.contains(':') {
+        let units: f64 = text.parse().ok()?;
+        return Some(Timestamp::uptime((units * nanos_per_unit) as i64));
+    }
+
+    let mut seconds = 0.0;
+    for part in text.split(':') {
+        seconds = seconds * 60.0 + part.replace(',', "").parse::<f64>().ok()?;
+    }
+    Some(Timestamp::uptime((seconds * 1e9) as i64))
+}
+
+/// A point in time, either wall clock time or time since boot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timestamp {
+    nanos: i64,
+    uptime: bool,
+}
+
+impl Timestamp {
+    fn absolute(nanos: i64) -> Self {
+        Timestamp {
+            nanos,
+            uptime: false,
+        }
+    }
+
+    fn uptime(nanos: i64) -> Self {
+        Timestamp {
+            nanos,
+            uptime: true,
+        }
+    }
+
+    /// The time from `earlier` to `self`.
+    pub fn since(&self, earlier: &Timestamp) -> TimeDelta {
+        TimeDelta(self.nanos.saturating_sub(earlier.nanos))
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.uptime {
+            true => write!(f, "{:.6}s", self.nanos as f64 / 1e9),
+            false => write!(
+                f,
+                "{}",
+                DateTime::from_timestamp_nanos(self.nanos).format("%Y-%m-%d %H:%M:%S%.6f")
+            ),
+        }
+    }
+}
+
+/// The time between two timestamps, in nanoseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeDelta(i64);
+
+impl fmt::Display for TimeDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "+" };
+        let seconds = self.0.unsigned_abs() as f64 / 1e9;
+        match seconds {
+            s if s < 1.0 => write!(f, "{}{:.3}ms", sign, s * 1e3),
+            s if s < 60.0 => write!(f, "{}{:.6}s", sign, s),
+            s => {
+                let total = s as u64;
+                write!(
+                    f,
+                    "{}{}h {:02}m {:09.6}s",
+                    sign,
+                    total / 3600,
+                    (total / 60) % 60,
+                    s % 60.0
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(format: &str, text: &str) -> Option<String> {
+        TimestampFormat::new(format)
+            .parse(text)
+            .map(|t| t.to_string())
+    }
+
+    #[test]
+    fn common_formats_are_recognized() {
+        let auto = |text| parse("auto", text);
+        assert_eq!(
+            auto("2024-03-01T10:00:00.5+01:00").as_deref(),
+            Some("2024-03-01 09:00:00.500000")
+        );
+        assert_eq!(
+            auto("2024-03-01 10:00:00,250").as_deref(),
+            Some("2024-03-01 10:00:00.250000")
+        );
+        assert_eq!(
+            auto("2024/03/01 10:00:00").as_deref(),
+            Some("2024-03-01 10:00:00.000000")
+        );
+        // Without a year or date, the time is in 1970
+        assert_eq!(
+            auto("Mar  1 10:00:00").as_deref(),
+            Some("1970-03-01 10:00:00.000000")
+        );
+        assert_eq!(
+            auto("10:00:00.125").as_deref(),
+            Some("1970-01-01 10:00:00.125000")
+        );
+        assert_eq!(auto("not a time"), None);
+    }
+
+    #[test]
+    fn numbers_are_epoch_or_uptime_by_size() {
+        let auto = |text| parse("auto", text);
+        let epoch = Some("2023-11-14 22:13:20.000000");
+        assert_eq!(auto("1700000000").as_deref(), epoch);
+        assert_eq!(auto("1700000000000").as_deref(), epoch);
+        assert_eq!(auto("1700000000000000").as_deref(), epoch);
+        assert_eq!(auto("1700000000000000000").as_deref(), epoch);
+        assert_eq!(auto("[   12.345678]").as_deref(), Some("12.345678s"));
+        // No precision is lost to floating point
+        assert_eq!(
+            auto("1717225200.123000000").as_deref(),
+            Some("2024-06-01 07:00:00.123000")
+        );
+        assert_eq!(
+            auto("1717225200123.456").as_deref(),
+            Some("2024-06-01 07:00:00.123456")
+        );
+        assert_eq!(auto("1.7e9").as_deref(), Some("2023-11-14 22:13:20.000000"));
+    }
+
+    #[test]
+    fn explicit_formats() {
+        assert_eq!(
+            parse("epoch", "1700000000.5").as_deref(),
+            Some("2023-11-14 22:13:20.500000")
+        );
+        assert_eq!(
+            parse("epoch", "1717225200.123").as_deref(),
+            Some("2024-06-01 07:00:00.123000")
+        );
+        assert_eq!(
+            parse("epoch_ms", "1700000000500").as_deref(),
+            Some("2023-11-14 22:13:20.500000")
+        );
+        assert_eq!(
+            parse("uptime", "(00:01:02.5)").as_deref(),
+            Some("62.500000s")
+        );
+        assert_eq!(
+            parse("uptime", "00:00:12.345,678").as_deref(),
+            Some("12.345678s")
+        );
+        assert_eq!(parse("uptime_ms", "1500").as_deref(), Some("1.500000s"));
+        assert_eq!(
+            parse("%d.%m.%Y %H:%M", "01.03.2024 10:00").as_deref(),
+            Some("2024-03-01 10:00:00.000000")
+        );
+        assert_eq!(parse("rfc3339", "2024-03-01 10:00:00"), None);
+    }
+
+    #[test]
+    fn deltas() {
+        let uptime = |text| TimestampFormat::Uptime.parse(text).unwrap();
+        let delta = |a, b| uptime(b).since(&uptime(a)).to_string();
+        assert_eq!(delta("1.0", "1.0125"), "+12.500ms");
+        assert_eq!(delta("2.5", "1.0"), "-1.500000s");
+        assert_eq!(delta("0", "3725.5"), "+1h 02m 05.500000s");
+
+        let extremes = (Timestamp::uptime(i64::MAX), Timestamp::uptime(i64::MIN));
+        assert_eq!(extremes.0.since(&extremes.1), TimeDelta(i64::MAX));
+    }
+}
@@ -0,0 +1,3 @@
+fn main() {
+    println!("cargo:rustc-link-arg=-T{}/defmt-host.x", std::env::var("CARGO_MANIFEST_DIR").unwrap());
+}
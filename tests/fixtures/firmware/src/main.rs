@@ -0,0 +1,95 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+
+static mut OUT: [u8; 4096] = [0; 4096];
+static mut LEN: usize = 0;
+static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+static mut TICKS: u64 = 0;
+
+fn emit(bytes: &[u8]) {
+    unsafe {
+        for b in bytes {
+            OUT[LEN] = *b;
+            LEN += 1;
+        }
+    }
+}
+
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        unsafe { (*core::ptr::addr_of_mut!(ENCODER)).start_frame(emit) }
+    }
+    unsafe fn flush() {}
+    unsafe fn release() {
+        unsafe { (*core::ptr::addr_of_mut!(ENCODER)).end_frame(emit) }
+    }
+    unsafe fn write(bytes: &[u8]) {
+        unsafe { (*core::ptr::addr_of_mut!(ENCODER)).write(bytes, emit) }
+    }
+}
+
+defmt::timestamp!("{=u64:us}", unsafe {
+    TICKS += 1500;
+    TICKS
+});
+
+#[derive(defmt::Format)]
+enum State {
+    Idle,
+    Busy { job: u8 },
+}
+
+#[derive(defmt::Format)]
+struct Reading {
+    channel: u8,
+    millivolts: i16,
+}
+
+#[derive(defmt::Format)]
+struct Empty;
+
+fn exit(code: usize) -> ! {
+    unsafe { asm!("syscall", in("rax") 60, in("rdi") code, options(noreturn)) }
+}
+
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    exit(1)
+}
+
+#[defmt::panic_handler]
+fn defmt_panic() -> ! {
+    exit(1)
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    defmt::info!("booting firmware v{=u8}.{=u8}", 1, 4);
+    defmt::debug!("sensor {=u8} read {=i16} mV", 3, -1250i16);
+    defmt::warn!("state is {}", State::Busy { job: 7 });
+    defmt::info!("state is {}", State::Idle);
+    defmt::error!("register {0=0..4:#x} flags {0=4..8:b}", 0xA5u8);
+    defmt::info!(
+        "readings {}",
+        [
+            Reading { channel: 1, millivolts: 3300 },
+            Reading { channel: 2, millivolts: -5 }
+        ]
+    );
+    defmt::trace!("name {=str} ok {=bool} ratio {=f32}", "pump", true, 0.5f32);
+    defmt::info!("bytes {=[u8]:x} and {:?}", [0xde, 0xad][..], Empty);
+    defmt::println!("done after {=u32} ticks", 42);
+    unsafe {
+        let len = LEN;
+        asm!("syscall", inlateout("rax") 1usize => _, in("rdi") 1, in("rsi") core::ptr::addr_of!(OUT) as *const u8, in("rdx") len, lateout("rcx") _, lateout("r11") _);
+    }
+    exit(0)
+}
+
+#[no_mangle]
+pub extern "C" fn rust_eh_personality() {}
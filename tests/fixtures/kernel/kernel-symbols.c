@@ -0,0 +1,32 @@
+/*
+The "kernel" behind kernel-symbols.elf, built with
+gcc -g -O2 -static -nostdlib -no-pie -fno-asynchronous-unwind-tables \
+    -fdebug-prefix-map=$PWD=kernel -Wl,-e,start_kernel -Wl,--build-id=none \
+    -o ../kernel-symbols.elf kernel-symbols.c
+*/
+volatile int counter;
+
+static inline __attribute__((always_inline)) void bump(int n)
+{
+	counter += n;
+	counter *= 3;
+}
+
+__attribute__((noinline)) void ext4_readdir(int n)
+{
+	bump(n);
+	counter ^= 5;
+}
+
+__attribute__((noinline)) void iterate_dir(int n)
+{
+	ext4_readdir(n);
+	counter -= 1;
+}
+
+void start_kernel(void)
+{
+	iterate_dir(2);
+	for (;;)
+		;
+}
@@ -0,0 +1,8 @@
+#!/bin/sh
+# Logs like src/app.c would, with the location of each printf
+echo "[src/app.c:13] starting up the engine"
+for n in 1 2 3; do
+    echo "[src/app.c:7] processing request number $n"
+    echo "[src/app.c:8] request $n took $((n * 10)) ms"
+done
+echo "[src/app.c:16] shutting down cleanly"
@@ -0,0 +1,98 @@
+#include <systemd/sd-journal.h>
+/* The calls are at the lines that tests/fixtures/journal.export logs them at */
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+/* Logs each connection, with the address of the peer */
+void accept_conn(const char *peer)
+{
+    sd_journal_print(LOG_INFO, "accepted connection from %s", peer);
+}
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+
+void handle_request(int ms, const char *query)
+{
+    if (ms > 1000)
+        sd_journal_print(LOG_WARNING, "slow request took %d ms\nquery: %s", ms, query);
+    if (ms > 5000)
+        return;
+
+    int err = run(query);
+    if (err)
+        return;
+    // A timeout is reported by the caller
+    if (timed_out(err))
+        sd_journal_print(LOG_ERR, "request failed: timeout");
+}
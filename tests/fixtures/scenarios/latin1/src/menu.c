@@ -0,0 +1,6 @@
+#include <stdio.h>
+
+void serve(int n)
+{
+    printf("caf ouvert %d\n", n);
+}
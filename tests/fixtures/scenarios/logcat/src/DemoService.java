@@ -0,0 +1,16 @@
+import android.util.Log;
+
+public class DemoService {
+    private static final String TAG = "DemoService";
+
+    void start(int port) {
+        Log.i(TAG, "service started on port " + port);
+    }
+
+    void handle(int id) {
+        Log.d(TAG, "handling request id=" + id);
+        if (!run(id)) {
+            Log.e(TAG, "request id=" + id + " failed: timeout");
+        }
+    }
+}
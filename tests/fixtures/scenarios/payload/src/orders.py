@@ -0,0 +1,12 @@
+import json
+import logging
+
+log = logging.getLogger(__name__)
+
+
+def place(user, items):
+    log.info("placing order %s", json.dumps({"user": user, "items": items}))
+
+
+def done(status, ms):
+    log.info("order done status=%d ms=%d", status, ms)
@@ -0,0 +1,7 @@
+#include "pw_log/log.h"
+
+void check_motor(const char *name, unsigned rpm, int stalled)
+{
+    if (stalled)
+        PW_LOG_WARN("Motor %s stalled at %u rpm", name, rpm);
+}
@@ -0,0 +1,6 @@
+#include "pw_log/log.h"
+
+void report_battery(int percent)
+{
+    PW_LOG_INFO("Battery at %d%%", percent);
+}
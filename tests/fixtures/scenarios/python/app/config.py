@@ -0,0 +1,16 @@
+import logging
+
+log = logging.getLogger(__name__)
+
+
+def load(path):
+    raise KeyError(path)
+
+
+def main():
+    log.info("loading the config")
+    try:
+        load("cfg")
+    except KeyError:
+        log.exception("bad config")
+    log.info("shutting down")
@@ -0,0 +1,9 @@
+#!/bin/sh
+# Like app.sh, taking its time to handle each request
+echo "[src/app.c:13] starting up the engine"
+for n in 1 2 3; do
+    echo "[src/app.c:7] processing request number $n"
+    sleep 0.5
+    echo "[src/app.c:8] request $n took $((n * 500)) ms"
+done
+echo "[src/app.c:16] shutting down cleanly"
@@ -0,0 +1,20 @@
+use tracing::{info, info_span, instrument};
+
+#[instrument]
+fn handle_request(id: u32) {
+    let _s = info_span!("db", table = "users").entered();
+    info!("querying database");
+}
+
+fn parse_header(buf: &[u8]) {
+    log::debug!("enter parse_header");
+    log::debug!("header is {} bytes", buf.len());
+    decode(buf);
+    log::debug!("exit parse_header");
+}
+
+fn decode(buf: &[u8]) {
+    log::debug!("-> decode(len={})", buf.len());
+    log::debug!("decoding now");
+    log::debug!("exit decode");
+}
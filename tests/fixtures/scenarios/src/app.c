@@ -0,0 +1,18 @@
+#include <stdio.h>
+
+static void handle(int n)
+{
+    int ms = n * 10;
+
+    printf("processing request number %d\n", n);
+    printf("request %d took %d ms\n", n, ms);
+}
+
+int main(void)
+{
+    printf("starting up the engine\n");
+    for (int n = 1; n <= 3; n++)
+        handle(n);
+    printf("shutting down cleanly\n");
+    return 0;
+}
@@ -0,0 +1,8 @@
+#include <glog/logging.h>
+
+void connect(const std::string& host, int n)
+{
+    if (n == 0)
+        LOG(INFO) << "connection opened to " << host;
+    LOG(WARNING) << "retrying request " << n;
+}